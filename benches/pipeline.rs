@@ -0,0 +1,121 @@
+//! Throughput of point extraction with every specification parsed per
+//! message, versus a [`PointPlan`] compiled once at startup.
+//!
+//! Run with `cargo bench --bench pipeline`.
+
+use criterion::{Criterion, black_box, criterion_group, criterion_main};
+use sinqtt::bridge::{
+    CompiledExpression, MessageProcessor, MessageScope, ParsedMessage, PointPlan, TimestampFormat,
+    TimestampZone, parse_timestamp,
+};
+use sinqtt::config::PointConfig;
+
+const POINT: &str = r#"
+measurement: $.topic[1]
+topic: node/+/thermometer/+/temperature
+schedule: "* * * * *"
+when: $.payload.temperature > -40 && exists($.payload.humidity)
+timestamp: $.payload.time
+fields:
+  temperature: $.payload.temperature
+  humidity:
+    value: $.payload.humidity
+    type: float
+  dew_point: = dew_point($.payload.temperature, $.payload.humidity)
+  fahrenheit: = round($.payload.temperature * 1.8 + 32, 1)
+tags:
+  id: $.topic[1]
+  channel: $.topic[3]
+  room: $.payload.location.room
+"#;
+
+const PAYLOAD: &[u8] = br#"{
+    "temperature": 23.4,
+    "humidity": 48.2,
+    "time": "2024-05-01T12:00:00Z",
+    "location": {"room": "kitchen", "floor": 1}
+}"#;
+
+fn message(processor: &MessageProcessor) -> ParsedMessage {
+    processor
+        .parse_message("node/kitchen/thermometer/0:1/temperature", PAYLOAD, 1)
+        .unwrap()
+}
+
+/// Extract the point through the `MessageProcessor` methods, which parse
+/// their specification on every call.
+fn reparse(point: &PointConfig, processor: &MessageProcessor, msg: &ParsedMessage) -> usize {
+    if let Some(schedule) = &point.schedule
+        && !processor.schedule_matches(schedule)
+    {
+        return 0;
+    }
+    if let Some(when) = &point.when
+        && !CompiledExpression::compile(when)
+            .is_ok_and(|when| when.matches(&MessageScope::new(msg)))
+    {
+        return 0;
+    }
+    let mut values = 0;
+    values += processor.get_value(&point.measurement, msg).is_some() as usize;
+    for spec in point.tags.values() {
+        values += processor.get_value(spec, msg).is_some() as usize;
+    }
+    for spec in point.fields.values() {
+        values += processor.extract_field(spec, msg).is_some() as usize;
+    }
+    if let Some(timestamp) = &point.timestamp {
+        values += processor
+            .get_value(timestamp, msg)
+            .and_then(|value| {
+                parse_timestamp(
+                    &value,
+                    &TimestampFormat::default(),
+                    TimestampZone::default(),
+                )
+            })
+            .is_some() as usize;
+    }
+    values
+}
+
+/// Extract the point with a plan compiled once at startup.
+fn precompiled(plan: &PointPlan, msg: &ParsedMessage) -> usize {
+    let scope = MessageScope::new(msg);
+    if !plan.schedule_matches() || !plan.condition_matches(&scope) {
+        return 0;
+    }
+    let mut values = 0;
+    values += plan.measurement().evaluate(&scope).is_some() as usize;
+    for (_, spec) in plan.tags() {
+        values += spec.evaluate(&scope).is_some() as usize;
+    }
+    for field in plan.fields() {
+        values += field.extract(&scope).len();
+    }
+    if let Some(timestamp) = plan.timestamp() {
+        values += timestamp.extract(&scope).is_some() as usize;
+    }
+    values
+}
+
+fn pipeline(c: &mut Criterion) {
+    let point: PointConfig = serde_yaml::from_str(POINT).unwrap();
+    let plan = PointPlan::compile(&point).unwrap();
+    let processor = MessageProcessor::new(None);
+    let msg = message(&processor);
+    assert_eq!(reparse(&point, &processor, &msg), 9);
+    assert_eq!(precompiled(&plan, &msg), 9);
+
+    let mut group = c.benchmark_group("point");
+    group.bench_function("reparse", |b| {
+        b.iter(|| reparse(black_box(&point), &processor, black_box(&msg)))
+    });
+    group.bench_function("precompiled", |b| {
+        b.iter(|| precompiled(black_box(&plan), black_box(&msg)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, pipeline);
+criterion_main!(benches);
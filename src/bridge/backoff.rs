@@ -0,0 +1,48 @@
+//! Exponential backoff shared by `InfluxDB` write retries and MQTT reconnects.
+
+use std::time::Duration;
+
+/// Compute the backoff delay before retry number `attempt` (0-based).
+///
+/// The delay doubles with each attempt from `initial_delay_ms` up to
+/// `max_delay_ms`, and `jitter` (in `0.0..=1.0`) scales it into the upper
+/// half of that range.
+#[must_use]
+pub fn backoff_delay(
+    initial_delay_ms: u64,
+    max_delay_ms: u64,
+    attempt: u32,
+    jitter: f64,
+) -> Duration {
+    let base = initial_delay_ms
+        .saturating_mul(1u64 << attempt.min(32))
+        .min(max_delay_ms);
+    let factor = 0.5 + 0.5 * jitter.clamp(0.0, 1.0);
+    Duration::from_millis((base as f64 * factor) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_delay_doubles_and_caps() {
+        assert_eq!(backoff_delay(100, 1000, 0, 1.0), Duration::from_millis(100));
+        assert_eq!(backoff_delay(100, 1000, 1, 1.0), Duration::from_millis(200));
+        assert_eq!(backoff_delay(100, 1000, 2, 1.0), Duration::from_millis(400));
+        assert_eq!(
+            backoff_delay(100, 1000, 10, 1.0),
+            Duration::from_millis(1000)
+        );
+        assert_eq!(
+            backoff_delay(100, 1000, 63, 1.0),
+            Duration::from_millis(1000)
+        );
+    }
+
+    #[test]
+    fn test_backoff_delay_jitter() {
+        assert_eq!(backoff_delay(100, 1000, 1, 0.0), Duration::from_millis(100));
+        assert_eq!(backoff_delay(100, 1000, 1, 0.5), Duration::from_millis(150));
+    }
+}
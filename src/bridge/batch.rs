@@ -0,0 +1,619 @@
+//! Batched `InfluxDB` writes with size and time flush thresholds.
+
+use super::influxdb::{InfluxDBWriter, Point, line_measurement};
+use super::mqtt::MessageAck;
+use super::spool::Spool;
+use crate::config::BatchConfig;
+use crate::error::{InfluxDbError, SinqttError};
+use crate::metrics::metrics;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+/// Capacity of the channel between message processing and the batch task.
+const CHANNEL_CAPACITY: usize = 1000;
+
+/// Smallest interval at which pending batches are checked for expiry.
+const MIN_TICK: Duration = Duration::from_millis(10);
+
+/// A line protocol entry waiting to be batched.
+#[derive(Debug)]
+struct BatchEntry {
+    bucket: Option<String>,
+    line: String,
+    ack: Option<MessageAck>,
+}
+
+/// Handle for queueing points into the background batch task.
+#[derive(Clone)]
+pub struct BatchWriter {
+    tx: mpsc::Sender<BatchEntry>,
+}
+
+impl BatchWriter {
+    /// Spawn the background batch task and return a handle to it.
+    ///
+    /// The task flushes pending batches when it is cancelled via `cancel_token`
+    /// or when every `BatchWriter` handle has been dropped. Batches that fail
+    /// to write are stored in `spool` (if given) and replayed later; until the
+    /// spool is empty again, new batches are appended to it.
+    #[must_use]
+    pub fn spawn(
+        writer: Arc<InfluxDBWriter>,
+        config: &BatchConfig,
+        spool: Option<(Spool, Duration)>,
+        cancel_token: CancellationToken,
+    ) -> (Self, JoinHandle<()>) {
+        let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+        let buffer = BatchBuffer::new(config);
+        let sink = BatchSink::new(writer, spool);
+        let task = tokio::spawn(run_batch_task(sink, buffer, rx, cancel_token));
+        (Self { tx }, task)
+    }
+
+    /// Queue a point for writing to the given bucket.
+    ///
+    /// The `ack`, if given, is held until the point has been written or spooled.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the background batch task has stopped.
+    pub async fn write_point(
+        &self,
+        point: &Point,
+        bucket: Option<&str>,
+        ack: Option<MessageAck>,
+    ) -> Result<(), SinqttError> {
+        let entry = BatchEntry {
+            bucket: bucket.map(String::from),
+            line: point.to_line_protocol(),
+            ack,
+        };
+        self.tx
+            .send(entry)
+            .await
+            .map_err(|_| SinqttError::InfluxDb(InfluxDbError::WriterStopped))
+    }
+}
+
+/// Lines collected for a single bucket.
+#[derive(Debug)]
+struct Batch {
+    pending: PendingBatch,
+    bytes: usize,
+    started: Instant,
+}
+
+/// Lines taken from the buffer, with the message acknowledgements they complete.
+#[derive(Debug, Default)]
+pub struct PendingBatch {
+    pub lines: Vec<String>,
+    pub acks: Vec<MessageAck>,
+}
+
+impl PendingBatch {
+    /// Release the acknowledgements, withholding them if the lines were not delivered.
+    pub fn settle(self, delivered: bool) {
+        if !delivered {
+            for ack in &self.acks {
+                ack.fail();
+            }
+        }
+    }
+}
+
+/// Per-bucket batch accumulator with flush thresholds.
+#[derive(Debug)]
+pub struct BatchBuffer {
+    max_lines: usize,
+    max_bytes: usize,
+    max_latency: Duration,
+    batches: HashMap<Option<String>, Batch>,
+}
+
+impl BatchBuffer {
+    /// Create an empty buffer from configuration.
+    #[must_use]
+    pub fn new(config: &BatchConfig) -> Self {
+        Self {
+            max_lines: config.max_lines,
+            max_bytes: config.max_bytes,
+            max_latency: Duration::from_millis(config.max_latency_ms),
+            batches: HashMap::new(),
+        }
+    }
+
+    /// Add a line to the bucket's batch.
+    ///
+    /// Returns `true` if the batch reached the line or byte threshold and
+    /// should be flushed.
+    pub fn push(
+        &mut self,
+        bucket: Option<String>,
+        line: String,
+        ack: Option<MessageAck>,
+        now: Instant,
+    ) -> bool {
+        let batch = self.batches.entry(bucket).or_insert_with(|| Batch {
+            pending: PendingBatch::default(),
+            bytes: 0,
+            started: now,
+        });
+        // Account for the newline separator between lines
+        batch.bytes += line.len() + 1;
+        batch.pending.lines.push(line);
+        batch.pending.acks.extend(ack);
+        batch.pending.lines.len() >= self.max_lines || batch.bytes >= self.max_bytes
+    }
+
+    /// Remove and return the pending lines of a bucket.
+    pub fn take(&mut self, bucket: &Option<String>) -> Option<PendingBatch> {
+        self.batches.remove(bucket).map(|batch| batch.pending)
+    }
+
+    /// Buckets whose oldest line has waited at least the maximum latency.
+    #[must_use]
+    pub fn expired(&self, now: Instant) -> Vec<Option<String>> {
+        self.batches
+            .iter()
+            .filter(|(_, batch)| now.duration_since(batch.started) >= self.max_latency)
+            .map(|(bucket, _)| bucket.clone())
+            .collect()
+    }
+
+    /// Remove and return all pending batches.
+    pub fn drain(&mut self) -> Vec<(Option<String>, PendingBatch)> {
+        self.batches
+            .drain()
+            .map(|(bucket, batch)| (bucket, batch.pending))
+            .collect()
+    }
+
+    /// Number of lines waiting across all buckets.
+    #[must_use]
+    pub fn pending(&self) -> usize {
+        self.batches
+            .values()
+            .map(|batch| batch.pending.lines.len())
+            .sum()
+    }
+
+    /// Interval at which pending batches should be checked for expiry.
+    #[must_use]
+    pub fn tick_interval(&self) -> Duration {
+        (self.max_latency / 4).max(MIN_TICK)
+    }
+}
+
+/// Destination of flushed batches, falling back to the spool on failure.
+struct BatchSink {
+    writer: Arc<InfluxDBWriter>,
+    spool: Option<Spool>,
+    replay_interval: Duration,
+    next_replay: Instant,
+}
+
+impl BatchSink {
+    fn new(writer: Arc<InfluxDBWriter>, spool: Option<(Spool, Duration)>) -> Self {
+        let (spool, replay_interval) = match spool {
+            Some((spool, interval)) => (Some(spool), interval),
+            None => (None, Duration::ZERO),
+        };
+        Self {
+            writer,
+            spool,
+            replay_interval,
+            next_replay: Instant::now(),
+        }
+    }
+
+    /// Write a batch of lines, spooling it if the write fails.
+    ///
+    /// While the spool holds batches, new batches are appended to it without
+    /// a write attempt, so they are written in order once `replay` has
+    /// emptied it.
+    ///
+    /// Returns `true` if the lines were written or spooled, or were rejected
+    /// by `InfluxDB` and can never be written.
+    async fn flush(&mut self, bucket: Option<&str>, lines: &[String]) -> bool {
+        if self.spool.as_ref().is_some_and(|spool| !spool.is_empty()) {
+            debug!(
+                "Spooling {} lines behind earlier spooled batches",
+                lines.len()
+            );
+            return self.spool(bucket, lines);
+        }
+
+        let bucket_name = bucket.unwrap_or(self.writer.default_bucket());
+        let Err(e) = self.writer.write_lines(lines, bucket).await else {
+            debug!("Flushed {} lines to bucket {}", lines.len(), bucket_name);
+            record_points(bucket_name, lines, true);
+            return true;
+        };
+
+        if self.spool.is_some() && is_retryable(&e) {
+            warn!(
+                "Failed to flush {} lines to bucket {}, spooling: {}",
+                lines.len(),
+                bucket_name,
+                e
+            );
+            self.next_replay = Instant::now() + self.replay_interval;
+            return self.spool(bucket, lines);
+        }
+
+        error!(
+            "Failed to flush {} lines to bucket {}: {}",
+            lines.len(),
+            bucket_name,
+            e
+        );
+        record_points(bucket_name, lines, false);
+        !is_retryable(&e)
+    }
+
+    /// Append a batch of lines to the spool; `false` if it cannot be stored.
+    fn spool(&mut self, bucket: Option<&str>, lines: &[String]) -> bool {
+        let Some(spool) = &mut self.spool else {
+            return false;
+        };
+        if let Err(e) = spool.push(bucket, lines) {
+            error!("Failed to spool {} lines: {}", lines.len(), e);
+            record_points(bucket.unwrap_or(self.writer.default_bucket()), lines, false);
+            return false;
+        }
+        true
+    }
+
+    /// Flush a pending batch and settle its acknowledgements.
+    async fn flush_batch(&mut self, bucket: Option<&str>, batch: PendingBatch) {
+        let delivered = self.flush(bucket, &batch.lines).await;
+        batch.settle(delivered);
+    }
+
+    /// Replay spooled batches oldest-first until the spool is empty or a write fails.
+    async fn replay(&mut self, now: Instant) {
+        let Some(spool) = &mut self.spool else {
+            return;
+        };
+        if spool.is_empty() || now < self.next_replay {
+            return;
+        }
+
+        while !spool.is_empty() {
+            let batch = match spool.peek() {
+                Ok(Some(batch)) => batch,
+                Ok(None) => break,
+                Err(e) => {
+                    error!("Dropping unreadable spool segment: {}", e);
+                    if let Err(e) = spool.pop() {
+                        error!("Failed to remove spool segment: {}", e);
+                        break;
+                    }
+                    continue;
+                }
+            };
+
+            let result = self
+                .writer
+                .write_lines(&batch.lines, batch.bucket.as_deref())
+                .await;
+            let bucket_name = batch
+                .bucket
+                .as_deref()
+                .unwrap_or(self.writer.default_bucket());
+            match &result {
+                Err(e) if is_retryable(e) => {
+                    debug!("Spool replay deferred: {}", e);
+                    self.next_replay = now + self.replay_interval;
+                    return;
+                }
+                Err(e) => {
+                    error!(
+                        "Dropping {} spooled lines rejected by InfluxDB: {}",
+                        batch.lines.len(),
+                        e
+                    );
+                    record_points(bucket_name, &batch.lines, false);
+                }
+                Ok(()) => record_points(bucket_name, &batch.lines, true),
+            }
+
+            if let Err(e) = spool.pop() {
+                error!("Failed to remove spool segment: {}", e);
+                break;
+            }
+            if result.is_ok() {
+                info!(
+                    "Replayed {} spooled lines, {} batches remaining",
+                    batch.lines.len(),
+                    spool.len()
+                );
+            }
+        }
+    }
+}
+
+/// Count the outcome of writing lines, grouped by measurement.
+///
+/// Spooled lines are not counted until they are replayed or dropped.
+fn record_points(bucket: &str, lines: &[String], written: bool) {
+    let mut counts: HashMap<String, u64> = HashMap::new();
+    for line in lines {
+        *counts.entry(line_measurement(line)).or_default() += 1;
+    }
+    for (measurement, count) in counts {
+        if written {
+            metrics().points_written(bucket, &measurement, count);
+        } else {
+            metrics().points_failed(bucket, &measurement, count);
+        }
+    }
+}
+
+/// Check if a failed write may succeed later and is worth spooling.
+fn is_retryable(err: &SinqttError) -> bool {
+    matches!(err, SinqttError::InfluxDb(e) if e.is_retryable())
+}
+
+/// Receive entries, flush on thresholds and drain on shutdown.
+async fn run_batch_task(
+    mut sink: BatchSink,
+    mut buffer: BatchBuffer,
+    mut rx: mpsc::Receiver<BatchEntry>,
+    cancel_token: CancellationToken,
+) {
+    let mut ticker = tokio::time::interval(buffer.tick_interval());
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            entry = rx.recv() => {
+                let Some(entry) = entry else { break };
+                metrics().set_channel_backlog("batch", rx.len());
+                let bucket = entry.bucket.clone();
+                if buffer.push(entry.bucket, entry.line, entry.ack, Instant::now())
+                    && let Some(batch) = buffer.take(&bucket)
+                {
+                    sink.flush_batch(bucket.as_deref(), batch).await;
+                }
+            }
+            _ = ticker.tick() => {
+                let now = Instant::now();
+                for bucket in buffer.expired(now) {
+                    if let Some(batch) = buffer.take(&bucket) {
+                        sink.flush_batch(bucket.as_deref(), batch).await;
+                    }
+                }
+                sink.replay(now).await;
+            }
+            () = cancel_token.cancelled() => break,
+        }
+    }
+
+    // Drain anything still queued, then flush all pending batches
+    rx.close();
+    while let Some(entry) = rx.recv().await {
+        buffer.push(entry.bucket, entry.line, entry.ack, Instant::now());
+    }
+
+    let pending = buffer.pending();
+    if pending > 0 {
+        info!("Flushing {} pending lines before shutdown", pending);
+    }
+    for (bucket, batch) in buffer.drain() {
+        sink.flush_batch(bucket.as_deref(), batch).await;
+    }
+    debug!("Batch writer stopped");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_config(max_lines: usize, max_bytes: usize, max_latency_ms: u64) -> BatchConfig {
+        BatchConfig {
+            max_lines,
+            max_bytes,
+            max_latency_ms,
+        }
+    }
+
+    #[test]
+    fn test_push_flushes_on_max_lines() {
+        let mut buffer = BatchBuffer::new(&make_config(2, 1024, 1000));
+        let now = Instant::now();
+
+        assert!(!buffer.push(None, "m value=1i".to_string(), None, now));
+        assert!(buffer.push(None, "m value=2i".to_string(), None, now));
+        assert_eq!(buffer.take(&None).unwrap().lines.len(), 2);
+        assert_eq!(buffer.pending(), 0);
+    }
+
+    #[test]
+    fn test_push_flushes_on_max_bytes() {
+        let mut buffer = BatchBuffer::new(&make_config(100, 20, 1000));
+        let now = Instant::now();
+
+        assert!(!buffer.push(None, "m value=1i".to_string(), None, now));
+        assert!(buffer.push(None, "m value=2i".to_string(), None, now));
+    }
+
+    #[test]
+    fn test_batches_are_grouped_per_bucket() {
+        let mut buffer = BatchBuffer::new(&make_config(2, 1024, 1000));
+        let now = Instant::now();
+        let other = Some("other".to_string());
+
+        assert!(!buffer.push(None, "m value=1i".to_string(), None, now));
+        assert!(!buffer.push(other.clone(), "m value=2i".to_string(), None, now));
+        assert_eq!(buffer.pending(), 2);
+
+        assert_eq!(
+            buffer.take(&other).unwrap().lines,
+            vec!["m value=2i".to_string()]
+        );
+        assert_eq!(
+            buffer.take(&None).unwrap().lines,
+            vec!["m value=1i".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_expired_after_max_latency() {
+        let mut buffer = BatchBuffer::new(&make_config(100, 1024, 50));
+        let start = Instant::now();
+
+        buffer.push(None, "m value=1i".to_string(), None, start);
+        assert!(buffer.expired(start).is_empty());
+        assert_eq!(
+            buffer.expired(start + Duration::from_millis(50)),
+            vec![None::<String>]
+        );
+    }
+
+    #[test]
+    fn test_drain_returns_all_batches() {
+        let mut buffer = BatchBuffer::new(&make_config(100, 1024, 1000));
+        let now = Instant::now();
+
+        buffer.push(None, "a value=1i".to_string(), None, now);
+        buffer.push(Some("b".to_string()), "b value=1i".to_string(), None, now);
+
+        let drained = buffer.drain();
+        assert_eq!(drained.len(), 2);
+        assert_eq!(buffer.pending(), 0);
+    }
+
+    #[test]
+    fn test_tick_interval_has_lower_bound() {
+        let buffer = BatchBuffer::new(&make_config(100, 1024, 1));
+        assert_eq!(buffer.tick_interval(), MIN_TICK);
+
+        let buffer = BatchBuffer::new(&make_config(100, 1024, 1000));
+        assert_eq!(buffer.tick_interval(), Duration::from_millis(250));
+    }
+
+    /// `InfluxDB` write endpoint that fails with 503 until it is brought up.
+    mod influxdb {
+        use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+        use std::sync::{Arc, Mutex};
+        use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+        use tokio::net::TcpListener;
+
+        #[derive(Default)]
+        pub struct FakeInfluxDb {
+            pub port: u16,
+            up: AtomicBool,
+            requests: AtomicUsize,
+            bodies: Mutex<Vec<String>>,
+        }
+
+        impl FakeInfluxDb {
+            pub async fn start() -> Arc<Self> {
+                let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                let server = Arc::new(Self {
+                    port: listener.local_addr().unwrap().port(),
+                    ..Self::default()
+                });
+                let handle = Arc::clone(&server);
+                tokio::spawn(async move {
+                    while let Ok((stream, _)) = listener.accept().await {
+                        handle.respond(stream).await;
+                    }
+                });
+                server
+            }
+
+            async fn respond(&self, mut stream: tokio::net::TcpStream) {
+                let mut reader = BufReader::new(&mut stream);
+                let mut length = 0;
+                let mut line = String::new();
+                while reader.read_line(&mut line).await.unwrap() > 2 {
+                    if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                    line.clear();
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).await.unwrap();
+
+                self.requests.fetch_add(1, Ordering::SeqCst);
+                let status = if self.up.load(Ordering::SeqCst) {
+                    self.bodies
+                        .lock()
+                        .unwrap()
+                        .push(String::from_utf8(body).unwrap());
+                    "204 No Content"
+                } else {
+                    "503 Service Unavailable"
+                };
+                let response =
+                    format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+
+            pub fn up(&self) {
+                self.up.store(true, Ordering::SeqCst);
+            }
+
+            /// Number of write requests received, failed or not.
+            pub fn requests(&self) -> usize {
+                self.requests.load(Ordering::SeqCst)
+            }
+
+            /// Bodies of the successful write requests, in order.
+            pub fn bodies(&self) -> Vec<String> {
+                self.bodies.lock().unwrap().clone()
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_batches_are_spooled_in_order_while_influxdb_is_down() {
+        let influxdb = influxdb::FakeInfluxDb::start().await;
+        let config: crate::config::InfluxDBConfig = serde_yaml::from_str(&format!(
+            "host: 127.0.0.1\nport: {}\ntoken: t\nbucket: b\n\
+             retry: {{max_retries: 2, initial_delay_ms: 1, max_delay_ms: 1}}",
+            influxdb.port
+        ))
+        .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let spool = Spool::open(&crate::config::SpoolConfig {
+            directory: dir.path().to_path_buf(),
+            max_bytes: 1024 * 1024,
+            replay_interval_ms: 0,
+        })
+        .unwrap();
+        let writer = Arc::new(InfluxDBWriter::new(&config).unwrap());
+        let mut sink = BatchSink::new(writer, Some((spool, Duration::ZERO)));
+        let batch = |i: usize| vec![format!("m value={i}i")];
+
+        // Only the first batch is tried, with its retries; the rest are
+        // spooled behind it without waiting on InfluxDB
+        for i in 0..3 {
+            assert!(sink.flush(None, &batch(i)).await);
+        }
+        assert_eq!(influxdb.requests(), 3);
+        assert_eq!(sink.spool.as_ref().unwrap().len(), 3);
+
+        // Once InfluxDB is back, new batches still queue behind the spool
+        influxdb.up();
+        assert!(sink.flush(None, &batch(3)).await);
+        assert_eq!(influxdb.requests(), 3);
+
+        sink.replay(Instant::now()).await;
+        assert!(sink.spool.as_ref().unwrap().is_empty());
+
+        // With the spool empty, batches are written directly again
+        assert!(sink.flush(None, &batch(4)).await);
+        assert_eq!(
+            influxdb.bodies(),
+            (0..5).map(|i| format!("m value={i}i")).collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,204 @@
+//! Flattening of nested JSON values into named fields.
+
+use crate::config::{FlattenArrays, FlattenConfig};
+use serde_json::Value;
+
+/// Flatten a JSON value into `(name, value)` pairs of scalar leaves.
+///
+/// Nested keys are joined with the configured separator, e.g. `env.temp`.
+/// Null leaves are skipped. Once `max_depth` levels have been flattened,
+/// remaining objects and arrays are stored as JSON strings.
+#[must_use]
+pub fn flatten_value(value: &Value, config: &FlattenConfig) -> Vec<(String, Value)> {
+    let mut out = Vec::new();
+    walk(value, String::new(), 0, config, &mut out);
+    out.retain(|(name, _)| is_selected(name, config));
+    out
+}
+
+fn walk(
+    value: &Value,
+    path: String,
+    depth: usize,
+    config: &FlattenConfig,
+    out: &mut Vec<(String, Value)>,
+) {
+    let depth_reached = config.max_depth.is_some_and(|max| depth >= max);
+
+    match value {
+        Value::Null => {}
+        Value::Object(_) if depth_reached => emit_json(value, path, out),
+        Value::Object(map) => {
+            for (key, child) in map {
+                walk(child, join(&path, key, config), depth + 1, config, out);
+            }
+        }
+        Value::Array(elements) => match config.arrays {
+            FlattenArrays::Skip => {}
+            FlattenArrays::Json => emit_json(value, path, out),
+            FlattenArrays::Index if depth_reached => emit_json(value, path, out),
+            FlattenArrays::Index => {
+                for (index, child) in elements.iter().enumerate() {
+                    walk(
+                        child,
+                        join(&path, &index.to_string(), config),
+                        depth + 1,
+                        config,
+                        out,
+                    );
+                }
+            }
+        },
+        scalar => {
+            // A scalar at the root has no name to be stored under
+            if !path.is_empty() {
+                out.push((path, scalar.clone()));
+            }
+        }
+    }
+}
+
+fn emit_json(value: &Value, path: String, out: &mut Vec<(String, Value)>) {
+    if !path.is_empty() {
+        out.push((path, Value::String(value.to_string())));
+    }
+}
+
+fn join(path: &str, key: &str, config: &FlattenConfig) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{path}{}{key}", config.separator)
+    }
+}
+
+/// Apply include and exclude glob lists to a flattened name.
+fn is_selected(name: &str, config: &FlattenConfig) -> bool {
+    let included =
+        config.include.is_empty() || config.include.iter().any(|p| glob_matches(p, name));
+    included && !config.exclude.iter().any(|p| glob_matches(p, name))
+}
+
+/// Match a name against a glob pattern supporting `*` and `?`.
+#[must_use]
+pub fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                // Backtrack: let the last `*` consume one more character
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn names(fields: &[(String, Value)]) -> Vec<&str> {
+        let mut names: Vec<&str> = fields.iter().map(|(n, _)| n.as_str()).collect();
+        names.sort_unstable();
+        names
+    }
+
+    #[test]
+    fn test_flatten_nested_object() {
+        let value = json!({"env": {"temp": 21.5, "hum": 40}, "battery": 87});
+        let fields = flatten_value(&value, &FlattenConfig::new("$.payload"));
+
+        assert_eq!(names(&fields), vec!["battery", "env.hum", "env.temp"]);
+        assert!(fields.contains(&("env.temp".to_string(), json!(21.5))));
+    }
+
+    #[test]
+    fn test_flatten_custom_separator() {
+        let mut config = FlattenConfig::new("$.payload");
+        config.separator = "_".to_string();
+
+        let fields = flatten_value(&json!({"env": {"temp": 1}}), &config);
+        assert_eq!(names(&fields), vec!["env_temp"]);
+    }
+
+    #[test]
+    fn test_flatten_arrays_index() {
+        let fields = flatten_value(
+            &json!({"values": [1, 2], "list": [{"a": 1}]}),
+            &FlattenConfig::new("$.payload"),
+        );
+        assert_eq!(names(&fields), vec!["list.0.a", "values.0", "values.1"]);
+    }
+
+    #[test]
+    fn test_flatten_arrays_skip_and_json() {
+        let value = json!({"values": [1, 2], "x": 1});
+
+        let mut config = FlattenConfig::new("$.payload");
+        config.arrays = FlattenArrays::Skip;
+        assert_eq!(names(&flatten_value(&value, &config)), vec!["x"]);
+
+        config.arrays = FlattenArrays::Json;
+        let fields = flatten_value(&value, &config);
+        assert!(fields.contains(&("values".to_string(), json!("[1,2]"))));
+    }
+
+    #[test]
+    fn test_flatten_max_depth() {
+        let mut config = FlattenConfig::new("$.payload");
+        config.max_depth = Some(1);
+
+        let fields = flatten_value(&json!({"a": {"b": {"c": 1}}, "d": 2}), &config);
+        assert_eq!(names(&fields), vec!["a", "d"]);
+        assert!(fields.contains(&("a".to_string(), json!(r#"{"b":{"c":1}}"#))));
+    }
+
+    #[test]
+    fn test_flatten_include_exclude() {
+        let value = json!({"env": {"temp": 1, "hum": 2}, "debug": {"uptime": 3}});
+
+        let mut config = FlattenConfig::new("$.payload");
+        config.include = vec!["env.*".to_string()];
+        config.exclude = vec!["*.hum".to_string()];
+
+        assert_eq!(names(&flatten_value(&value, &config)), vec!["env.temp"]);
+    }
+
+    #[test]
+    fn test_flatten_skips_null_and_root_scalar() {
+        assert!(flatten_value(&json!(42), &FlattenConfig::new("$.payload")).is_empty());
+        assert!(flatten_value(&json!({"a": null}), &FlattenConfig::new("$.payload")).is_empty());
+    }
+
+    #[test]
+    fn test_glob_matches() {
+        assert!(glob_matches("*", "anything"));
+        assert!(glob_matches("env.*", "env.temp"));
+        assert!(glob_matches("*.temp", "env.temp"));
+        assert!(glob_matches("env.?um", "env.hum"));
+        assert!(glob_matches("a*b*c", "axxbyyc"));
+        assert!(!glob_matches("env.*", "environment"));
+        assert!(!glob_matches("temp", "temp2"));
+    }
+}
@@ -1,5 +1,6 @@
 //! HTTP forwarding for webhook support.
 
+use super::tls::{TlsOptions, http_client};
 use crate::config::HttpConfig;
 use crate::error::SinqttError;
 use reqwest::Client;
@@ -52,8 +53,11 @@ impl HttpAction {
 
 impl HttpForwarder {
     /// Create a new HTTP forwarder from configuration.
-    #[must_use]
-    pub fn new(config: &HttpConfig) -> Self {
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the TLS certificate files cannot be loaded.
+    pub fn new(config: &HttpConfig) -> Result<Self, SinqttError> {
         let action = config.action.parse().unwrap_or_else(|()| {
             warn!(
                 "Unknown HTTP action '{}', defaulting to POST",
@@ -62,13 +66,21 @@ impl HttpForwarder {
             HttpAction::Post
         });
 
-        Self {
-            client: Client::new(),
+        let client = http_client(TlsOptions {
+            cafile: config.cafile.as_deref(),
+            certfile: config.certfile.as_deref(),
+            keyfile: config.keyfile.as_deref(),
+            insecure_skip_verify: config.insecure_skip_verify,
+            ..TlsOptions::default()
+        })?;
+
+        Ok(Self {
+            client,
             destination: config.destination.clone(),
             action,
             username: config.username.clone(),
             password: config.password.clone(),
-        }
+        })
     }
 
     /// Get the destination URL.
@@ -262,6 +274,10 @@ mod tests {
             action: action.to_string(),
             username: None,
             password: None,
+            cafile: None,
+            certfile: None,
+            keyfile: None,
+            insecure_skip_verify: false,
         }
     }
 
@@ -271,13 +287,17 @@ mod tests {
             action: action.to_string(),
             username: Some(username.to_string()),
             password: Some(password.to_string()),
+            cafile: None,
+            certfile: None,
+            keyfile: None,
+            insecure_skip_verify: false,
         }
     }
 
     #[test]
     fn test_http_forwarder_creation_post() {
         let config = make_config("post");
-        let forwarder = HttpForwarder::new(&config);
+        let forwarder = HttpForwarder::new(&config).unwrap();
 
         assert_eq!(forwarder.destination(), "http://example.com/webhook");
         assert_eq!(forwarder.action(), HttpAction::Post);
@@ -287,7 +307,7 @@ mod tests {
     #[test]
     fn test_http_forwarder_creation_put() {
         let config = make_config("put");
-        let forwarder = HttpForwarder::new(&config);
+        let forwarder = HttpForwarder::new(&config).unwrap();
 
         assert_eq!(forwarder.action(), HttpAction::Put);
     }
@@ -295,7 +315,7 @@ mod tests {
     #[test]
     fn test_http_forwarder_creation_patch() {
         let config = make_config("patch");
-        let forwarder = HttpForwarder::new(&config);
+        let forwarder = HttpForwarder::new(&config).unwrap();
 
         assert_eq!(forwarder.action(), HttpAction::Patch);
     }
@@ -303,22 +323,22 @@ mod tests {
     #[test]
     fn test_http_forwarder_case_insensitive() {
         let config = make_config("POST");
-        let forwarder = HttpForwarder::new(&config);
+        let forwarder = HttpForwarder::new(&config).unwrap();
         assert_eq!(forwarder.action(), HttpAction::Post);
 
         let config = make_config("Put");
-        let forwarder = HttpForwarder::new(&config);
+        let forwarder = HttpForwarder::new(&config).unwrap();
         assert_eq!(forwarder.action(), HttpAction::Put);
 
         let config = make_config("PATCH");
-        let forwarder = HttpForwarder::new(&config);
+        let forwarder = HttpForwarder::new(&config).unwrap();
         assert_eq!(forwarder.action(), HttpAction::Patch);
     }
 
     #[test]
     fn test_http_forwarder_unknown_action_defaults_to_post() {
         let config = make_config("unknown");
-        let forwarder = HttpForwarder::new(&config);
+        let forwarder = HttpForwarder::new(&config).unwrap();
 
         assert_eq!(forwarder.action(), HttpAction::Post);
     }
@@ -326,7 +346,7 @@ mod tests {
     #[test]
     fn test_http_forwarder_with_auth() {
         let config = make_config_with_auth("post", "user", "secret");
-        let forwarder = HttpForwarder::new(&config);
+        let forwarder = HttpForwarder::new(&config).unwrap();
 
         assert!(forwarder.has_auth());
     }
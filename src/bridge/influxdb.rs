@@ -1,12 +1,31 @@
 //! `InfluxDB` writer using line protocol over HTTP.
 
-use crate::config::InfluxDBConfig;
-use crate::error::SinqttError;
+use super::backoff::backoff_delay;
+use super::tls::{TlsOptions, http_client};
+use crate::config::{InfluxDBApiVersion, InfluxDBConfig, RetryConfig};
+use crate::error::{InfluxDbError, RejectedLine, SinqttError};
+use crate::health::health;
+use crate::metrics::metrics;
+use regex::Regex;
 use reqwest::Client;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
 use serde_json::Value;
 use std::collections::HashMap;
 use std::io::Write;
-use tracing::{debug, error};
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, warn};
+
+/// Matches per-line errors such as `line 2: invalid field format`.
+static LINE_ERROR_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?m)line (\d+): ([^\n]+)").expect("invalid LINE_ERROR_REGEX pattern")
+});
+
+/// Matches single-line parse errors such as `unable to parse 'm value=': missing field value`.
+static UNABLE_TO_PARSE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"unable to parse '([^']*)': ([^\n]+)")
+        .expect("invalid UNABLE_TO_PARSE_REGEX pattern")
+});
 
 /// `InfluxDB` writer for sending data points.
 pub struct InfluxDBWriter {
@@ -15,7 +34,11 @@ pub struct InfluxDBWriter {
     token: String,
     default_bucket: String,
     org: String,
+    api_version: InfluxDBApiVersion,
+    accept_partial: Option<bool>,
+    no_sync: Option<bool>,
     enable_gzip: bool,
+    retry: RetryConfig,
 }
 
 impl InfluxDBWriter {
@@ -23,9 +46,15 @@ impl InfluxDBWriter {
     ///
     /// # Errors
     ///
-    /// This function is infallible but returns `Result` for API consistency.
+    /// Returns an error if the TLS certificate files cannot be loaded.
     pub fn new(config: &InfluxDBConfig) -> Result<Self, SinqttError> {
-        let client = Client::new();
+        let client = http_client(TlsOptions {
+            cafile: config.cafile.as_deref(),
+            certfile: config.certfile.as_deref(),
+            keyfile: config.keyfile.as_deref(),
+            insecure_skip_verify: config.insecure_skip_verify,
+            ..TlsOptions::default()
+        })?;
 
         // Build base URL with proper scheme
         let base_url = if config.host.starts_with("http://") || config.host.starts_with("https://")
@@ -41,7 +70,11 @@ impl InfluxDBWriter {
             token: config.token.clone(),
             org: config.org.clone(),
             default_bucket: config.bucket.clone(),
+            api_version: config.api_version,
+            accept_partial: config.accept_partial,
+            no_sync: config.no_sync,
             enable_gzip: config.enable_gzip,
+            retry: config.retry.clone(),
         })
     }
 
@@ -55,11 +88,7 @@ impl InfluxDBWriter {
         point: &Point,
         bucket: Option<&str>,
     ) -> Result<(), SinqttError> {
-        let bucket = bucket.unwrap_or(&self.default_bucket);
-        let url = format!(
-            "{}/api/v2/write?org={}&bucket={}&precision=ns",
-            self.write_url, self.org, bucket
-        );
+        let url = self.build_write_url(bucket.unwrap_or(&self.default_bucket));
 
         let line = point.to_line_protocol();
         debug!("Writing to InfluxDB: {}", line);
@@ -81,11 +110,7 @@ impl InfluxDBWriter {
             return Ok(());
         }
 
-        let bucket = bucket.unwrap_or(&self.default_bucket);
-        let url = format!(
-            "{}/api/v2/write?org={}&bucket={}&precision=ns",
-            self.write_url, self.org, bucket
-        );
+        let url = self.build_write_url(bucket.unwrap_or(&self.default_bucket));
 
         let lines: Vec<String> = points.iter().map(|p| p.to_line_protocol()).collect();
         let body = lines.join("\n");
@@ -94,39 +119,155 @@ impl InfluxDBWriter {
         self.send_write_request(&url, body).await
     }
 
+    /// Write pre-encoded line protocol lines to `InfluxDB` in a single request.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the HTTP request fails or `InfluxDB` returns an error status.
+    pub async fn write_lines(
+        &self,
+        lines: &[String],
+        bucket: Option<&str>,
+    ) -> Result<(), SinqttError> {
+        if lines.is_empty() {
+            return Ok(());
+        }
+
+        let url = self.build_write_url(bucket.unwrap_or(&self.default_bucket));
+
+        let body = lines.join("\n");
+        debug!("Writing {} lines to InfluxDB", lines.len());
+
+        self.send_write_request(&url, body).await
+    }
+
+    /// Build the write endpoint URL for a bucket (database in v3).
+    #[must_use]
+    pub fn build_write_url(&self, bucket: &str) -> String {
+        match self.api_version {
+            InfluxDBApiVersion::V2 => format!(
+                "{}/api/v2/write?org={}&bucket={}&precision=ns",
+                self.write_url, self.org, bucket
+            ),
+            InfluxDBApiVersion::V3 => {
+                let mut url = format!(
+                    "{}/api/v3/write_lp?db={}&precision=nanosecond",
+                    self.write_url, bucket
+                );
+                if let Some(accept_partial) = self.accept_partial {
+                    url.push_str(&format!("&accept_partial={accept_partial}"));
+                }
+                if let Some(no_sync) = self.no_sync {
+                    url.push_str(&format!("&no_sync={no_sync}"));
+                }
+                url
+            }
+        }
+    }
+
+    /// Build the `Authorization` header value for the configured API version.
+    fn authorization(&self) -> String {
+        match self.api_version {
+            InfluxDBApiVersion::V2 => format!("Token {}", self.token),
+            InfluxDBApiVersion::V3 => format!("Bearer {}", self.token),
+        }
+    }
+
     /// Send write request with optional gzip compression.
+    ///
+    /// Retryable failures are retried with jittered exponential backoff,
+    /// honoring `Retry-After` when the server provides it.
     async fn send_write_request(&self, url: &str, body: String) -> Result<(), SinqttError> {
-        let mut request = self
-            .client
-            .post(url)
-            .header("Authorization", format!("Token {}", self.token))
-            .header("Content-Type", "text/plain; charset=utf-8");
-
-        let body_bytes = if self.enable_gzip {
+        let payload = if self.enable_gzip {
             // Compress with gzip
             let mut encoder =
                 flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
             encoder.write_all(body.as_bytes())?;
-            let compressed = encoder.finish()?;
-            request = request.header("Content-Encoding", "gzip");
-            compressed
+            encoder.finish()?
         } else {
-            body.into_bytes()
+            body.clone().into_bytes()
         };
 
-        let response = request.body(body_bytes).send().await?;
+        let mut attempt = 0;
+        loop {
+            let started = Instant::now();
+            let result = self.send_once(url, payload.clone(), &body).await;
+            metrics().observe_write_duration(started.elapsed());
+
+            let err = match result {
+                Ok(()) => {
+                    health().write_succeeded();
+                    return Ok(());
+                }
+                Err(e) => e,
+            };
+
+            if err.is_retryable() && attempt < self.retry.max_retries {
+                let delay = retry_delay(&self.retry, attempt, &err, fastrand::f64());
+                attempt += 1;
+                warn!(
+                    "InfluxDB write failed: {} - retrying in {:?} (attempt {}/{})",
+                    err, delay, attempt, self.retry.max_retries
+                );
+                tokio::time::sleep(delay).await;
+                continue;
+            }
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            error!("InfluxDB write failed: {} - {}", status, body);
-            return Err(SinqttError::InfluxDb(format!(
-                "Write failed with status {}: {}",
-                status, body
-            )));
+            error!("InfluxDB write failed: {}", err);
+            if let InfluxDbError::Rejected { lines, .. } = &err {
+                for rejected in lines {
+                    error!(
+                        "Rejected line {}: {} ({})",
+                        rejected
+                            .line_number
+                            .map_or_else(|| "?".to_string(), |n| n.to_string()),
+                        rejected.line.as_deref().unwrap_or("<unknown>"),
+                        rejected.message
+                    );
+                }
+            } else {
+                // Rejected lines are a data problem, not an unhealthy database
+                health().write_failed();
+            }
+            return Err(err.into());
         }
+    }
 
-        Ok(())
+    /// Send a single write request and classify any failure.
+    async fn send_once(
+        &self,
+        url: &str,
+        payload: Vec<u8>,
+        body: &str,
+    ) -> Result<(), InfluxDbError> {
+        let mut request = self
+            .client
+            .post(url)
+            .header("Authorization", self.authorization())
+            .header("Content-Type", "text/plain; charset=utf-8");
+        if self.enable_gzip {
+            request = request.header("Content-Encoding", "gzip");
+        }
+
+        let response = request
+            .body(payload)
+            .send()
+            .await
+            .map_err(|e| InfluxDbError::Connection(e.to_string()))?;
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+
+        let retry_after = parse_retry_after(response.headers());
+        let response_body = response.text().await.unwrap_or_default();
+        Err(classify_error(
+            status.as_u16(),
+            retry_after,
+            &response_body,
+            body,
+        ))
     }
 
     /// Get the default bucket name.
@@ -136,6 +277,176 @@ impl InfluxDBWriter {
     }
 }
 
+/// Delay before the next retry, preferring the server's `Retry-After`.
+fn retry_delay(config: &RetryConfig, attempt: u32, err: &InfluxDbError, jitter: f64) -> Duration {
+    let max_delay = Duration::from_millis(config.max_delay_ms);
+    match err {
+        InfluxDbError::Server {
+            retry_after: Some(retry_after),
+            ..
+        } => (*retry_after).min(max_delay),
+        _ => backoff_delay(
+            config.initial_delay_ms,
+            config.max_delay_ms,
+            attempt,
+            jitter,
+        ),
+    }
+}
+
+/// Parse a `Retry-After` header given in seconds or as an HTTP date.
+fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?;
+    retry_after_delay(value, chrono::Utc::now())
+}
+
+/// Delay until a `Retry-After` value, relative to `now`.
+///
+/// HTTP dates accept the IMF-fixdate form and the obsolete RFC 850 and
+/// asctime forms; a date in the past yields no delay.
+fn retry_after_delay(value: &str, now: chrono::DateTime<chrono::Utc>) -> Option<Duration> {
+    use chrono::{DateTime, NaiveDateTime, Utc};
+
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value)
+        .map(|date| date.with_timezone(&Utc))
+        .or_else(|_| {
+            NaiveDateTime::parse_from_str(value, "%A, %d-%b-%y %H:%M:%S GMT")
+                .or_else(|_| NaiveDateTime::parse_from_str(value, "%a %b %e %H:%M:%S %Y"))
+                .map(|date| date.and_utc())
+        })
+        .ok()?;
+    Some((date - now).to_std().unwrap_or(Duration::ZERO))
+}
+
+/// Classify an error response from `InfluxDB`.
+///
+/// `sent` is the line protocol body of the request, used to look up the
+/// offending lines reported by line number.
+fn classify_error(
+    status: u16,
+    retry_after: Option<Duration>,
+    response_body: &str,
+    sent: &str,
+) -> InfluxDbError {
+    let message = error_message(response_body);
+    match status {
+        408 | 429 | 500..=599 => InfluxDbError::Server {
+            status,
+            message,
+            retry_after,
+        },
+        400 | 422 => {
+            let lines = parse_rejected_lines(response_body, &message, sent);
+            InfluxDbError::Rejected {
+                status,
+                message,
+                lines,
+            }
+        }
+        _ => InfluxDbError::Client { status, message },
+    }
+}
+
+/// Extract the error message from a JSON error body, falling back to the raw body.
+///
+/// v2 responses carry `message`, v3 responses carry `error`.
+fn error_message(response_body: &str) -> String {
+    serde_json::from_str::<Value>(response_body)
+        .ok()
+        .and_then(|v| {
+            v.get("message")
+                .or_else(|| v.get("error"))
+                .and_then(Value::as_str)
+                .map(String::from)
+        })
+        .unwrap_or_else(|| response_body.trim().to_string())
+}
+
+/// Parse the lines reported as rejected in an error response.
+///
+/// v3 responses list rejected lines in a structured `data` array (or a single
+/// object); v2 responses describe them in the error message text.
+fn parse_rejected_lines(response_body: &str, message: &str, sent: &str) -> Vec<RejectedLine> {
+    let sent_lines: Vec<&str> = sent.lines().collect();
+
+    if let Some(data) = serde_json::from_str::<Value>(response_body)
+        .ok()
+        .and_then(|v| v.get("data").cloned())
+    {
+        let entries = match data {
+            Value::Array(entries) => entries,
+            entry @ Value::Object(_) => vec![entry],
+            _ => Vec::new(),
+        };
+        let rejected: Vec<RejectedLine> = entries
+            .iter()
+            .map(|entry| {
+                let line_number = entry
+                    .get("line_number")
+                    .and_then(Value::as_u64)
+                    .and_then(|n| usize::try_from(n).ok());
+                let line = entry
+                    .get("original_line")
+                    .and_then(Value::as_str)
+                    .map(String::from)
+                    .or_else(|| {
+                        line_number
+                            .and_then(|n| n.checked_sub(1))
+                            .and_then(|i| sent_lines.get(i))
+                            .map(|l| (*l).to_string())
+                    });
+                RejectedLine {
+                    line_number,
+                    line,
+                    message: entry
+                        .get("error_message")
+                        .and_then(Value::as_str)
+                        .unwrap_or_default()
+                        .to_string(),
+                }
+            })
+            .collect();
+        if !rejected.is_empty() {
+            return rejected;
+        }
+    }
+
+    let mut rejected: Vec<RejectedLine> = LINE_ERROR_REGEX
+        .captures_iter(message)
+        .filter_map(|cap| {
+            let line_number = cap.get(1)?.as_str().parse::<usize>().ok()?;
+            Some(RejectedLine {
+                line_number: Some(line_number),
+                line: line_number
+                    .checked_sub(1)
+                    .and_then(|i| sent_lines.get(i))
+                    .map(|l| (*l).to_string()),
+                message: cap.get(2)?.as_str().trim().to_string(),
+            })
+        })
+        .collect();
+
+    if rejected.is_empty() {
+        rejected = UNABLE_TO_PARSE_REGEX
+            .captures_iter(message)
+            .filter_map(|cap| {
+                let line = cap.get(1)?.as_str();
+                Some(RejectedLine {
+                    line_number: sent_lines.iter().position(|l| *l == line).map(|i| i + 1),
+                    line: Some(line.to_string()),
+                    message: cap.get(2)?.as_str().trim().to_string(),
+                })
+            })
+            .collect();
+    }
+
+    rejected
+}
+
 /// Represents an InfluxDB data point.
 #[derive(Debug, Clone)]
 pub struct Point {
@@ -326,6 +637,25 @@ impl Point {
     }
 }
 
+/// Extract the unescaped measurement name from a line protocol line.
+#[must_use]
+pub fn line_measurement(line: &str) -> String {
+    let mut measurement = String::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(escaped) = chars.next() {
+                    measurement.push(escaped);
+                }
+            }
+            ',' | ' ' => break,
+            c => measurement.push(c),
+        }
+    }
+    measurement
+}
+
 fn escape_measurement(s: &str) -> String {
     s.replace(',', "\\,").replace(' ', "\\ ")
 }
@@ -356,6 +686,231 @@ fn escape_string_value(s: &str) -> String {
 mod tests {
     use super::*;
 
+    fn make_retry_config() -> RetryConfig {
+        RetryConfig {
+            max_retries: 3,
+            initial_delay_ms: 100,
+            max_delay_ms: 1000,
+        }
+    }
+
+    #[test]
+    fn test_retry_delay_honors_retry_after() {
+        let config = make_retry_config();
+        let err = InfluxDbError::Server {
+            status: 429,
+            message: "slow down".into(),
+            retry_after: Some(Duration::from_millis(700)),
+        };
+        assert_eq!(
+            retry_delay(&config, 0, &err, 1.0),
+            Duration::from_millis(700)
+        );
+
+        // Retry-After is capped by the maximum delay
+        let err = InfluxDbError::Server {
+            status: 503,
+            message: "unavailable".into(),
+            retry_after: Some(Duration::from_secs(60)),
+        };
+        assert_eq!(
+            retry_delay(&config, 0, &err, 1.0),
+            Duration::from_millis(1000)
+        );
+    }
+
+    #[test]
+    fn test_parse_retry_after_seconds() {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, "5".parse().unwrap());
+        assert_eq!(parse_retry_after(&headers), Some(Duration::from_secs(5)));
+
+        headers.insert(
+            RETRY_AFTER,
+            "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap(),
+        );
+        // A date in the past means retry right away
+        assert_eq!(parse_retry_after(&headers), Some(Duration::ZERO));
+
+        headers.insert(RETRY_AFTER, "soon".parse().unwrap());
+        assert_eq!(parse_retry_after(&headers), None);
+    }
+
+    #[test]
+    fn test_parse_retry_after_http_date() {
+        let now = chrono::DateTime::parse_from_rfc3339("2015-10-21T07:27:30Z")
+            .unwrap()
+            .to_utc();
+        for value in [
+            "Wed, 21 Oct 2015 07:28:00 GMT",
+            "Wednesday, 21-Oct-15 07:28:00 GMT",
+            "Wed Oct 21 07:28:00 2015",
+        ] {
+            assert_eq!(
+                retry_after_delay(value, now),
+                Some(Duration::from_secs(30)),
+                "{value}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_classify_retryable_statuses() {
+        for status in [408, 429, 500, 502, 503, 504] {
+            let err = classify_error(status, None, "", "");
+            assert!(err.is_retryable(), "status {status} should be retryable");
+        }
+    }
+
+    #[test]
+    fn test_classify_client_error_not_retryable() {
+        let err = classify_error(
+            401,
+            None,
+            r#"{"code":"unauthorized","message":"unauthorized access"}"#,
+            "",
+        );
+        assert!(!err.is_retryable());
+        assert!(matches!(
+            err,
+            InfluxDbError::Client { status: 401, ref message } if message == "unauthorized access"
+        ));
+    }
+
+    #[test]
+    fn test_classify_rejected_with_line_numbers() {
+        let sent = "m value=1i\nm value=\nm value=\"x\"";
+        let body = r#"{"code":"invalid","message":"failed to parse line protocol:\nerrors encountered on line(s):\nline 2: missing field value\nline 3: field type conflict"}"#;
+
+        let err = classify_error(400, None, body, sent);
+        assert!(!err.is_retryable());
+        let InfluxDbError::Rejected { lines, .. } = err else {
+            panic!("expected rejected error");
+        };
+        assert_eq!(
+            lines,
+            vec![
+                RejectedLine {
+                    line_number: Some(2),
+                    line: Some("m value=".to_string()),
+                    message: "missing field value".to_string(),
+                },
+                RejectedLine {
+                    line_number: Some(3),
+                    line: Some("m value=\"x\"".to_string()),
+                    message: "field type conflict".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_classify_rejected_unable_to_parse() {
+        let sent = "m value=1i\nweather temperature=";
+        let body = r#"{"code":"invalid","message":"unable to parse 'weather temperature=': missing field value"}"#;
+
+        let InfluxDbError::Rejected { lines, .. } = classify_error(400, None, body, sent) else {
+            panic!("expected rejected error");
+        };
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].line_number, Some(2));
+        assert_eq!(lines[0].line.as_deref(), Some("weather temperature="));
+        assert_eq!(lines[0].message, "missing field value");
+    }
+
+    #[test]
+    fn test_classify_rejected_v3_partial_write() {
+        let sent = "home,room=Kitchen temp=21.0\nhome,room=Kitchen temp=hi";
+        let body = r#"{"error":"partial write of line protocol occurred","data":[{"original_line":"home,room=Kitchen temp=hi","line_number":2,"error_message":"invalid column type for column 'temp'"}]}"#;
+
+        let InfluxDbError::Rejected { message, lines, .. } = classify_error(400, None, body, sent)
+        else {
+            panic!("expected rejected error");
+        };
+        assert_eq!(message, "partial write of line protocol occurred");
+        assert_eq!(
+            lines,
+            vec![RejectedLine {
+                line_number: Some(2),
+                line: Some("home,room=Kitchen temp=hi".to_string()),
+                message: "invalid column type for column 'temp'".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_classify_rejected_v3_single_object() {
+        let sent = "m value=1i\nm value=";
+        let body = r#"{"error":"parsing failed for write_lp endpoint","data":{"line_number":2,"error_message":"No fields were provided"}}"#;
+
+        let InfluxDbError::Rejected { lines, .. } = classify_error(400, None, body, sent) else {
+            panic!("expected rejected error");
+        };
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].line.as_deref(), Some("m value="));
+        assert_eq!(lines[0].message, "No fields were provided");
+    }
+
+    fn make_influxdb_config(api_version: InfluxDBApiVersion) -> InfluxDBConfig {
+        InfluxDBConfig {
+            host: "localhost".to_string(),
+            port: 8181,
+            token: "secret".to_string(),
+            org: "my-org".to_string(),
+            bucket: "my-bucket".to_string(),
+            api_version,
+            accept_partial: None,
+            no_sync: None,
+            enable_gzip: false,
+            batch: crate::config::BatchConfig::default(),
+            spool: None,
+            retry: RetryConfig::default(),
+            cafile: None,
+            certfile: None,
+            keyfile: None,
+            insecure_skip_verify: false,
+        }
+    }
+
+    #[test]
+    fn test_build_write_url_v2() {
+        let writer = InfluxDBWriter::new(&make_influxdb_config(InfluxDBApiVersion::V2)).unwrap();
+
+        assert_eq!(
+            writer.build_write_url("data"),
+            "http://localhost:8181/api/v2/write?org=my-org&bucket=data&precision=ns"
+        );
+        assert_eq!(writer.authorization(), "Token secret");
+    }
+
+    #[test]
+    fn test_build_write_url_v3() {
+        let mut config = make_influxdb_config(InfluxDBApiVersion::V3);
+        config.accept_partial = Some(false);
+        config.no_sync = Some(true);
+        let writer = InfluxDBWriter::new(&config).unwrap();
+
+        assert_eq!(
+            writer.build_write_url("data"),
+            "http://localhost:8181/api/v3/write_lp?db=data&precision=nanosecond&accept_partial=false&no_sync=true"
+        );
+        assert_eq!(writer.authorization(), "Bearer secret");
+    }
+
+    #[test]
+    fn test_error_message_plain_text_body() {
+        assert_eq!(error_message("  bad gateway \n"), "bad gateway");
+    }
+
+    #[test]
+    fn test_line_measurement() {
+        assert_eq!(line_measurement("temperature value=23.5"), "temperature");
+        assert_eq!(line_measurement("temp,room=a value=1i 123"), "temp");
+
+        let point = Point::new("my room,x").field("value", FieldValue::Int(1));
+        assert_eq!(line_measurement(&point.to_line_protocol()), "my room,x");
+    }
+
     #[test]
     fn test_line_protocol_simple() {
         let point = Point::new("temperature").field("value", FieldValue::Float(23.5));
@@ -1,11 +1,39 @@
 //! Bridge module - connects MQTT to `InfluxDB`.
 
+mod backoff;
+mod batch;
+mod credentials;
+mod flatten;
 mod http;
 mod influxdb;
 mod mqtt;
+mod plan;
 mod processor;
+mod spool;
+mod subscription;
+mod timestamp;
+mod tls;
 
+pub use batch::{BatchBuffer, BatchWriter, PendingBatch};
+#[cfg(feature = "jwt")]
+pub use credentials::JwtGenerator;
+pub use credentials::{
+    Credentials, Login, Password, PasswordFile, PasswordProvider, StaticPassword,
+};
+pub use flatten::{flatten_value, glob_matches};
 pub use http::{HttpAction, HttpContentBuilder, HttpForwarder};
 pub use influxdb::{FieldValue, InfluxDBWriter, Point};
-pub use mqtt::{MqttHandler, MqttMessage};
+pub use mqtt::{
+    MessageAck, MqttHandler, MqttMessage, Subscriptions, diff_subscriptions, shared_subscription,
+    topic_filter, websocket_url,
+};
+pub use plan::{
+    CompiledExpression, CompiledPath, FieldPlan, MessageScope, PointPlan, TimestampPlan, ValueSpec,
+};
 pub use processor::{MessageProcessor, ParsedMessage};
+pub use spool::{Spool, SpooledBatch};
+pub use subscription::{Subscription, covers, overlaps, partial_overlaps, plan_subscriptions};
+pub use timestamp::{TimestampFormat, TimestampZone, parse_timestamp};
+#[cfg(feature = "tls")]
+pub use tls::client_config;
+pub use tls::{TlsOptions, http_client};
@@ -1,25 +1,586 @@
 //! MQTT client handler.
 
-use crate::config::MqttConfig;
+use super::backoff::backoff_delay;
+use super::credentials::{Credentials, Login};
+use super::subscription::Subscription;
+#[cfg(any(feature = "tls", feature = "websocket"))]
+use super::tls::TlsOptions;
+use crate::config::{FailoverStrategy, MqttConfig, MqttProtocol};
 use crate::error::SinqttError;
-use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Packet, QoS, Transport};
-use std::time::Duration;
-use tokio::sync::mpsc;
+use crate::health::health;
+use crate::metrics::metrics;
+use rumqttc::v5::mqttbytes::v5::{
+    DisconnectReasonCode, Packet as V5Packet, Publish as V5Publish, PublishProperties,
+};
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Packet, Publish, QoS, Transport, v5};
+use serde_json::{Map, Value, json};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::sync::{Notify, mpsc};
 use tracing::{debug, error, info, warn};
 
+/// Keep-alive interval for the broker connection.
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+
+/// Request path of WebSocket connections when not configured.
+const DEFAULT_WEBSOCKET_PATH: &str = "/mqtt";
+
+/// Longest time before a password expires at which the connection is renewed.
+const RENEWAL_MARGIN: Duration = Duration::from_secs(60);
+
+/// Capacity of the request channel between client and event loop.
+const REQUEST_CAPACITY: usize = 100;
+
 /// Message received from MQTT broker.
 #[derive(Debug, Clone)]
 pub struct MqttMessage {
+    /// Name of the broker the message was received from.
+    pub broker: String,
     pub topic: String,
     pub payload: Vec<u8>,
     pub qos: u8,
+    /// MQTT v5 publish properties, addressable as `$.properties`.
+    pub properties: Option<Value>,
+    /// Pending acknowledgement when `ack_after_write` is enabled.
+    pub ack: Option<MessageAck>,
+}
+
+/// Deferred acknowledgement of a QoS 1/2 publish.
+///
+/// Clones travel with the points produced from the message. The message is
+/// settled when the last clone is dropped: it is acknowledged if processing
+/// was marked complete and no write failed. Otherwise the handler reconnects
+/// so that the broker redelivers it, provided the session is persistent.
+#[derive(Clone)]
+pub struct MessageAck {
+    inner: Arc<AckInner>,
+}
+
+struct AckInner {
+    queue: Arc<AckQueue>,
+    seq: u64,
+    pkid: u16,
+    completed: AtomicBool,
+    failed: AtomicBool,
+}
+
+impl MessageAck {
+    fn new(queue: Arc<AckQueue>, publish: ReceivedPublish) -> Self {
+        let pkid = publish.pkid();
+        let seq = queue.register(publish);
+        Self {
+            inner: Arc::new(AckInner {
+                queue,
+                seq,
+                pkid,
+                completed: AtomicBool::new(false),
+                failed: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    /// Mark the message as fully processed, with all points queued.
+    pub fn complete(&self) {
+        self.inner.completed.store(true, Ordering::Release);
+    }
+
+    /// Prevent the acknowledgement, e.g. because a point was not written.
+    pub fn fail(&self) {
+        self.inner.failed.store(true, Ordering::Release);
+    }
+}
+
+impl std::fmt::Debug for MessageAck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MessageAck")
+            .field("pkid", &self.inner.pkid)
+            .field("completed", &self.inner.completed.load(Ordering::Acquire))
+            .field("failed", &self.inner.failed.load(Ordering::Acquire))
+            .finish()
+    }
+}
+
+impl Drop for AckInner {
+    fn drop(&mut self) {
+        let acknowledge =
+            self.completed.load(Ordering::Acquire) && !self.failed.load(Ordering::Acquire);
+        if !acknowledge {
+            debug!("Leaving message {} unacknowledged", self.pkid);
+        }
+        self.queue.settle(self.seq, acknowledge);
+    }
+}
+
+/// Acknowledgements of the messages received on one connection.
+///
+/// MQTT requires acknowledgements in the order the publishes were received,
+/// so a settled message is only acknowledged once all earlier ones are. When
+/// a message fails, the queue is closed and the handler is notified to reset
+/// the connection; acknowledgements still pending on a closed queue are
+/// dropped, as the broker redelivers those messages on the new connection.
+struct AckQueue {
+    client: Client,
+    failed: Arc<Notify>,
+    state: Mutex<AckState>,
+}
+
+#[derive(Default)]
+struct AckState {
+    /// Sequence number of the first entry.
+    first: u64,
+    /// Publishes in order of receipt, with whether each may be acknowledged.
+    entries: VecDeque<(ReceivedPublish, bool)>,
+    closed: bool,
+}
+
+impl AckQueue {
+    fn new(client: Client, failed: Arc<Notify>) -> Self {
+        Self {
+            client,
+            failed,
+            state: Mutex::new(AckState::default()),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, AckState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Add a received publish, returning its sequence number.
+    fn register(&self, publish: ReceivedPublish) -> u64 {
+        let mut state = self.lock();
+        let seq = state.first + state.entries.len() as u64;
+        if !state.closed {
+            state.entries.push_back((publish, false));
+        }
+        seq
+    }
+
+    /// Settle a message, sending the acknowledgements that are now in order.
+    fn settle(&self, seq: u64, acknowledge: bool) {
+        let mut state = self.lock();
+        if state.closed {
+            return;
+        }
+        if !acknowledge {
+            self.fail(&mut state);
+            return;
+        }
+        let index = usize::try_from(seq - state.first).unwrap_or(usize::MAX);
+        if let Some(entry) = state.entries.get_mut(index) {
+            entry.1 = true;
+        }
+        while state.entries.front().is_some_and(|(_, ready)| *ready) {
+            let Some((publish, _)) = state.entries.pop_front() else {
+                break;
+            };
+            state.first += 1;
+            if let Err(e) = self.client.try_ack(&publish) {
+                warn!(
+                    "Failed to acknowledge message {} on {}: {}",
+                    publish.pkid(),
+                    publish.topic(),
+                    e
+                );
+                self.fail(&mut state);
+                return;
+            }
+        }
+    }
+
+    /// Stop acknowledging, e.g. because the connection was lost.
+    fn close(&self) {
+        let mut state = self.lock();
+        state.closed = true;
+        state.entries.clear();
+    }
+
+    fn fail(&self, state: &mut AckState) {
+        state.closed = true;
+        state.entries.clear();
+        self.failed.notify_one();
+    }
+}
+
+/// Client for the configured protocol version.
+#[derive(Clone)]
+enum Client {
+    V3(AsyncClient),
+    V5(v5::AsyncClient),
+}
+
+impl Client {
+    async fn subscribe(&self, subscription: &Subscription) -> Result<(), SinqttError> {
+        let topic = subscription.filter.as_str();
+        match self {
+            Self::V3(client) => {
+                let qos = match subscription.qos {
+                    0 => QoS::AtMostOnce,
+                    1 => QoS::AtLeastOnce,
+                    _ => QoS::ExactlyOnce,
+                };
+                client.subscribe(topic, qos).await?;
+            }
+            Self::V5(client) => {
+                let qos = match subscription.qos {
+                    0 => v5::mqttbytes::QoS::AtMostOnce,
+                    1 => v5::mqttbytes::QoS::AtLeastOnce,
+                    _ => v5::mqttbytes::QoS::ExactlyOnce,
+                };
+                client.subscribe(topic, qos).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn unsubscribe(&self, topic: &str) -> Result<(), SinqttError> {
+        match self {
+            Self::V3(client) => client.unsubscribe(topic).await?,
+            Self::V5(client) => client.unsubscribe(topic).await?,
+        }
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> Result<(), SinqttError> {
+        match self {
+            Self::V3(client) => client.disconnect().await?,
+            Self::V5(client) => client.disconnect().await?,
+        }
+        Ok(())
+    }
+
+    fn try_ack(&self, publish: &ReceivedPublish) -> Result<(), SinqttError> {
+        match (self, publish) {
+            (Self::V3(client), ReceivedPublish::V3(publish)) => client.try_ack(publish)?,
+            (Self::V5(client), ReceivedPublish::V5(publish)) => client.try_ack(publish)?,
+            _ => unreachable!("publish received with a different protocol version"),
+        }
+        Ok(())
+    }
+}
+
+/// Publish packet of either protocol version.
+#[derive(Debug, Clone)]
+enum ReceivedPublish {
+    V3(Publish),
+    V5(V5Publish),
+}
+
+impl ReceivedPublish {
+    fn pkid(&self) -> u16 {
+        match self {
+            Self::V3(publish) => publish.pkid,
+            Self::V5(publish) => publish.pkid,
+        }
+    }
+
+    fn topic(&self) -> String {
+        match self {
+            Self::V3(publish) => publish.topic.clone(),
+            Self::V5(publish) => String::from_utf8_lossy(&publish.topic).into_owned(),
+        }
+    }
+
+    fn qos(&self) -> u8 {
+        match self {
+            Self::V3(publish) => publish.qos as u8,
+            Self::V5(publish) => publish.qos as u8,
+        }
+    }
+
+    fn payload(&self) -> &[u8] {
+        match self {
+            Self::V3(publish) => &publish.payload,
+            Self::V5(publish) => &publish.payload,
+        }
+    }
+
+    fn properties(&self) -> Option<Value> {
+        match self {
+            Self::V3(_) => None,
+            Self::V5(publish) => publish.properties.as_ref().map(properties_object),
+        }
+    }
+}
+
+/// Convert v5 publish properties into the object exposed as `$.properties`.
+///
+/// User properties are collected into `user_properties`; when a name is
+/// repeated, the last value wins.
+fn properties_object(properties: &PublishProperties) -> Value {
+    let mut obj = Map::new();
+    if let Some(content_type) = &properties.content_type {
+        obj.insert("content_type".to_string(), json!(content_type));
+    }
+    if let Some(response_topic) = &properties.response_topic {
+        obj.insert("response_topic".to_string(), json!(response_topic));
+    }
+    if let Some(expiry) = properties.message_expiry_interval {
+        obj.insert("message_expiry_interval".to_string(), json!(expiry));
+    }
+    if let Some(indicator) = properties.payload_format_indicator {
+        obj.insert("payload_format_indicator".to_string(), json!(indicator));
+    }
+    if !properties.user_properties.is_empty() {
+        let user = properties
+            .user_properties
+            .iter()
+            .map(|(name, value)| (name.clone(), json!(value)))
+            .collect();
+        obj.insert("user_properties".to_string(), Value::Object(user));
+    }
+    Value::Object(obj)
+}
+
+/// Event loop for the configured protocol version.
+enum Connection {
+    V3(Box<EventLoop>),
+    V5(Box<v5::EventLoop>),
+}
+
+/// Protocol-independent event from the event loop.
+#[derive(Debug)]
+enum BrokerEvent {
+    Connected {
+        session_present: bool,
+    },
+    ConnectionRefused(String),
+    SubAck {
+        granted: usize,
+        rejected: Vec<String>,
+    },
+    Publish(ReceivedPublish),
+    Disconnected(String),
+    Error(String),
+    Other(String),
+    Ignored,
+}
+
+impl Connection {
+    /// Drop the network connection so that the next poll reconnects.
+    fn reset(&mut self) {
+        match self {
+            Self::V3(eventloop) => eventloop.clean(),
+            Self::V5(eventloop) => eventloop.clean(),
+        }
+    }
+
+    async fn poll(&mut self) -> BrokerEvent {
+        match self {
+            Self::V3(eventloop) => match eventloop.poll().await {
+                Ok(Event::Incoming(Packet::ConnAck(connack))) => {
+                    if connack.code == rumqttc::ConnectReturnCode::Success {
+                        BrokerEvent::Connected {
+                            session_present: connack.session_present,
+                        }
+                    } else {
+                        BrokerEvent::ConnectionRefused(format!("{:?}", connack.code))
+                    }
+                }
+                Ok(Event::Incoming(Packet::SubAck(suback))) => {
+                    let rejected: Vec<String> = suback
+                        .return_codes
+                        .iter()
+                        .filter(|code| matches!(code, rumqttc::SubscribeReasonCode::Failure))
+                        .map(|code| format!("{code:?}"))
+                        .collect();
+                    BrokerEvent::SubAck {
+                        granted: suback.return_codes.len() - rejected.len(),
+                        rejected,
+                    }
+                }
+                Ok(Event::Incoming(Packet::Publish(publish))) => {
+                    BrokerEvent::Publish(ReceivedPublish::V3(publish))
+                }
+                Ok(Event::Incoming(Packet::Disconnect)) => {
+                    BrokerEvent::Disconnected("disconnect received".to_string())
+                }
+                Ok(Event::Outgoing(_)) => BrokerEvent::Ignored,
+                Ok(event) => BrokerEvent::Other(format!("{event:?}")),
+                Err(rumqttc::ConnectionError::ConnectionRefused(code)) => {
+                    BrokerEvent::ConnectionRefused(format!("{code:?}"))
+                }
+                Err(e) => BrokerEvent::Error(e.to_string()),
+            },
+            Self::V5(eventloop) => match eventloop.poll().await {
+                Ok(v5::Event::Incoming(V5Packet::ConnAck(connack))) => {
+                    if connack.code == v5::mqttbytes::v5::ConnectReturnCode::Success {
+                        BrokerEvent::Connected {
+                            session_present: connack.session_present,
+                        }
+                    } else {
+                        let reason = connack.properties.and_then(|p| p.reason_string);
+                        BrokerEvent::ConnectionRefused(reason_text(&connack.code, reason))
+                    }
+                }
+                Ok(v5::Event::Incoming(V5Packet::SubAck(suback))) => {
+                    let rejected: Vec<String> = suback
+                        .return_codes
+                        .iter()
+                        .filter(|code| {
+                            !matches!(code, v5::mqttbytes::v5::SubscribeReasonCode::Success(_))
+                        })
+                        .map(|code| format!("{code:?}"))
+                        .collect();
+                    BrokerEvent::SubAck {
+                        granted: suback.return_codes.len() - rejected.len(),
+                        rejected,
+                    }
+                }
+                Ok(v5::Event::Incoming(V5Packet::Publish(publish))) => {
+                    BrokerEvent::Publish(ReceivedPublish::V5(publish))
+                }
+                Ok(v5::Event::Incoming(V5Packet::Disconnect(disconnect))) => {
+                    let reason = disconnect.properties.and_then(|p| p.reason_string);
+                    BrokerEvent::Disconnected(disconnect_reason(disconnect.reason_code, reason))
+                }
+                Ok(v5::Event::Outgoing(_)) => BrokerEvent::Ignored,
+                Ok(event) => BrokerEvent::Other(format!("{event:?}")),
+                Err(v5::ConnectionError::MqttState(v5::StateError::ServerDisconnect {
+                    reason_code,
+                    reason_string,
+                })) => BrokerEvent::Disconnected(disconnect_reason(reason_code, reason_string)),
+                Err(v5::ConnectionError::ConnectionRefused(code)) => {
+                    BrokerEvent::ConnectionRefused(reason_text(&code, None))
+                }
+                Err(e) => BrokerEvent::Error(e.to_string()),
+            },
+        }
+    }
+}
+
+/// Describe a v5 disconnect reason code and optional reason string.
+fn disconnect_reason(code: DisconnectReasonCode, reason: Option<String>) -> String {
+    format!("{} (0x{:02x})", reason_text(&code, reason), code as u8)
+}
+
+fn reason_text(code: &impl std::fmt::Debug, reason: Option<String>) -> String {
+    match reason {
+        Some(reason) => format!("{code:?}: {reason}"),
+        None => format!("{code:?}"),
+    }
 }
 
 /// MQTT handler for connecting to broker and receiving messages.
 pub struct MqttHandler {
-    client: AsyncClient,
-    eventloop: EventLoop,
-    topics: Vec<String>,
+    broker: String,
+    config: MqttConfig,
+    client_id: String,
+    credentials: Credentials,
+    /// Time to reconnect with new credentials before the password expires.
+    renew_at: Option<SystemTime>,
+    client: Client,
+    connection: Connection,
+    /// The broker followed by its fallbacks.
+    endpoints: Vec<Endpoint>,
+    /// Index of the endpoint currently connected to or tried.
+    current: usize,
+    subscriptions: Arc<Mutex<Vec<Subscription>>>,
+    /// Acknowledgements of messages received on the current connection.
+    acks: Arc<AckQueue>,
+    /// Notified when a message of the current connection is not written.
+    ack_failed: Arc<Notify>,
+}
+
+/// Handle for changing the subscribed topics of a running handler.
+#[derive(Clone)]
+pub struct Subscriptions {
+    broker: String,
+    client: Client,
+    subscriptions: Arc<Mutex<Vec<Subscription>>>,
+}
+
+impl Subscriptions {
+    /// Name of the broker the subscriptions are made on.
+    #[must_use]
+    pub fn broker(&self) -> &str {
+        &self.broker
+    }
+
+    /// Get the current subscriptions.
+    #[must_use]
+    pub fn current(&self) -> Vec<Subscription> {
+        lock_subscriptions(&self.subscriptions).clone()
+    }
+
+    /// Replace the subscriptions, subscribing to new or changed filters and
+    /// unsubscribing from removed ones.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a subscribe or unsubscribe request cannot be queued.
+    pub async fn update(&self, subscriptions: Vec<Subscription>) -> Result<(), SinqttError> {
+        let (added, removed) = {
+            let mut current = lock_subscriptions(&self.subscriptions);
+            let diff = diff_subscriptions(&current, &subscriptions);
+            *current = subscriptions;
+            diff
+        };
+
+        health().subscriptions_requested(&self.broker, added.len());
+        for topic in removed {
+            info!("Unsubscribing from topic: {}", topic);
+            self.client.unsubscribe(&topic).await?;
+        }
+        for subscription in added {
+            info!(
+                "Subscribing to topic: {} (QoS {})",
+                subscription.filter, subscription.qos
+            );
+            self.client.subscribe(&subscription).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Compute the subscriptions to make and the topic filters to unsubscribe from.
+///
+/// A filter whose QoS changed is subscribed again, which replaces the
+/// existing subscription without unsubscribing.
+#[must_use]
+pub fn diff_subscriptions(
+    current: &[Subscription],
+    new: &[Subscription],
+) -> (Vec<Subscription>, Vec<String>) {
+    let added = new
+        .iter()
+        .filter(|s| !current.contains(s))
+        .cloned()
+        .collect();
+    let removed = current
+        .iter()
+        .filter(|s| !new.iter().any(|n| n.filter == s.filter))
+        .map(|s| s.filter.clone())
+        .collect();
+    (added, removed)
+}
+
+/// Build the subscription for a topic filter, shared within `group` if given.
+///
+/// Filters that already are shared subscriptions are kept as they are.
+#[must_use]
+pub fn shared_subscription(group: Option<&str>, topic: &str) -> String {
+    match group {
+        Some(group) if !topic.starts_with("$share/") => format!("$share/{group}/{topic}"),
+        _ => topic.to_string(),
+    }
+}
+
+/// Get the topic filter of a subscription, without any `$share/<group>/` prefix.
+#[must_use]
+pub fn topic_filter(subscription: &str) -> &str {
+    subscription
+        .strip_prefix("$share/")
+        .and_then(|shared| shared.split_once('/'))
+        .map_or(subscription, |(_, filter)| filter)
+}
+
+fn lock_subscriptions(
+    subscriptions: &Mutex<Vec<Subscription>>,
+) -> std::sync::MutexGuard<'_, Vec<Subscription>> {
+    subscriptions.lock().unwrap_or_else(|e| e.into_inner())
 }
 
 impl MqttHandler {
@@ -28,120 +589,326 @@ impl MqttHandler {
     /// # Errors
     ///
     /// Returns an error if TLS is configured but the TLS transport cannot be created.
-    pub fn new(config: &MqttConfig, topics: Vec<String>) -> Result<Self, SinqttError> {
-        let client_id = format!("sinqtt-{}", std::process::id());
-        let mut options = MqttOptions::new(client_id, &config.host, config.port);
-        options.set_keep_alive(Duration::from_secs(60));
-        options.set_clean_session(true);
+    pub fn new(config: &MqttConfig, subscriptions: Vec<Subscription>) -> Result<Self, SinqttError> {
+        let client_id = config
+            .client_id
+            .clone()
+            .unwrap_or_else(|| match &config.name {
+                Some(name) => format!("sinqtt-{}-{name}", std::process::id()),
+                None => format!("sinqtt-{}", std::process::id()),
+            });
 
-        // Set credentials if provided
-        if let (Some(username), Some(password)) = (&config.username, &config.password) {
-            options.set_credentials(username, password);
-        }
+        let endpoints = config
+            .endpoints()
+            .iter()
+            .map(Self::create_endpoint)
+            .collect::<Result<Vec<_>, _>>()?;
+        let credentials = Credentials::from_config(config)?;
+        let login = credentials.login()?;
+        let (client, connection) = match config.protocol {
+            MqttProtocol::V3 => {
+                let options = Self::options_v3(
+                    config,
+                    client_id.clone(),
+                    endpoints[0].clone(),
+                    login.as_ref(),
+                );
+                let (client, eventloop) = AsyncClient::new(options, REQUEST_CAPACITY);
+                (Client::V3(client), Connection::V3(Box::new(eventloop)))
+            }
+            MqttProtocol::V5 => {
+                let options = Self::options_v5(
+                    config,
+                    client_id.clone(),
+                    endpoints[0].clone(),
+                    login.as_ref(),
+                );
+                let (client, eventloop) = v5::AsyncClient::new(options, REQUEST_CAPACITY);
+                (Client::V5(client), Connection::V5(Box::new(eventloop)))
+            }
+        };
 
-        // TLS configuration
-        if let Some(cafile) = &config.cafile {
-            let transport = Self::create_tls_transport(config)?;
-            options.set_transport(transport);
-            debug!("TLS enabled with CA file: {:?}", cafile);
+        let broker = config.name().to_string();
+        health().register_broker(&broker);
+        for endpoint in &endpoints {
+            metrics().set_mqtt_endpoint_active(&broker, &endpoint.label(), false);
         }
 
-        let (client, eventloop) = AsyncClient::new(options, 100);
-
+        let ack_failed = Arc::new(Notify::new());
         Ok(Self {
+            broker,
+            config: config.clone(),
+            client_id,
+            credentials,
+            renew_at: renewal_time(login.as_ref(), SystemTime::now()),
+            acks: Arc::new(AckQueue::new(client.clone(), ack_failed.clone())),
+            ack_failed,
             client,
-            eventloop,
-            topics,
+            connection,
+            endpoints,
+            current: 0,
+            subscriptions: Arc::new(Mutex::new(subscriptions)),
         })
     }
 
-    /// Create TLS transport configuration.
-    #[cfg(feature = "tls")]
-    fn create_tls_transport(config: &MqttConfig) -> Result<Transport, SinqttError> {
-        use std::fs;
-        use std::sync::Arc;
+    /// Replace the credentials, e.g. with a custom [`PasswordProvider`](super::PasswordProvider).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the credentials cannot produce a password.
+    pub fn with_credentials(mut self, credentials: Credentials) -> Result<Self, SinqttError> {
+        self.credentials = credentials;
+        self.use_endpoint(self.current)?;
+        Ok(self)
+    }
 
-        let cafile = config.cafile.as_ref().ok_or_else(|| {
-            SinqttError::Config(crate::error::ConfigError::Validation(
-                "CA file required for TLS".to_string(),
-            ))
-        })?;
+    /// Build MQTT 3.1.1 connection options for an endpoint.
+    fn options_v3(
+        config: &MqttConfig,
+        client_id: String,
+        endpoint: Endpoint,
+        login: Option<&Login>,
+    ) -> MqttOptions {
+        let mut options = MqttOptions::new(client_id, endpoint.address, endpoint.port);
+        options.set_transport(endpoint.transport);
+        options.set_keep_alive(KEEP_ALIVE);
+        options.set_clean_session(config.clean_session);
+        options.set_manual_acks(config.ack_after_write);
 
-        let ca_cert = fs::read(cafile)?;
+        if let Some(login) = login {
+            options.set_credentials(&login.username, &login.password.value);
+        }
+        #[cfg(feature = "websocket")]
+        if !endpoint.headers.is_empty() {
+            let headers = endpoint.headers;
+            options.set_request_modifier(move |request| add_headers(request, headers.clone()));
+        }
+        options
+    }
 
-        let mut root_cert_store = rustls::RootCertStore::empty();
-        let certs = rustls_pemfile::certs(&mut ca_cert.as_slice())
-            .filter_map(|r| r.ok())
-            .collect::<Vec<_>>();
+    /// Build MQTT 5 connection options for an endpoint.
+    fn options_v5(
+        config: &MqttConfig,
+        client_id: String,
+        endpoint: Endpoint,
+        login: Option<&Login>,
+    ) -> v5::MqttOptions {
+        let mut options = v5::MqttOptions::new(client_id, endpoint.address, endpoint.port);
+        options.set_transport(endpoint.transport);
+        options.set_keep_alive(KEEP_ALIVE);
+        options.set_clean_start(config.clean_session);
+        options.set_session_expiry_interval(session_expiry(config));
+        options.set_manual_acks(config.ack_after_write);
 
-        for cert in certs {
-            root_cert_store.add(cert).map_err(|e| {
-                SinqttError::Config(crate::error::ConfigError::Validation(format!(
-                    "Invalid CA certificate: {}",
-                    e
-                )))
-            })?;
-        }
-
-        let client_config =
-            if let (Some(certfile), Some(keyfile)) = (&config.certfile, &config.keyfile) {
-                let cert_chain = fs::read(certfile)?;
-                let key = fs::read(keyfile)?;
-
-                let certs = rustls_pemfile::certs(&mut cert_chain.as_slice())
-                    .filter_map(|r| r.ok())
-                    .collect();
-                let key = rustls_pemfile::private_key(&mut key.as_slice())
-                    .map_err(|e| {
-                        SinqttError::Config(crate::error::ConfigError::Validation(format!(
-                            "Invalid key file: {}",
-                            e
-                        )))
-                    })?
-                    .ok_or_else(|| {
-                        SinqttError::Config(crate::error::ConfigError::Validation(
-                            "No private key found in key file".to_string(),
-                        ))
-                    })?;
-
-                rustls::ClientConfig::builder()
-                    .with_root_certificates(root_cert_store)
-                    .with_client_auth_cert(certs, key)
-                    .map_err(|e| {
-                        SinqttError::Config(crate::error::ConfigError::Validation(format!(
-                            "TLS client auth error: {}",
-                            e
-                        )))
-                    })?
+        if let Some(login) = login {
+            options.set_credentials(&login.username, &login.password.value);
+        }
+        #[cfg(feature = "websocket")]
+        if !endpoint.headers.is_empty() {
+            let headers = endpoint.headers;
+            options.set_request_modifier(move |request| add_headers(request, headers.clone()));
+        }
+        options
+    }
+
+    /// Connect to the endpoint at `index` with fresh credentials on the
+    /// next connection attempt.
+    fn use_endpoint(&mut self, index: usize) -> Result<(), SinqttError> {
+        let login = self.credentials.login()?;
+        self.renew_at = renewal_time(login.as_ref(), SystemTime::now());
+        self.current = index;
+
+        let endpoint = self.endpoints[index].clone();
+        let client_id = self.client_id.clone();
+        match &mut self.connection {
+            Connection::V3(eventloop) => {
+                eventloop.mqtt_options =
+                    Self::options_v3(&self.config, client_id, endpoint, login.as_ref());
+            }
+            Connection::V5(eventloop) => {
+                eventloop.options =
+                    Self::options_v5(&self.config, client_id, endpoint, login.as_ref());
+            }
+        }
+        Ok(())
+    }
+
+    /// Reconnect with new credentials before the current password expires.
+    fn renew_credentials(&mut self) {
+        info!(
+            "MQTT credentials for '{}' are about to expire, reconnecting",
+            self.broker
+        );
+        if let Err(e) = self.use_endpoint(self.current) {
+            error!(
+                "Failed to renew MQTT credentials for '{}': {}",
+                self.broker, e
+            );
+            self.renew_at = None;
+            return;
+        }
+        self.reset_connection();
+    }
+
+    /// Drop the network connection so that the next poll reconnects.
+    fn reset_connection(&mut self) {
+        self.renew_acks();
+        self.connection.reset();
+    }
+
+    /// Start acknowledging on a new connection, dropping the
+    /// acknowledgements still pending on the previous one.
+    fn renew_acks(&mut self) {
+        self.acks.close();
+        self.acks = Arc::new(AckQueue::new(self.client.clone(), self.ack_failed.clone()));
+    }
+
+    /// Record a failed or lost connection, switch to the next endpoint if
+    /// failing over and wait for the backoff delay of `attempt`.
+    async fn reconnect(&mut self, was_connected: bool, attempt: u32) {
+        self.renew_acks();
+        let endpoint = self.endpoints[self.current].label();
+        health().mqtt_disconnected(&self.broker);
+        metrics().mqtt_connection_failed(&self.broker, &endpoint);
+        metrics().set_mqtt_endpoint_active(&self.broker, &endpoint, false);
+
+        let previous = self.current;
+        let next = next_endpoint(
+            self.config.failover,
+            previous,
+            self.endpoints.len(),
+            was_connected,
+        );
+        // Credentials are renewed on every attempt, e.g. to pick up a rotated password file
+        if let Err(e) = self.use_endpoint(next) {
+            error!(
+                "Failed to load MQTT credentials for '{}': {}",
+                self.broker, e
+            );
+        }
+        if self.current != previous {
+            info!(
+                "MQTT broker '{}' failing over from {} to {}",
+                self.broker,
+                endpoint,
+                self.endpoints[self.current].label()
+            );
+        }
+
+        let reconnect = &self.config.reconnect;
+        let delay = backoff_delay(
+            reconnect.initial_delay_ms,
+            reconnect.max_delay_ms,
+            attempt,
+            fastrand::f64(),
+        );
+        debug!(
+            "Reconnecting to MQTT broker '{}' in {:?}",
+            self.broker, delay
+        );
+        tokio::time::sleep(delay).await;
+    }
+
+    /// Resolve the broker address and transport.
+    fn create_endpoint(config: &MqttConfig) -> Result<Endpoint, SinqttError> {
+        if let Some(url) = websocket_url(config) {
+            return Self::create_websocket_endpoint(config, url);
+        }
+
+        // TLS configuration
+        let transport = if config.uses_tls() {
+            let tls_config = Self::create_tls_config(config)?;
+            debug!("TLS enabled with CA file: {:?}", config.cafile);
+            Transport::tls_with_config(tls_config)
+        } else {
+            Transport::tcp()
+        };
+
+        Ok(Endpoint {
+            address: config.host.clone(),
+            port: config.port,
+            transport,
+            #[cfg(feature = "websocket")]
+            headers: http::HeaderMap::new(),
+        })
+    }
+
+    /// Create a WebSocket endpoint, secured with TLS for `wss://` URLs.
+    #[cfg(feature = "websocket")]
+    fn create_websocket_endpoint(
+        config: &MqttConfig,
+        url: String,
+    ) -> Result<Endpoint, SinqttError> {
+        let transport = if url.starts_with("wss://") {
+            if tls_options(config).is_configured() {
+                let tls_config = Self::create_tls_config(config)?;
+                debug!("TLS enabled with CA file: {:?}", config.cafile);
+                Transport::wss_with_config(tls_config)
             } else {
-                rustls::ClientConfig::builder()
-                    .with_root_certificates(root_cert_store)
-                    .with_no_client_auth()
-            };
+                Transport::wss_with_default_config()
+            }
+        } else {
+            Transport::ws()
+        };
+
+        debug!("Connecting over WebSockets to {}", url);
+        Ok(Endpoint {
+            address: url,
+            port: config.port,
+            transport,
+            headers: websocket_headers(&config.headers)?,
+        })
+    }
 
-        Ok(Transport::tls_with_config(
-            rumqttc::TlsConfiguration::Rustls(Arc::new(client_config)),
-        ))
+    /// Create WebSocket endpoint (no-op when WebSocket feature is disabled).
+    #[cfg(not(feature = "websocket"))]
+    fn create_websocket_endpoint(
+        _config: &MqttConfig,
+        _url: String,
+    ) -> Result<Endpoint, SinqttError> {
+        Err(SinqttError::Config(crate::error::ConfigError::Validation(
+            "WebSocket support not compiled in. Enable the 'websocket' feature.".to_string(),
+        )))
     }
 
-    /// Create TLS transport (no-op when TLS feature is disabled).
+    /// Create TLS client configuration.
+    #[cfg(feature = "tls")]
+    fn create_tls_config(config: &MqttConfig) -> Result<rumqttc::TlsConfiguration, SinqttError> {
+        let client_config = super::tls::client_config(tls_options(config))?;
+        Ok(rumqttc::TlsConfiguration::Rustls(std::sync::Arc::new(
+            client_config,
+        )))
+    }
+
+    /// Create TLS configuration (no-op when TLS feature is disabled).
     #[cfg(not(feature = "tls"))]
-    fn create_tls_transport(_config: &MqttConfig) -> Result<Transport, SinqttError> {
+    fn create_tls_config(_config: &MqttConfig) -> Result<rumqttc::TlsConfiguration, SinqttError> {
         Err(SinqttError::Config(crate::error::ConfigError::Validation(
             "TLS support not compiled in. Enable the 'tls' feature.".to_string(),
         )))
     }
 
-    /// Get a reference to the MQTT client.
-    pub fn client(&self) -> &AsyncClient {
-        &self.client
+    /// Get a handle for changing the subscribed topics while running.
+    #[must_use]
+    pub fn subscriptions(&self) -> Subscriptions {
+        Subscriptions {
+            broker: self.broker.clone(),
+            client: self.client.clone(),
+            subscriptions: self.subscriptions.clone(),
+        }
     }
 
     /// Subscribe to configured topics.
-    async fn subscribe_topics(&self) -> Result<(), SinqttError> {
-        for topic in &self.topics {
-            info!("Subscribing to topic: {}", topic);
-            self.client.subscribe(topic, QoS::AtLeastOnce).await?;
+    async fn subscribe_topics(
+        client: &Client,
+        subscriptions: &[Subscription],
+    ) -> Result<(), SinqttError> {
+        for subscription in subscriptions {
+            info!(
+                "Subscribing to topic: {} (QoS {})",
+                subscription.filter, subscription.qos
+            );
+            client.subscribe(subscription).await?;
         }
         Ok(())
     }
@@ -159,36 +926,94 @@ impl MqttHandler {
     /// Returns an error if the MQTT connection fails and cannot be recovered.
     pub async fn run(mut self, tx: mpsc::Sender<MqttMessage>) -> Result<(), SinqttError> {
         info!(
-            "Starting MQTT event loop, {} topics configured",
-            self.topics.len()
+            "Starting MQTT event loop for broker '{}', {} topics configured",
+            self.broker,
+            lock_subscriptions(&self.subscriptions).len()
         );
 
+        // Consecutive failed connection attempts, for the backoff delay
+        let mut attempt: u32 = 0;
+        let mut connected = false;
+
         loop {
-            match self.eventloop.poll().await {
-                Ok(Event::Incoming(Packet::ConnAck(connack))) => {
-                    if connack.code == rumqttc::ConnectReturnCode::Success {
-                        info!("Connected to MQTT broker");
-                        if let Err(e) = self.subscribe_topics().await {
-                            error!("Failed to subscribe to topics: {}", e);
-                        }
+            let renewal = self.renew_at.map(|at| {
+                at.duration_since(SystemTime::now())
+                    .unwrap_or(Duration::ZERO)
+            });
+            let event = tokio::select! {
+                event = self.connection.poll() => event,
+                () = sleep_or_pending(renewal) => {
+                    self.renew_credentials();
+                    continue;
+                }
+                () = self.ack_failed.notified() => {
+                    warn!(
+                        "Message from MQTT broker '{}' was not written, reconnecting for redelivery",
+                        self.broker
+                    );
+                    self.reset_connection();
+                    continue;
+                }
+            };
+
+            match event {
+                BrokerEvent::Connected { session_present } => {
+                    let endpoint = self.endpoints[self.current].label();
+                    if session_present {
+                        info!(
+                            "Connected to MQTT broker '{}' at {}, resuming session",
+                            self.broker, endpoint
+                        );
                     } else {
-                        error!("MQTT connection failed: {:?}", connack.code);
+                        info!("Connected to MQTT broker '{}' at {}", self.broker, endpoint);
+                    }
+                    metrics().set_mqtt_endpoint_active(&self.broker, &endpoint, true);
+                    attempt = 0;
+                    connected = true;
+                    let subscriptions = lock_subscriptions(&self.subscriptions).clone();
+                    health().mqtt_connected(&self.broker, subscriptions.len());
+                    if let Err(e) = Self::subscribe_topics(&self.client, &subscriptions).await {
+                        error!("Failed to subscribe to topics: {}", e);
                     }
                 }
-                Ok(Event::Incoming(Packet::SubAck(suback))) => {
-                    debug!("Subscription acknowledged: {:?}", suback);
+                BrokerEvent::ConnectionRefused(reason) => {
+                    error!(
+                        "MQTT connection to '{}' at {} failed: {}",
+                        self.broker,
+                        self.endpoints[self.current].label(),
+                        reason
+                    );
+                    self.reconnect(std::mem::take(&mut connected), attempt)
+                        .await;
+                    attempt = attempt.saturating_add(1);
                 }
-                Ok(Event::Incoming(Packet::Publish(publish))) => {
+                BrokerEvent::SubAck { granted, rejected } => {
+                    debug!("Subscription acknowledged: {} granted", granted);
+                    if !rejected.is_empty() {
+                        error!(
+                            "Broker rejected {} subscriptions: {}",
+                            rejected.len(),
+                            rejected.join(", ")
+                        );
+                    }
+                    health().subscription_acked(&self.broker, granted, rejected.len());
+                }
+                BrokerEvent::Publish(publish) => {
                     debug!(
                         "Received message on topic: {} ({} bytes)",
-                        publish.topic,
-                        publish.payload.len()
+                        publish.topic(),
+                        publish.payload().len()
                     );
 
+                    let ack = (self.config.ack_after_write && publish.qos() > 0)
+                        .then(|| MessageAck::new(self.acks.clone(), publish.clone()));
                     let msg = MqttMessage {
-                        topic: publish.topic,
-                        payload: publish.payload.to_vec(),
-                        qos: publish.qos as u8,
+                        broker: self.broker.clone(),
+                        topic: publish.topic(),
+                        payload: publish.payload().to_vec(),
+                        qos: publish.qos(),
+                        properties: publish.properties(),
+                        ack,
                     };
 
                     if tx.send(msg).await.is_err() {
@@ -196,23 +1021,26 @@ impl MqttHandler {
                         break;
                     }
                 }
-                Ok(Event::Incoming(Packet::PingResp)) => {
-                    debug!("Ping response received");
-                }
-                Ok(Event::Incoming(Packet::Disconnect)) => {
-                    warn!("Disconnected from MQTT broker");
-                }
-                Ok(Event::Outgoing(_)) => {
-                    // Outgoing events are internal, ignore
+                BrokerEvent::Disconnected(reason) => {
+                    warn!(
+                        "Disconnected from MQTT broker '{}': {}",
+                        self.broker, reason
+                    );
+                    self.reconnect(std::mem::take(&mut connected), attempt)
+                        .await;
+                    attempt = attempt.saturating_add(1);
                 }
-                Ok(event) => {
-                    debug!("MQTT event: {:?}", event);
+                BrokerEvent::Error(e) => {
+                    error!("MQTT connection error on '{}': {}", self.broker, e);
+                    // rumqttc reconnects on the next poll
+                    self.reconnect(std::mem::take(&mut connected), attempt)
+                        .await;
+                    attempt = attempt.saturating_add(1);
                 }
-                Err(e) => {
-                    error!("MQTT connection error: {}", e);
-                    // rumqttc will automatically try to reconnect
-                    tokio::time::sleep(Duration::from_secs(1)).await;
+                BrokerEvent::Other(event) => {
+                    debug!("MQTT event: {}", event);
                 }
+                BrokerEvent::Ignored => {}
             }
         }
 
@@ -225,58 +1053,379 @@ impl MqttHandler {
     ///
     /// Returns an error if the disconnect request fails.
     pub async fn disconnect(&self) -> Result<(), SinqttError> {
-        info!("Disconnecting from MQTT broker");
+        info!("Disconnecting from MQTT broker '{}'", self.broker);
         self.client.disconnect().await?;
         Ok(())
     }
 }
 
+/// Broker address and transport to connect with.
+#[derive(Clone)]
+struct Endpoint {
+    /// Hostname, or the URL of a WebSocket endpoint.
+    address: String,
+    port: u16,
+    transport: Transport,
+    #[cfg(feature = "websocket")]
+    headers: http::HeaderMap,
+}
+
+impl Endpoint {
+    /// Address of the endpoint for logs and metrics.
+    fn label(&self) -> String {
+        if self.address.contains("://") {
+            self.address.clone()
+        } else {
+            format!("{}:{}", self.address, self.port)
+        }
+    }
+}
+
+/// Index of the endpoint to try after the one at `current` failed.
+///
+/// With the ordered strategy a lost connection starts over at the first
+/// endpoint, so the primary broker is used again once it is back.
+#[must_use]
+fn next_endpoint(
+    strategy: FailoverStrategy,
+    current: usize,
+    count: usize,
+    was_connected: bool,
+) -> usize {
+    match strategy {
+        FailoverStrategy::Ordered if was_connected => 0,
+        _ => (current + 1) % count.max(1),
+    }
+}
+
+/// Get the TLS settings of a broker connection.
+#[cfg(any(feature = "tls", feature = "websocket"))]
+fn tls_options(config: &MqttConfig) -> TlsOptions<'_> {
+    TlsOptions {
+        cafile: config.cafile.as_deref(),
+        certfile: config.certfile.as_deref(),
+        keyfile: config.keyfile.as_deref(),
+        insecure_skip_verify: false,
+        verify_name: config.tls_verify_name.as_deref(),
+        alpn: &config.alpn,
+    }
+}
+
+/// Get the WebSocket URL of the broker, if it is reached over WebSockets.
+///
+/// A `ws://` or `wss://` host is used as is; otherwise the URL is built from
+/// the host, port and path, using `wss` when TLS is enabled.
+#[must_use]
+pub fn websocket_url(config: &MqttConfig) -> Option<String> {
+    if !config.is_websocket() {
+        return None;
+    }
+    if config.host.contains("://") {
+        return Some(config.host.clone());
+    }
+
+    let scheme = if config.uses_tls() { "wss" } else { "ws" };
+    let path = config.path.as_deref().unwrap_or(DEFAULT_WEBSOCKET_PATH);
+    Some(format!("{scheme}://{}:{}{path}", config.host, config.port))
+}
+
+/// Parse the configured WebSocket handshake headers.
+#[cfg(feature = "websocket")]
+fn websocket_headers(
+    headers: &std::collections::HashMap<String, String>,
+) -> Result<http::HeaderMap, SinqttError> {
+    let invalid =
+        |message: String| SinqttError::Config(crate::error::ConfigError::Validation(message));
+
+    let mut map = http::HeaderMap::new();
+    for (name, value) in headers {
+        let header_name = http::HeaderName::from_bytes(name.as_bytes())
+            .map_err(|e| invalid(format!("Invalid WebSocket header name '{name}': {e}")))?;
+        let header_value = http::HeaderValue::from_str(value)
+            .map_err(|e| invalid(format!("Invalid WebSocket header value for '{name}': {e}")))?;
+        map.insert(header_name, header_value);
+    }
+    Ok(map)
+}
+
+/// Add the configured headers to the WebSocket handshake request.
+#[cfg(feature = "websocket")]
+async fn add_headers(
+    mut request: http::Request<()>,
+    headers: http::HeaderMap,
+) -> http::Request<()> {
+    request.headers_mut().extend(headers);
+    request
+}
+
+/// Time at which to renew credentials whose password expires.
+///
+/// The connection is renewed a tenth of the password lifetime before it
+/// expires, but at most [`RENEWAL_MARGIN`] before.
+fn renewal_time(login: Option<&Login>, now: SystemTime) -> Option<SystemTime> {
+    let expires_at = login?.password.expires_at?;
+    let lifetime = expires_at.duration_since(now).unwrap_or(Duration::ZERO);
+    Some(expires_at - (lifetime / 10).min(RENEWAL_MARGIN))
+}
+
+/// Sleep for `duration`, or forever if there is none.
+async fn sleep_or_pending(duration: Option<Duration>) {
+    match duration {
+        Some(duration) => tokio::time::sleep(duration).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Session expiry interval to request with MQTT v5.
+///
+/// Without an explicit `session_expiry_secs`, a persistent session
+/// (`clean_session: false`) never expires, matching MQTT 3.1.1 semantics.
+fn session_expiry(config: &MqttConfig) -> Option<u32> {
+    config
+        .session_expiry_secs
+        .or((!config.clean_session).then_some(u32::MAX))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::bridge::{Password, PasswordProvider};
+    use crate::config::MqttTransport;
 
     fn make_config(host: &str, port: u16) -> MqttConfig {
         MqttConfig {
+            name: None,
             host: host.to_string(),
             port,
+            failover_hosts: Vec::new(),
+            failover: FailoverStrategy::Ordered,
+            reconnect: crate::config::ReconnectConfig::default(),
+            transport: MqttTransport::Tcp,
+            path: None,
+            headers: std::collections::HashMap::new(),
+            protocol: MqttProtocol::V3,
             username: None,
             password: None,
+            password_file: None,
+            jwt: None,
+            tls: false,
             cafile: None,
             certfile: None,
             keyfile: None,
+            tls_verify_name: None,
+            alpn: Vec::new(),
+            client_id: None,
+            clean_session: true,
+            session_expiry_secs: None,
+            ack_after_write: false,
+            share_group: None,
         }
     }
 
     #[test]
     fn test_mqtt_handler_creation() {
         let config = make_config("localhost", 1883);
-        let topics = vec!["test/#".to_string()];
-        let handler = MqttHandler::new(&config, topics);
+        let subscriptions = vec![Subscription::new("test/#", 1)];
+        let handler = MqttHandler::new(&config, subscriptions);
         assert!(handler.is_ok());
     }
 
     #[test]
     fn test_mqtt_handler_with_auth() {
         let config = MqttConfig {
-            host: "localhost".to_string(),
-            port: 1883,
             username: Some("user".to_string()),
             password: Some("pass".to_string()),
-            cafile: None,
-            certfile: None,
-            keyfile: None,
+            ..make_config("localhost", 1883)
         };
-        let topics = vec!["test/+/temp".to_string()];
-        let handler = MqttHandler::new(&config, topics);
+        let subscriptions = vec![Subscription::new("test/+/temp", 1)];
+        let handler = MqttHandler::new(&config, subscriptions);
         assert!(handler.is_ok());
     }
 
+    #[test]
+    fn test_mqtt_handler_username_only() {
+        let config = MqttConfig {
+            username: Some("device-1".to_string()),
+            ..make_config("localhost", 1883)
+        };
+        let handler = MqttHandler::new(&config, vec![Subscription::new("test/#", 1)]).unwrap();
+
+        let Connection::V3(eventloop) = &handler.connection else {
+            panic!("expected an MQTT 3.1.1 connection");
+        };
+        let login = eventloop.mqtt_options.credentials().unwrap();
+        assert_eq!(login.username, "device-1");
+        assert!(login.password.is_empty());
+        assert_eq!(handler.renew_at, None);
+    }
+
+    #[test]
+    fn test_mqtt_handler_custom_credentials() {
+        struct Token;
+        impl PasswordProvider for Token {
+            fn password(&self) -> Result<Password, SinqttError> {
+                Ok(Password {
+                    value: "token".to_string(),
+                    expires_at: Some(SystemTime::now() + Duration::from_secs(3600)),
+                })
+            }
+        }
+
+        let config = make_config("localhost", 1883);
+        let handler = MqttHandler::new(&config, vec![Subscription::new("test/#", 1)])
+            .unwrap()
+            .with_credentials(Credentials::new(
+                Some("unused".to_string()),
+                Some(Box::new(Token)),
+            ))
+            .unwrap();
+
+        let Connection::V3(eventloop) = &handler.connection else {
+            panic!("expected an MQTT 3.1.1 connection");
+        };
+        assert_eq!(
+            eventloop.mqtt_options.credentials().unwrap().password,
+            "token"
+        );
+        assert!(handler.renew_at.is_some());
+    }
+
+    #[test]
+    fn test_renewal_time() {
+        let now = SystemTime::now();
+        let login = |lifetime: u64| Login {
+            username: String::new(),
+            password: Password {
+                value: "token".to_string(),
+                expires_at: Some(now + Duration::from_secs(lifetime)),
+            },
+        };
+
+        assert_eq!(
+            renewal_time(Some(&login(3600)), now),
+            Some(now + Duration::from_secs(3540))
+        );
+        assert_eq!(
+            renewal_time(Some(&login(100)), now),
+            Some(now + Duration::from_secs(90))
+        );
+        assert_eq!(renewal_time(None, now), None);
+    }
+
+    #[test]
+    fn test_mqtt_handler_v5() {
+        let config = MqttConfig {
+            protocol: MqttProtocol::V5,
+            client_id: Some("sinqtt-test".to_string()),
+            clean_session: false,
+            ..make_config("localhost", 1883)
+        };
+        let handler = MqttHandler::new(&config, vec![Subscription::new("test/#", 1)]).unwrap();
+        assert!(matches!(handler.client, Client::V5(_)));
+    }
+
+    #[test]
+    fn test_mqtt_handler_failover_endpoints() {
+        let config = MqttConfig {
+            failover_hosts: vec!["node2".to_string(), "node3:1884".to_string()],
+            ..make_config("node1", 1883)
+        };
+        let mut handler = MqttHandler::new(&config, vec![Subscription::new("test/#", 1)]).unwrap();
+
+        let labels: Vec<String> = handler.endpoints.iter().map(Endpoint::label).collect();
+        assert_eq!(labels, vec!["node1:1883", "node2:1883", "node3:1884"]);
+
+        handler.use_endpoint(2).unwrap();
+        let Connection::V3(eventloop) = &handler.connection else {
+            panic!("expected an MQTT 3.1.1 connection");
+        };
+        assert_eq!(
+            eventloop.mqtt_options.broker_address(),
+            ("node3".to_string(), 1884)
+        );
+    }
+
+    #[test]
+    fn test_next_endpoint() {
+        use FailoverStrategy::{Ordered, RoundRobin};
+
+        // Failed connection attempts move on to the next endpoint
+        assert_eq!(next_endpoint(Ordered, 0, 3, false), 1);
+        assert_eq!(next_endpoint(Ordered, 2, 3, false), 0);
+        assert_eq!(next_endpoint(RoundRobin, 1, 3, false), 2);
+
+        // A lost connection returns to the primary only when ordered
+        assert_eq!(next_endpoint(Ordered, 2, 3, true), 0);
+        assert_eq!(next_endpoint(RoundRobin, 1, 3, true), 2);
+
+        // A single endpoint is always retried
+        assert_eq!(next_endpoint(Ordered, 0, 1, false), 0);
+        assert_eq!(next_endpoint(RoundRobin, 0, 1, true), 0);
+    }
+
+    #[test]
+    fn test_session_expiry() {
+        let mut config = make_config("localhost", 1883);
+        assert_eq!(session_expiry(&config), None);
+
+        config.clean_session = false;
+        assert_eq!(session_expiry(&config), Some(u32::MAX));
+
+        config.session_expiry_secs = Some(3600);
+        assert_eq!(session_expiry(&config), Some(3600));
+    }
+
+    #[test]
+    fn test_properties_object() {
+        let properties = PublishProperties {
+            payload_format_indicator: None,
+            message_expiry_interval: Some(60),
+            topic_alias: None,
+            response_topic: Some("reply/sensor".to_string()),
+            correlation_data: None,
+            user_properties: vec![
+                ("device".to_string(), "a".to_string()),
+                ("site".to_string(), "lab".to_string()),
+            ],
+            subscription_identifiers: Vec::new(),
+            content_type: Some("application/json".to_string()),
+        };
+
+        assert_eq!(
+            properties_object(&properties),
+            json!({
+                "content_type": "application/json",
+                "response_topic": "reply/sensor",
+                "message_expiry_interval": 60,
+                "user_properties": {"device": "a", "site": "lab"},
+            })
+        );
+    }
+
+    #[test]
+    fn test_disconnect_reason() {
+        assert_eq!(
+            disconnect_reason(DisconnectReasonCode::SessionTakenOver, None),
+            "SessionTakenOver (0x8e)"
+        );
+        assert_eq!(
+            disconnect_reason(
+                DisconnectReasonCode::ServerShuttingDown,
+                Some("maintenance".to_string())
+            ),
+            "ServerShuttingDown: maintenance (0x8b)"
+        );
+    }
+
     #[test]
     fn test_mqtt_message_struct() {
         let msg = MqttMessage {
+            broker: "default".to_string(),
             topic: "test/sensor/temp".to_string(),
             payload: b"25.5".to_vec(),
             qos: 1,
+            properties: None,
+            ack: None,
         };
 
         assert_eq!(msg.topic, "test/sensor/temp");
@@ -287,13 +1436,13 @@ mod tests {
     #[test]
     fn test_mqtt_handler_multiple_topics() {
         let config = make_config("localhost", 1883);
-        let topics = vec![
-            "node/+/temperature".to_string(),
-            "node/+/humidity".to_string(),
-            "stat/#".to_string(),
+        let subscriptions = vec![
+            Subscription::new("node/+/temperature", 1),
+            Subscription::new("node/+/humidity", 1),
+            Subscription::new("stat/#", 2),
         ];
-        let handler = MqttHandler::new(&config, topics.clone()).unwrap();
-        assert_eq!(handler.topics.len(), 3);
+        let handler = MqttHandler::new(&config, subscriptions.clone()).unwrap();
+        assert_eq!(handler.subscriptions().current(), subscriptions);
     }
 
     #[cfg(not(feature = "tls"))]
@@ -301,16 +1450,249 @@ mod tests {
     fn test_tls_without_feature_returns_error() {
         use std::path::PathBuf;
         let config = MqttConfig {
-            host: "localhost".to_string(),
-            port: 8883,
-            username: None,
-            password: None,
             cafile: Some(PathBuf::from("/path/to/ca.crt")),
-            certfile: None,
-            keyfile: None,
+            ..make_config("localhost", 8883)
         };
-        let topics = vec!["test/#".to_string()];
-        let result = MqttHandler::new(&config, topics);
+        let subscriptions = vec![Subscription::new("test/#", 1)];
+        let result = MqttHandler::new(&config, subscriptions);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_websocket_url() {
+        let config = make_config("broker", 1883);
+        assert_eq!(websocket_url(&config), None);
+
+        let config = MqttConfig {
+            transport: MqttTransport::Websocket,
+            ..make_config("broker", 8080)
+        };
+        assert_eq!(
+            websocket_url(&config).as_deref(),
+            Some("ws://broker:8080/mqtt")
+        );
+
+        let config = MqttConfig {
+            transport: MqttTransport::Websocket,
+            path: Some("/ws".to_string()),
+            cafile: Some("/path/to/ca.crt".into()),
+            ..make_config("broker", 443)
+        };
+        assert_eq!(
+            websocket_url(&config).as_deref(),
+            Some("wss://broker:443/ws")
+        );
+
+        let config = make_config("wss://proxy.example.com/mqtt", 1883);
+        assert_eq!(
+            websocket_url(&config).as_deref(),
+            Some("wss://proxy.example.com/mqtt")
+        );
+    }
+
+    #[cfg(feature = "websocket")]
+    #[test]
+    fn test_mqtt_handler_websocket() {
+        let config = MqttConfig {
+            headers: [("Authorization".to_string(), "Bearer token".to_string())].into(),
+            ..make_config("ws://localhost:8080/mqtt", 1883)
+        };
+        assert!(MqttHandler::new(&config, vec![Subscription::new("test/#", 1)]).is_ok());
+
+        let config = MqttConfig {
+            headers: [("Bad Header".to_string(), "value".to_string())].into(),
+            ..make_config("ws://localhost:8080/mqtt", 1883)
+        };
+        assert!(MqttHandler::new(&config, vec![Subscription::new("test/#", 1)]).is_err());
+    }
+
+    #[cfg(not(feature = "websocket"))]
+    #[test]
+    fn test_websocket_without_feature_returns_error() {
+        let config = make_config("ws://localhost:8080/mqtt", 1883);
+        let result = MqttHandler::new(&config, vec![Subscription::new("test/#", 1)]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_shared_subscription() {
+        assert_eq!(shared_subscription(None, "a/#"), "a/#");
+        assert_eq!(
+            shared_subscription(Some("sinqtt"), "a/#"),
+            "$share/sinqtt/a/#"
+        );
+        assert_eq!(
+            shared_subscription(Some("sinqtt"), "$share/other/a/#"),
+            "$share/other/a/#"
+        );
+    }
+
+    #[test]
+    fn test_topic_filter() {
+        assert_eq!(topic_filter("a/+/b"), "a/+/b");
+        assert_eq!(topic_filter("$share/group/a/+/b"), "a/+/b");
+        assert_eq!(topic_filter("$share/group"), "$share/group");
+    }
+
+    #[test]
+    fn test_diff_subscriptions() {
+        let current = vec![Subscription::new("a/#", 1), Subscription::new("b/#", 1)];
+        let new = vec![Subscription::new("b/#", 1), Subscription::new("c/+", 1)];
+
+        let (added, removed) = diff_subscriptions(&current, &new);
+        assert_eq!(added, vec![Subscription::new("c/+", 1)]);
+        assert_eq!(removed, vec!["a/#".to_string()]);
+
+        let (added, removed) = diff_subscriptions(&current, &current);
+        assert!(added.is_empty());
+        assert!(removed.is_empty());
+    }
+
+    #[test]
+    fn test_diff_subscriptions_qos_change() {
+        let current = vec![Subscription::new("a/#", 0)];
+        let new = vec![Subscription::new("a/#", 2)];
+
+        let (added, removed) = diff_subscriptions(&current, &new);
+        assert_eq!(added, new);
+        assert!(removed.is_empty());
+    }
+
+    /// Broker side of MQTT 3.1.1 connections, enough to test acknowledgements.
+    mod broker {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::{TcpListener, TcpStream};
+
+        pub const PUBACK: u8 = 0x40;
+
+        /// Read a packet, returning its first header byte and its body.
+        pub async fn read_packet(stream: &mut TcpStream) -> std::io::Result<(u8, Vec<u8>)> {
+            let header = tokio::time::timeout(std::time::Duration::from_secs(5), stream.read_u8())
+                .await
+                .map_err(|_| std::io::Error::from(std::io::ErrorKind::TimedOut))??;
+            let (mut length, mut shift) = (0usize, 0);
+            loop {
+                let byte = stream.read_u8().await?;
+                length |= usize::from(byte & 0x7f) << shift;
+                if byte & 0x80 == 0 {
+                    break;
+                }
+                shift += 7;
+            }
+            let mut body = vec![0; length];
+            stream.read_exact(&mut body).await?;
+            Ok((header, body))
+        }
+
+        /// Accept a client, acknowledging its connection and subscription.
+        pub async fn accept(listener: &TcpListener) -> TcpStream {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let (connect, _) = read_packet(&mut stream).await.unwrap();
+            assert_eq!(connect >> 4, 1);
+            stream.write_all(&[0x20, 0x02, 0x00, 0x00]).await.unwrap();
+            let (subscribe, body) = read_packet(&mut stream).await.unwrap();
+            assert_eq!(subscribe >> 4, 8);
+            stream
+                .write_all(&[0x90, 0x03, body[0], body[1], 0x01])
+                .await
+                .unwrap();
+            stream
+        }
+
+        /// Send a QoS 1 publish, flagged as redelivered if `dup` is set.
+        pub async fn publish(stream: &mut TcpStream, pkid: u16, dup: bool) {
+            let topic = b"test/a";
+            let mut body = Vec::new();
+            body.extend_from_slice(&(topic.len() as u16).to_be_bytes());
+            body.extend_from_slice(topic);
+            body.extend_from_slice(&pkid.to_be_bytes());
+            body.extend_from_slice(b"1");
+            let header = if dup { 0x3a } else { 0x32 };
+            let mut packet = vec![header, body.len() as u8];
+            packet.extend(body);
+            stream.write_all(&packet).await.unwrap();
+        }
+
+        /// Read the next acknowledgement, returning its packet identifier.
+        pub async fn puback(stream: &mut TcpStream) -> u16 {
+            let (header, body) = read_packet(stream).await.unwrap();
+            assert_eq!(header, PUBACK);
+            u16::from_be_bytes([body[0], body[1]])
+        }
+    }
+
+    async fn start_handler(
+        listener: &tokio::net::TcpListener,
+    ) -> (mpsc::Receiver<MqttMessage>, tokio::task::JoinHandle<()>) {
+        let port = listener.local_addr().unwrap().port();
+        let config = MqttConfig {
+            client_id: Some("sinqtt-test".to_string()),
+            clean_session: false,
+            ack_after_write: true,
+            ..make_config("127.0.0.1", port)
+        };
+        let handler = MqttHandler::new(&config, vec![Subscription::new("test/#", 1)]).unwrap();
+        let (tx, rx) = mpsc::channel(10);
+        let task = tokio::spawn(async move {
+            let _ = handler.run(tx).await;
+        });
+        (rx, task)
+    }
+
+    #[tokio::test]
+    async fn test_acks_follow_receive_order() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (mut rx, task) = start_handler(&listener).await;
+        let mut stream = broker::accept(&listener).await;
+
+        broker::publish(&mut stream, 1, false).await;
+        broker::publish(&mut stream, 2, false).await;
+        let first = rx.recv().await.unwrap();
+        let second = rx.recv().await.unwrap();
+
+        // Settling the second message first holds its acknowledgement back
+        second.ack.as_ref().unwrap().complete();
+        drop(second);
+        first.ack.as_ref().unwrap().complete();
+        drop(first);
+
+        assert_eq!(broker::puback(&mut stream).await, 1);
+        assert_eq!(broker::puback(&mut stream).await, 2);
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn test_failed_write_leads_to_redelivery() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (mut rx, task) = start_handler(&listener).await;
+        let mut stream = broker::accept(&listener).await;
+
+        broker::publish(&mut stream, 1, false).await;
+        let mut msg = rx.recv().await.unwrap();
+        let ack = msg.ack.take().unwrap();
+        let batch = crate::bridge::PendingBatch {
+            lines: vec!["m value=1".to_string()],
+            acks: vec![ack.clone()],
+        };
+        ack.complete();
+        drop(ack);
+        batch.settle(false);
+
+        // The connection is closed without acknowledging the message
+        let closed = broker::read_packet(&mut stream).await.unwrap_err();
+        assert!(matches!(
+            closed.kind(),
+            std::io::ErrorKind::UnexpectedEof | std::io::ErrorKind::ConnectionReset
+        ));
+
+        // The broker redelivers it on the new connection, where it is acknowledged
+        let mut stream = broker::accept(&listener).await;
+        broker::publish(&mut stream, 1, true).await;
+        let msg = rx.recv().await.unwrap();
+        assert_eq!(msg.payload, b"1");
+        msg.ack.as_ref().unwrap().complete();
+        drop(msg);
+        assert_eq!(broker::puback(&mut stream).await, 1);
+        task.abort();
+    }
 }
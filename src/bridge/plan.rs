@@ -0,0 +1,775 @@
+//! Precompiled point pipelines.
+//!
+//! A [`PointPlan`] holds the JSONPaths, expressions and cron schedule of a
+//! point in parsed form, so that messages are evaluated without parsing any
+//! specification again. Evaluation borrows the message through a
+//! [`MessageScope`] instead of building a JSON object from it for each value.
+
+use super::flatten::flatten_value;
+use super::processor::{ParsedMessage, convert_type, message_object};
+use super::timestamp::{TimestampFormat, TimestampZone, parse_timestamp};
+use crate::config::{FieldSpec, FlattenConfig, PointConfig, normalize_cron_schedule};
+use crate::error::{ConfigError, ExpressionError};
+use crate::expr::Expression;
+use cron::Schedule;
+use jsonpath_rust::parser::model::{JpQuery, Segment, Selector};
+use jsonpath_rust::parser::parse_json_path;
+use jsonpath_rust::query::js_path_process;
+use serde_json::Value;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use tracing::warn;
+
+/// A message being evaluated, optionally narrowed to a `foreach` element.
+///
+/// The topic array and the full message object are only built when a
+/// JSONPath needs them.
+pub struct MessageScope<'a> {
+    msg: &'a ParsedMessage,
+    item: Option<(&'a Value, usize)>,
+    topic: OnceLock<Value>,
+    object: OnceLock<Value>,
+}
+
+impl<'a> MessageScope<'a> {
+    /// Create a scope for a parsed message.
+    #[must_use]
+    pub fn new(msg: &'a ParsedMessage) -> Self {
+        Self::with_item(msg, None)
+    }
+
+    fn with_item(msg: &'a ParsedMessage, item: Option<(&'a Value, usize)>) -> Self {
+        Self {
+            msg,
+            item,
+            topic: OnceLock::new(),
+            object: OnceLock::new(),
+        }
+    }
+
+    /// The current `foreach` element, if any.
+    #[must_use]
+    pub fn item(&self) -> Option<&'a Value> {
+        self.item.map(|(item, _)| item)
+    }
+
+    fn topic(&self) -> &Value {
+        self.topic
+            .get_or_init(|| Value::from(self.msg.topic.clone()))
+    }
+
+    fn object(&self) -> &Value {
+        self.object.get_or_init(|| {
+            let mut object = message_object(self.msg);
+            if let Some((item, index)) = self.item {
+                object["item"] = item.clone();
+                object["index"] = Value::from(index);
+            }
+            object
+        })
+    }
+}
+
+/// Member of the message object a JSONPath starts from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Root {
+    Payload,
+    Item,
+    Properties,
+    Topic,
+    /// The full message object, for paths not starting with a single member.
+    Message,
+}
+
+/// A JSONPath compiled for querying messages.
+#[derive(Debug, Clone)]
+pub struct CompiledPath {
+    root: Root,
+    /// Segments after the root member.
+    query: JpQuery,
+}
+
+impl CompiledPath {
+    /// Compile a JSONPath.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ConfigError::InvalidJsonPath` if the path cannot be parsed.
+    pub fn compile(path: &str) -> Result<Self, ConfigError> {
+        let query = parse_json_path(path)
+            .map_err(|e| ConfigError::InvalidJsonPath(format!("{}: {}", path, e)))?;
+
+        let root = match query.segments.first() {
+            Some(Segment::Selector(Selector::Name(name))) => match name.as_str() {
+                "payload" => Root::Payload,
+                "item" => Root::Item,
+                "properties" => Root::Properties,
+                "topic" => Root::Topic,
+                _ => Root::Message,
+            },
+            _ => Root::Message,
+        };
+        if root == Root::Message {
+            return Ok(Self { root, query });
+        }
+
+        // Filters referring to the root (`$`) need the full message object
+        let rest = JpQuery::new(query.segments[1..].to_vec());
+        if rest.to_string()[1..].contains('$') {
+            return Ok(Self {
+                root: Root::Message,
+                query,
+            });
+        }
+        Ok(Self { root, query: rest })
+    }
+
+    /// Query all values selected by the path.
+    #[must_use]
+    pub fn query<'s>(&self, scope: &'s MessageScope<'_>) -> Vec<&'s Value> {
+        let root = match self.root {
+            Root::Payload => Some(&scope.msg.payload),
+            Root::Item => scope.item.map(|(item, _)| item),
+            Root::Properties => scope.msg.properties.as_ref(),
+            Root::Topic => Some(scope.topic()),
+            Root::Message => Some(scope.object()),
+        };
+        root.and_then(|root| js_path_process(&self.query, root).ok())
+            .map(|results| results.into_iter().map(|r| r.val()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Query the first value selected by the path.
+    #[must_use]
+    pub fn first<'s>(&self, scope: &'s MessageScope<'_>) -> Option<&'s Value> {
+        self.query(scope).into_iter().next()
+    }
+}
+
+/// An expression with its JSONPaths compiled.
+#[derive(Debug, Clone)]
+pub struct CompiledExpression {
+    /// The expression as written, for log messages.
+    text: String,
+    expression: Expression,
+    /// Compiled path of each expression variable; `None` for plain variables.
+    paths: Vec<Option<CompiledPath>>,
+}
+
+impl CompiledExpression {
+    /// Compile an expression, with or without a leading `=`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ConfigError::Validation` if the expression cannot be parsed.
+    pub fn compile(text: &str) -> Result<Self, ConfigError> {
+        let expression = Expression::parse(text)
+            .map_err(|e| ConfigError::Validation(format!("Expression '{text}' is invalid: {e}")))?;
+        let paths = expression
+            .variables()
+            .iter()
+            .map(|name| {
+                name.starts_with('$')
+                    .then(|| CompiledPath::compile(name))
+                    .transpose()
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            text: text.to_string(),
+            expression,
+            paths,
+        })
+    }
+
+    /// Evaluate the expression; `None` if it fails, e.g. for a missing path.
+    #[must_use]
+    pub fn evaluate(&self, scope: &MessageScope<'_>) -> Option<Value> {
+        self.expression
+            .evaluate(&self.values(scope))
+            .map_err(|e| self.failed(e))
+            .ok()
+    }
+
+    /// Evaluate the expression, keeping an integer result as an integer.
+    #[must_use]
+    pub fn evaluate_int(&self, scope: &MessageScope<'_>) -> Option<Value> {
+        self.expression
+            .evaluate_int(&self.values(scope))
+            .map_err(|e| self.failed(e))
+            .ok()
+    }
+
+    /// Evaluate the expression as a condition; a failed evaluation does not match.
+    #[must_use]
+    pub fn matches(&self, scope: &MessageScope<'_>) -> bool {
+        self.expression
+            .evaluate_condition(&self.values(scope))
+            .map_err(|e| self.failed(e))
+            .unwrap_or(false)
+    }
+
+    /// Log a failed evaluation; missing paths are expected and not logged.
+    fn failed(&self, error: ExpressionError) {
+        if !matches!(error, ExpressionError::MissingVariable(_)) {
+            warn!("Expression '{}' failed: {}", self.text, error);
+        }
+    }
+
+    fn values<'s>(&self, scope: &'s MessageScope<'_>) -> Vec<Option<&'s Value>> {
+        self.paths
+            .iter()
+            .map(|path| path.as_ref().and_then(|path| path.first(scope)))
+            .collect()
+    }
+}
+
+/// A compiled value specification: expression, JSONPath or literal.
+#[derive(Debug, Clone)]
+pub struct ValueSpec {
+    source: String,
+    kind: ValueKind,
+}
+
+#[derive(Debug, Clone)]
+enum ValueKind {
+    Expression(CompiledExpression),
+    Path(CompiledPath),
+    Literal,
+}
+
+impl ValueSpec {
+    /// Compile a value specification.
+    ///
+    /// Specifications starting with `=` are expressions, those containing
+    /// `$.` are JSONPaths and anything else is a literal string.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the expression or JSONPath cannot be parsed.
+    pub fn compile(spec: &str) -> Result<Self, ConfigError> {
+        let kind = if spec.starts_with('=') {
+            ValueKind::Expression(CompiledExpression::compile(spec)?)
+        } else if spec.contains("$.") {
+            ValueKind::Path(CompiledPath::compile(spec)?)
+        } else {
+            ValueKind::Literal
+        };
+        Ok(Self {
+            source: spec.to_string(),
+            kind,
+        })
+    }
+
+    /// The specification as written in the configuration.
+    #[must_use]
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Evaluate the specification for a message.
+    #[must_use]
+    pub fn evaluate(&self, scope: &MessageScope<'_>) -> Option<Value> {
+        match &self.kind {
+            ValueKind::Expression(expression) => expression.evaluate(scope),
+            _ => self.evaluate_int(scope),
+        }
+    }
+
+    /// Evaluate the specification, keeping an integer expression result as
+    /// an integer instead of a float.
+    #[must_use]
+    pub fn evaluate_int(&self, scope: &MessageScope<'_>) -> Option<Value> {
+        match &self.kind {
+            ValueKind::Expression(expression) => expression.evaluate_int(scope),
+            ValueKind::Path(path) => path.first(scope).cloned(),
+            ValueKind::Literal if self.source.is_empty() => None,
+            ValueKind::Literal => Some(Value::String(self.source.clone())),
+        }
+    }
+}
+
+/// A compiled field, yielding one value or, with flattening, several.
+#[derive(Debug, Clone)]
+pub struct FieldPlan {
+    name: String,
+    value: ValueSpec,
+    field_type: Option<String>,
+    /// Field name prefix and options when flattening (name ending with `*`).
+    flatten: Option<(String, FlattenConfig)>,
+}
+
+impl FieldPlan {
+    /// Compile a field specification.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value specification cannot be compiled.
+    pub fn compile(name: &str, spec: &FieldSpec) -> Result<Self, ConfigError> {
+        let field_type = match spec {
+            FieldSpec::Simple(_) => None,
+            FieldSpec::Typed(typed) => typed.field_type.clone(),
+            FieldSpec::Flatten(config) => config.field_type.clone(),
+        };
+        let flatten = name.strip_suffix('*').map(|prefix| {
+            let config = match spec {
+                FieldSpec::Flatten(config) => config.clone(),
+                _ => FlattenConfig::new(spec.value()),
+            };
+            (prefix.to_string(), config)
+        });
+        Ok(Self {
+            name: name.to_string(),
+            value: ValueSpec::compile(spec.value())?,
+            field_type,
+            flatten,
+        })
+    }
+
+    /// The value specification of the field.
+    #[must_use]
+    pub fn value(&self) -> &ValueSpec {
+        &self.value
+    }
+
+    /// Extract the named field values for a message.
+    ///
+    /// A field name ending with `*` flattens the extracted JSON object into
+    /// multiple fields, prefixed with the part of the name before `*`.
+    /// Any other name yields at most one field. Numeric expression results
+    /// are floats, unless the field has `type: int`.
+    #[must_use]
+    pub fn extract(&self, scope: &MessageScope<'_>) -> Vec<(String, Value)> {
+        let value = match self.field_type.as_deref() {
+            Some("int") => self.value.evaluate_int(scope),
+            _ => self.value.evaluate(scope),
+        };
+        let Some(value) = value else {
+            return Vec::new();
+        };
+        let convert = |value: Value| match &self.field_type {
+            Some(type_name) => convert_type(&value, type_name),
+            None => Some(value),
+        };
+
+        let Some((prefix, config)) = &self.flatten else {
+            return convert(value)
+                .map(|value| (self.name.clone(), value))
+                .into_iter()
+                .collect();
+        };
+        flatten_value(&value, config)
+            .into_iter()
+            .filter_map(|(key, value)| Some((format!("{prefix}{key}"), convert(value)?)))
+            .collect()
+    }
+}
+
+/// A compiled message timestamp.
+#[derive(Debug, Clone)]
+pub struct TimestampPlan {
+    value: ValueSpec,
+    format: TimestampFormat,
+    zone: TimestampZone,
+}
+
+impl TimestampPlan {
+    /// Compile a timestamp specification with optional format and timezone.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value, format or timezone is invalid.
+    pub fn compile(
+        spec: &str,
+        format: Option<&str>,
+        timezone: Option<&str>,
+    ) -> Result<Self, ConfigError> {
+        Ok(Self {
+            value: ValueSpec::compile(spec)?,
+            format: format
+                .map(str::parse)
+                .transpose()
+                .map_err(ConfigError::Validation)?
+                .unwrap_or_default(),
+            zone: timezone
+                .map(str::parse)
+                .transpose()
+                .map_err(ConfigError::Validation)?
+                .unwrap_or_default(),
+        })
+    }
+
+    /// The value specification of the timestamp.
+    #[must_use]
+    pub fn value(&self) -> &ValueSpec {
+        &self.value
+    }
+
+    /// Extract the timestamp in nanoseconds; `None` if missing or unparseable.
+    #[must_use]
+    pub fn extract(&self, scope: &MessageScope<'_>) -> Option<i64> {
+        let value = self.value.evaluate_int(scope)?;
+        parse_timestamp(&value, &self.format, self.zone)
+    }
+}
+
+/// A point configuration compiled for processing messages.
+#[derive(Debug, Clone)]
+pub struct PointPlan {
+    config: PointConfig,
+    measurement: ValueSpec,
+    tags: Vec<(String, ValueSpec)>,
+    fields: Vec<FieldPlan>,
+    schedule: Option<Schedule>,
+    condition: Option<CompiledExpression>,
+    foreach: Option<CompiledPath>,
+    timestamp: Option<TimestampPlan>,
+    httpcontent: Vec<(String, ValueSpec)>,
+}
+
+impl PointPlan {
+    /// Compile a point configuration.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a specification, the schedule or the timestamp
+    /// options are invalid.
+    pub fn compile(config: &PointConfig) -> Result<Self, ConfigError> {
+        let named = |specs: &std::collections::HashMap<String, String>| {
+            specs
+                .iter()
+                .map(|(name, spec)| Ok((name.clone(), ValueSpec::compile(spec)?)))
+                .collect::<Result<Vec<_>, ConfigError>>()
+        };
+        let schedule = config
+            .schedule
+            .as_deref()
+            .map(|schedule| {
+                normalize_cron_schedule(schedule)
+                    .and_then(|normalized| Schedule::from_str(&normalized).ok())
+                    .ok_or_else(|| ConfigError::InvalidCron(schedule.to_string()))
+            })
+            .transpose()?;
+
+        Ok(Self {
+            measurement: ValueSpec::compile(&config.measurement)?,
+            tags: named(&config.tags)?,
+            fields: config
+                .fields
+                .iter()
+                .map(|(name, spec)| FieldPlan::compile(name, spec))
+                .collect::<Result<_, _>>()?,
+            schedule,
+            condition: config
+                .when
+                .as_deref()
+                .map(CompiledExpression::compile)
+                .transpose()?,
+            foreach: config
+                .foreach
+                .as_deref()
+                .map(CompiledPath::compile)
+                .transpose()?,
+            timestamp: config
+                .timestamp
+                .as_deref()
+                .map(|spec| {
+                    TimestampPlan::compile(
+                        spec,
+                        config.timestamp_format.as_deref(),
+                        config.timezone.as_deref(),
+                    )
+                })
+                .transpose()?,
+            httpcontent: named(&config.httpcontent)?,
+            config: config.clone(),
+        })
+    }
+
+    /// The configuration the plan was compiled from.
+    #[must_use]
+    pub fn config(&self) -> &PointConfig {
+        &self.config
+    }
+
+    /// The measurement name specification.
+    #[must_use]
+    pub fn measurement(&self) -> &ValueSpec {
+        &self.measurement
+    }
+
+    /// Tag names with their value specifications.
+    #[must_use]
+    pub fn tags(&self) -> &[(String, ValueSpec)] {
+        &self.tags
+    }
+
+    /// The fields of the point.
+    #[must_use]
+    pub fn fields(&self) -> &[FieldPlan] {
+        &self.fields
+    }
+
+    /// The message timestamp, if configured.
+    #[must_use]
+    pub fn timestamp(&self) -> Option<&TimestampPlan> {
+        self.timestamp.as_ref()
+    }
+
+    /// HTTP content keys with their value specifications.
+    #[must_use]
+    pub fn httpcontent(&self) -> &[(String, ValueSpec)] {
+        &self.httpcontent
+    }
+
+    /// Check if the schedule, if any, matches the current minute.
+    #[must_use]
+    pub fn schedule_matches(&self) -> bool {
+        self.schedule
+            .as_ref()
+            .is_none_or(|schedule| schedule_matches_at(schedule, chrono::Utc::now()))
+    }
+
+    /// Check if the `when` condition, if any, matches.
+    #[must_use]
+    pub fn condition_matches(&self, scope: &MessageScope<'_>) -> bool {
+        self.condition
+            .as_ref()
+            .is_none_or(|condition| condition.matches(scope))
+    }
+
+    /// Expand a message into one scope per `foreach` element.
+    ///
+    /// If the JSONPath selects a single array, each of its elements becomes an
+    /// item; if it selects several values (e.g. with a wildcard), each value
+    /// becomes an item. The current element is addressable as `$.item` and its
+    /// position as `$.index`. Returns `None` if the point has no `foreach`.
+    #[must_use]
+    pub fn items<'s>(&self, scope: &'s MessageScope<'_>) -> Option<Vec<MessageScope<'s>>> {
+        self.foreach
+            .as_ref()
+            .map(|foreach| foreach_items(foreach, scope))
+    }
+}
+
+/// Expand a message into one scope per element selected by `foreach`.
+fn foreach_items<'s>(foreach: &CompiledPath, scope: &'s MessageScope<'_>) -> Vec<MessageScope<'s>> {
+    let results = foreach.query(scope);
+    let items: Vec<&Value> = match results.as_slice() {
+        [Value::Array(elements)] => elements.iter().collect(),
+        _ => results,
+    };
+    items
+        .into_iter()
+        .enumerate()
+        .map(|(index, item)| MessageScope::with_item(scope.msg, Some((item, index))))
+        .collect()
+}
+
+/// Check if a cron schedule matches the minute of the given time.
+///
+/// This implements the same behavior as Python's `pycron.is_now()`.
+pub(super) fn schedule_matches_at<Tz: chrono::TimeZone>(
+    schedule: &Schedule,
+    time: chrono::DateTime<Tz>,
+) -> bool {
+    use chrono::{Duration, Timelike};
+
+    // Get start of current minute (truncate seconds and nanoseconds)
+    let current_minute_start = time
+        .with_second(0)
+        .and_then(|t| t.with_nanosecond(0))
+        .unwrap_or_else(|| time.clone());
+
+    // Look for scheduled times starting just before the current minute
+    let check_from = current_minute_start.clone() - Duration::seconds(1);
+
+    // The next scheduled time after check_from should be within current minute if schedule matches
+    if let Some(next_scheduled) = schedule.after(&check_from).next() {
+        // Check if the next scheduled time is within the current minute
+        let next_minute_start = next_scheduled
+            .with_second(0)
+            .and_then(|t| t.with_nanosecond(0));
+        next_minute_start == Some(current_minute_start)
+    } else {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn message(payload: Value) -> ParsedMessage {
+        ParsedMessage {
+            topic: vec!["node".to_string(), "kitchen".to_string()],
+            payload,
+            timestamp: None,
+            qos: 1,
+            broker: Some("main".to_string()),
+            properties: None,
+            base64decoded: None,
+        }
+    }
+
+    fn point(yaml: &str) -> PointPlan {
+        PointPlan::compile(&serde_yaml::from_str(yaml).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_path_roots() {
+        let msg = message(json!({"temp": 21.5, "limit": 20}));
+        let scope = MessageScope::new(&msg);
+        let first = |path: &str| CompiledPath::compile(path).unwrap().first(&scope).cloned();
+
+        assert_eq!(
+            CompiledPath::compile("$.payload.temp").unwrap().root,
+            Root::Payload
+        );
+        assert_eq!(first("$.payload.temp"), Some(json!(21.5)));
+        assert_eq!(first("$.topic[1]"), Some(json!("kitchen")));
+        assert_eq!(first("$.qos"), Some(json!(1)));
+        assert_eq!(first("$.item"), None);
+        assert_eq!(first("$.properties.user"), None);
+    }
+
+    #[test]
+    fn test_path_filter_on_root_uses_message() {
+        let msg = message(json!({"values": [10, 30], "limit": 20}));
+        let scope = MessageScope::new(&msg);
+        let path = CompiledPath::compile("$.payload.values[?@ > $.payload.limit]").unwrap();
+
+        assert_eq!(path.root, Root::Message);
+        assert_eq!(path.query(&scope), vec![&json!(30)]);
+    }
+
+    #[test]
+    fn test_path_invalid() {
+        assert!(matches!(
+            CompiledPath::compile("$.payload["),
+            Err(ConfigError::InvalidJsonPath(_))
+        ));
+    }
+
+    #[test]
+    fn test_value_spec_kinds() {
+        let msg = message(json!({"temp": 21.5}));
+        let scope = MessageScope::new(&msg);
+        let evaluate = |spec: &str| ValueSpec::compile(spec).unwrap().evaluate(&scope);
+
+        assert_eq!(evaluate("$.payload.temp"), Some(json!(21.5)));
+        assert_eq!(evaluate("= $.payload.temp * 2"), Some(json!(43.0)));
+        assert_eq!(evaluate("= $.payload.missing * 2"), None);
+        assert_eq!(evaluate("kitchen"), Some(json!("kitchen")));
+        assert_eq!(evaluate(""), None);
+        assert!(ValueSpec::compile("= ($.payload.temp").is_err());
+    }
+
+    #[test]
+    fn test_point_items() {
+        let plan = point(
+            r#"
+measurement: temperature
+topic: node/+
+foreach: $.payload.sensors
+fields:
+  value: $.item.value
+  position: $.index
+"#,
+        );
+        let msg = message(json!({"sensors": [{"value": 1}, {"value": 2}]}));
+        let scope = MessageScope::new(&msg);
+        let items = plan.items(&scope).unwrap();
+
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[1].item(), Some(&json!({"value": 2})));
+        let mut fields: Vec<_> = plan
+            .fields()
+            .iter()
+            .flat_map(|field| field.extract(&items[1]))
+            .collect();
+        fields.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            fields,
+            vec![
+                ("position".to_string(), json!(1)),
+                ("value".to_string(), json!(2))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_point_condition_and_timestamp() {
+        let plan = point(
+            r#"
+measurement: temperature
+topic: node/+
+when: $.payload.temp > 20
+timestamp: $.payload.time
+fields:
+  temp: $.payload.temp
+"#,
+        );
+        let warm = message(json!({"temp": 21.5, "time": 1700000000}));
+        let cold = message(json!({"temp": 18.0}));
+
+        assert!(plan.condition_matches(&MessageScope::new(&warm)));
+        assert!(!plan.condition_matches(&MessageScope::new(&cold)));
+        assert_eq!(
+            plan.timestamp().unwrap().extract(&MessageScope::new(&warm)),
+            Some(1_700_000_000_000_000_000)
+        );
+        assert_eq!(
+            plan.timestamp().unwrap().extract(&MessageScope::new(&cold)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_point_condition_guarding_optional_path() {
+        let plan = point(
+            r#"
+measurement: battery
+topic: node/+
+when: "!exists($.payload.battery) || $.payload.battery > 20"
+fields:
+  temp: $.payload.temp
+"#,
+        );
+        let matches = |payload| plan.condition_matches(&MessageScope::new(&message(payload)));
+
+        assert!(matches(json!({"temp": 21.5})));
+        assert!(matches(json!({"battery": 80})));
+        assert!(!matches(json!({"battery": 10})));
+    }
+
+    #[test]
+    fn test_point_schedule() {
+        let plan = point(
+            r#"
+measurement: temperature
+topic: node/+
+schedule: "*/5 * * * *"
+fields:
+  temp: $.payload.temp
+"#,
+        );
+        let schedule = plan.schedule.as_ref().unwrap();
+        let at = |minute: u32| {
+            chrono::TimeZone::with_ymd_and_hms(&chrono::Utc, 2024, 5, 1, 12, minute, 30).unwrap()
+        };
+
+        assert!(schedule_matches_at(schedule, at(10)));
+        assert!(!schedule_matches_at(schedule, at(11)));
+        assert!(point("measurement: m\ntopic: t\nfields: {}").schedule_matches());
+    }
+
+    #[test]
+    fn test_point_invalid_schedule() {
+        let config = serde_yaml::from_str("measurement: m\ntopic: t\nschedule: nope\nfields: {}");
+        assert!(matches!(
+            PointPlan::compile(&config.unwrap()),
+            Err(ConfigError::InvalidCron(_))
+        ));
+    }
+}
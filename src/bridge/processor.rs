@@ -1,12 +1,13 @@
 //! Message processing pipeline.
 
+use super::mqtt::{MqttMessage, topic_filter};
+use super::plan::{CompiledPath, FieldPlan, MessageScope, ValueSpec, schedule_matches_at};
 use crate::config::{Base64DecodeConfig, FieldSpec, normalize_cron_schedule};
 use crate::error::SinqttError;
-use crate::expr::{evaluate_expression, jsonpath_to_variable, parse_expression};
 use base64::Engine;
-use jsonpath_rust::JsonPath;
 use serde_json::{Value, json};
 use std::collections::HashMap;
+use std::str::FromStr;
 
 /// Parsed MQTT message ready for processing.
 #[derive(Debug, Clone)]
@@ -15,6 +16,10 @@ pub struct ParsedMessage {
     pub payload: Value,
     pub timestamp: Option<i64>,
     pub qos: u8,
+    /// Name of the broker the message was received from.
+    pub broker: Option<String>,
+    /// MQTT v5 publish properties.
+    pub properties: Option<Value>,
     pub base64decoded: Option<HashMap<String, Base64Decoded>>,
 }
 
@@ -28,13 +33,36 @@ pub struct Base64Decoded {
 /// Message processor for transforming MQTT messages.
 pub struct MessageProcessor {
     base64_config: Option<Base64DecodeConfig>,
+    /// Compiled JSONPath of the base64 source.
+    base64_source: Option<CompiledPath>,
 }
 
 impl MessageProcessor {
     /// Create a new message processor.
     #[must_use]
     pub fn new(base64_config: Option<Base64DecodeConfig>) -> Self {
-        Self { base64_config }
+        let base64_source = base64_config
+            .as_ref()
+            .and_then(|config| CompiledPath::compile(&config.source).ok());
+        Self {
+            base64_config,
+            base64_source,
+        }
+    }
+
+    /// Parse a received MQTT message, including its v5 properties.
+    ///
+    /// # Errors
+    ///
+    /// This function is currently infallible but returns `Result` for API consistency.
+    pub fn parse_mqtt_message(&self, msg: &MqttMessage) -> Result<ParsedMessage, SinqttError> {
+        self.parse(
+            &msg.topic,
+            &msg.payload,
+            msg.qos,
+            Some(msg.broker.clone()),
+            msg.properties.clone(),
+        )
     }
 
     /// Parse an MQTT message into a structured format.
@@ -47,6 +75,17 @@ impl MessageProcessor {
         topic: &str,
         payload: &[u8],
         qos: u8,
+    ) -> Result<ParsedMessage, SinqttError> {
+        self.parse(topic, payload, qos, None, None)
+    }
+
+    fn parse(
+        &self,
+        topic: &str,
+        payload: &[u8],
+        qos: u8,
+        broker: Option<String>,
+        properties: Option<Value>,
     ) -> Result<ParsedMessage, SinqttError> {
         let topic_parts: Vec<String> = topic.split('/').map(String::from).collect();
 
@@ -66,12 +105,14 @@ impl MessageProcessor {
             payload: payload_value,
             timestamp: None,
             qos,
+            broker,
+            properties,
             base64decoded: None,
         };
 
         // Handle base64 decoding if configured
         if let Some(config) = &self.base64_config
-            && let Some(decoded) = self.decode_base64(&msg, config)
+            && let Some(decoded) = self.decode_base64(&msg)
         {
             let mut map = HashMap::new();
             map.insert(config.target.clone(), decoded);
@@ -82,13 +123,9 @@ impl MessageProcessor {
     }
 
     /// Decode base64 content from message.
-    fn decode_base64(
-        &self,
-        msg: &ParsedMessage,
-        config: &Base64DecodeConfig,
-    ) -> Option<Base64Decoded> {
-        let msg_value = self.build_message_object(msg);
-        let value = self.extract_jsonpath(&config.source, &msg_value)?;
+    fn decode_base64(&self, msg: &ParsedMessage) -> Option<Base64Decoded> {
+        let scope = MessageScope::new(msg);
+        let value = self.base64_source.as_ref()?.first(&scope)?;
 
         let encoded = value.as_str()?;
         let raw = base64::engine::general_purpose::STANDARD
@@ -102,25 +139,7 @@ impl MessageProcessor {
     /// Build a JSON object representing the message for JSONPath queries.
     #[must_use]
     pub fn build_message_object(&self, msg: &ParsedMessage) -> Value {
-        let mut obj = json!({
-            "topic": msg.topic,
-            "payload": msg.payload,
-            "timestamp": msg.timestamp,
-            "qos": msg.qos,
-        });
-
-        if let Some(decoded) = &msg.base64decoded {
-            let mut decoded_obj = json!({});
-            for (key, value) in decoded {
-                decoded_obj[key] = json!({
-                    "raw": value.raw,
-                    "hex": value.hex,
-                });
-            }
-            obj["base64decoded"] = decoded_obj;
-        }
-
-        obj
+        message_object(msg)
     }
 
     /// Extract a value using the given specification.
@@ -135,131 +154,31 @@ impl MessageProcessor {
             return None;
         }
 
-        let msg_value = self.build_message_object(msg);
-
-        // Expression mode
-        if spec.starts_with('=') {
-            return self.evaluate_expression_spec(spec, &msg_value);
-        }
-
-        // JSONPath mode
-        if spec.contains("$.") {
-            return self.extract_jsonpath(spec, &msg_value);
-        }
-
-        // Literal mode
-        Some(Value::String(spec.to_string()))
-    }
-
-    /// Evaluate an expression specification.
-    fn evaluate_expression_spec(&self, spec: &str, msg_value: &Value) -> Option<Value> {
-        let expr = spec.trim_start_matches('=').trim();
-        let (_, jsonpaths) = parse_expression(expr);
-
-        // Extract variables from message
-        let mut variables = HashMap::new();
-        for path in jsonpaths {
-            if let Some(value) = self.extract_jsonpath(&path, msg_value) {
-                let var_name = jsonpath_to_variable(&path);
-                if let Some(num) = value.as_f64() {
-                    variables.insert(var_name, num);
-                } else if let Some(num) = value.as_i64() {
-                    variables.insert(var_name, num as f64);
-                }
-            }
-        }
-
-        // Evaluate expression
-        evaluate_expression(spec, &variables).ok().map(Value::from)
-    }
-
-    /// Extract a value using JSONPath.
-    fn extract_jsonpath(&self, path: &str, value: &Value) -> Option<Value> {
-        let results = value.query(path).ok()?;
-
-        // jsonpath-rust returns a vector of references
-        results.into_iter().next().cloned()
+        ValueSpec::compile(spec)
+            .ok()?
+            .evaluate(&MessageScope::new(msg))
     }
 
     /// Convert a value to the specified type.
     #[must_use]
     pub fn convert_type(&self, value: &Value, type_name: &str) -> Option<Value> {
-        match type_name {
-            "float" => {
-                if let Some(f) = value.as_f64() {
-                    Some(Value::from(f))
-                } else if let Some(s) = value.as_str() {
-                    s.parse::<f64>().ok().map(Value::from)
-                } else {
-                    value.as_i64().map(|i| Value::from(i as f64))
-                }
-            }
-            "int" => {
-                if let Some(i) = value.as_i64() {
-                    Some(Value::from(i))
-                } else if let Some(f) = value.as_f64() {
-                    Some(Value::from(f as i64))
-                } else if let Some(s) = value.as_str() {
-                    s.parse::<i64>().ok().map(Value::from)
-                } else {
-                    None
-                }
-            }
-            "str" | "string" => Some(Value::String(match value {
-                Value::String(s) => s.clone(),
-                other => other.to_string(),
-            })),
-            "bool" => {
-                if let Some(b) = value.as_bool() {
-                    Some(Value::Bool(b))
-                } else if let Some(s) = value.as_str() {
-                    match s.to_lowercase().as_str() {
-                        "true" | "1" | "yes" | "on" => Some(Value::Bool(true)),
-                        "false" | "0" | "no" | "off" => Some(Value::Bool(false)),
-                        _ => None,
-                    }
-                } else {
-                    value.as_i64().map(|i| Value::Bool(i != 0))
-                }
-            }
-            "booltoint" => {
-                let bool_val = if let Some(b) = value.as_bool() {
-                    Some(b)
-                } else if let Some(s) = value.as_str() {
-                    match s.to_lowercase().as_str() {
-                        "true" | "1" | "yes" | "on" => Some(true),
-                        "false" | "0" | "no" | "off" => Some(false),
-                        _ => None,
-                    }
-                } else {
-                    None
-                };
-                bool_val.map(|b| Value::from(if b { 1i64 } else { 0i64 }))
-            }
-            _ => None,
-        }
+        convert_type(value, type_name)
     }
 
     /// Extract field value according to field specification.
     #[must_use]
     pub fn extract_field(&self, spec: &FieldSpec, msg: &ParsedMessage) -> Option<Value> {
-        match spec {
-            FieldSpec::Simple(s) => self.get_value(s, msg),
-            FieldSpec::Typed(config) => {
-                let value = self.get_value(&config.value, msg)?;
-                if let Some(type_name) = &config.field_type {
-                    self.convert_type(&value, type_name)
-                } else {
-                    Some(value)
-                }
-            }
-        }
+        let field = FieldPlan::compile("", spec).ok()?;
+        let (_, value) = field.extract(&MessageScope::new(msg)).into_iter().next()?;
+        Some(value)
     }
 
     /// Check if a topic matches a subscription pattern.
+    ///
+    /// A shared subscription (`$share/<group>/<filter>`) matches by its filter.
     #[must_use]
     pub fn topic_matches(&self, pattern: &str, topic: &str) -> bool {
-        let pattern_parts: Vec<&str> = pattern.split('/').collect();
+        let pattern_parts: Vec<&str> = topic_filter(pattern).split('/').collect();
         let topic_parts: Vec<&str> = topic.split('/').collect();
 
         let mut p_idx = 0;
@@ -310,38 +229,99 @@ impl MessageProcessor {
         schedule: &str,
         time: chrono::DateTime<Tz>,
     ) -> bool {
-        use chrono::{Duration, Timelike};
-        use cron::Schedule;
-        use std::str::FromStr;
-
         // Normalize 5-field cron (standard) to 6-field (with seconds)
-        let Some(normalized) = normalize_cron_schedule(schedule) else {
-            return false;
-        };
+        normalize_cron_schedule(schedule)
+            .and_then(|normalized| cron::Schedule::from_str(&normalized).ok())
+            .is_some_and(|schedule| schedule_matches_at(&schedule, time))
+    }
+}
 
-        let Ok(cron_schedule) = Schedule::from_str(&normalized) else {
-            return false;
-        };
+/// Build a JSON object representing the message for JSONPath queries.
+pub(super) fn message_object(msg: &ParsedMessage) -> Value {
+    let mut obj = json!({
+        "topic": msg.topic,
+        "payload": msg.payload,
+        "timestamp": msg.timestamp,
+        "qos": msg.qos,
+    });
+
+    if let Some(broker) = &msg.broker {
+        obj["broker"] = json!(broker);
+    }
 
-        // Get start of current minute (truncate seconds and nanoseconds)
-        let current_minute_start = time
-            .with_second(0)
-            .and_then(|t| t.with_nanosecond(0))
-            .unwrap_or_else(|| time.clone());
-
-        // Look for scheduled times starting just before the current minute
-        let check_from = current_minute_start.clone() - Duration::seconds(1);
-
-        // The next scheduled time after check_from should be within current minute if schedule matches
-        if let Some(next_scheduled) = cron_schedule.after(&check_from).next() {
-            // Check if the next scheduled time is within the current minute
-            let next_minute_start = next_scheduled
-                .with_second(0)
-                .and_then(|t| t.with_nanosecond(0));
-            next_minute_start == Some(current_minute_start)
-        } else {
-            false
+    if let Some(properties) = &msg.properties {
+        obj["properties"] = properties.clone();
+    }
+
+    if let Some(decoded) = &msg.base64decoded {
+        let mut decoded_obj = json!({});
+        for (key, value) in decoded {
+            decoded_obj[key] = json!({
+                "raw": value.raw,
+                "hex": value.hex,
+            });
+        }
+        obj["base64decoded"] = decoded_obj;
+    }
+
+    obj
+}
+
+/// Convert a value to the specified type.
+pub(super) fn convert_type(value: &Value, type_name: &str) -> Option<Value> {
+    match type_name {
+        "float" => {
+            if let Some(f) = value.as_f64() {
+                Some(Value::from(f))
+            } else if let Some(s) = value.as_str() {
+                s.parse::<f64>().ok().map(Value::from)
+            } else {
+                value.as_i64().map(|i| Value::from(i as f64))
+            }
+        }
+        "int" => {
+            if let Some(i) = value.as_i64() {
+                Some(Value::from(i))
+            } else if let Some(f) = value.as_f64() {
+                Some(Value::from(f as i64))
+            } else if let Some(s) = value.as_str() {
+                s.parse::<i64>().ok().map(Value::from)
+            } else {
+                None
+            }
+        }
+        "str" | "string" => Some(Value::String(match value {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        })),
+        "bool" => {
+            if let Some(b) = value.as_bool() {
+                Some(Value::Bool(b))
+            } else if let Some(s) = value.as_str() {
+                match s.to_lowercase().as_str() {
+                    "true" | "1" | "yes" | "on" => Some(Value::Bool(true)),
+                    "false" | "0" | "no" | "off" => Some(Value::Bool(false)),
+                    _ => None,
+                }
+            } else {
+                value.as_i64().map(|i| Value::Bool(i != 0))
+            }
+        }
+        "booltoint" => {
+            let bool_val = if let Some(b) = value.as_bool() {
+                Some(b)
+            } else if let Some(s) = value.as_str() {
+                match s.to_lowercase().as_str() {
+                    "true" | "1" | "yes" | "on" => Some(true),
+                    "false" | "0" | "no" | "off" => Some(false),
+                    _ => None,
+                }
+            } else {
+                None
+            };
+            bool_val.map(|b| Value::from(if b { 1i64 } else { 0i64 }))
         }
+        _ => None,
     }
 }
 
@@ -0,0 +1,385 @@
+//! Durable on-disk spool for line protocol batches that could not be delivered.
+//!
+//! Each batch is stored as a numbered segment file in the spool directory.
+//! The first line of a segment records the target bucket; the remaining lines
+//! are the line protocol payload. Segments are replayed oldest-first, and the
+//! oldest segments are evicted when the disk budget is exceeded.
+
+use crate::config::SpoolConfig;
+use crate::error::SinqttError;
+use std::collections::VecDeque;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+/// File extension of spool segments.
+const SEGMENT_EXTENSION: &str = "lp";
+
+/// Prefix of the header line holding the bucket name.
+const BUCKET_HEADER: &str = "#bucket";
+
+/// A batch read back from the spool.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpooledBatch {
+    pub bucket: Option<String>,
+    pub lines: Vec<String>,
+}
+
+/// A segment file on disk.
+#[derive(Debug)]
+struct Segment {
+    seq: u64,
+    path: PathBuf,
+    size: u64,
+}
+
+/// Write-ahead spool of undelivered batches.
+#[derive(Debug)]
+pub struct Spool {
+    directory: PathBuf,
+    max_bytes: u64,
+    next_seq: u64,
+    segments: VecDeque<Segment>,
+    total_bytes: u64,
+}
+
+impl Spool {
+    /// Open the spool directory, creating it if needed and indexing existing segments.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory cannot be created or read.
+    pub fn open(config: &SpoolConfig) -> Result<Self, SinqttError> {
+        fs::create_dir_all(&config.directory)?;
+
+        let mut segments = Vec::new();
+        for entry in fs::read_dir(&config.directory)? {
+            let path = entry?.path();
+            match path.extension().and_then(|e| e.to_str()) {
+                Some(SEGMENT_EXTENSION) => {}
+                Some("tmp") => {
+                    // Leftover from an interrupted write
+                    let _ = fs::remove_file(&path);
+                    continue;
+                }
+                _ => continue,
+            }
+            let Some(seq) = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(|s| s.parse::<u64>().ok())
+            else {
+                continue;
+            };
+            let size = fs::metadata(&path)?.len();
+            segments.push(Segment { seq, path, size });
+        }
+        segments.sort_by_key(|s| s.seq);
+
+        let next_seq = segments.last().map_or(0, |s| s.seq + 1);
+        let total_bytes = segments.iter().map(|s| s.size).sum();
+
+        if !segments.is_empty() {
+            debug!(
+                "Opened spool with {} segments ({} bytes)",
+                segments.len(),
+                total_bytes
+            );
+        }
+
+        Ok(Self {
+            directory: config.directory.clone(),
+            max_bytes: config.max_bytes,
+            next_seq,
+            segments: segments.into(),
+            total_bytes,
+        })
+    }
+
+    /// Append a batch as a new segment, evicting the oldest segments if the
+    /// disk budget is exceeded.
+    ///
+    /// The new segment itself is never evicted, so once this returns `Ok` the
+    /// batch is durably stored.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the batch alone exceeds the disk budget or the
+    /// segment cannot be written.
+    pub fn push(&mut self, bucket: Option<&str>, lines: &[String]) -> Result<(), SinqttError> {
+        if lines.is_empty() {
+            return Ok(());
+        }
+
+        let seq = self.next_seq;
+        let path = self.segment_path(seq);
+        let tmp_path = path.with_extension("tmp");
+
+        let mut content = String::from(BUCKET_HEADER);
+        if let Some(bucket) = bucket {
+            content.push(' ');
+            content.push_str(bucket);
+        }
+        for line in lines {
+            content.push('\n');
+            content.push_str(line);
+        }
+        content.push('\n');
+
+        let size = content.len() as u64;
+        if size > self.max_bytes {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::FileTooLarge,
+                format!(
+                    "batch of {} bytes exceeds spool budget of {} bytes",
+                    size, self.max_bytes
+                ),
+            )
+            .into());
+        }
+
+        {
+            let mut file = fs::File::create(&tmp_path)?;
+            file.write_all(content.as_bytes())?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp_path, &path)?;
+        sync_directory(&self.directory)?;
+
+        self.next_seq += 1;
+        self.total_bytes += size;
+        self.segments.push_back(Segment { seq, path, size });
+        debug!("Spooled {} lines to segment {}", lines.len(), seq);
+
+        // Evict older segments only; the one just written fits the budget
+        while self.total_bytes > self.max_bytes && self.segments.len() > 1 {
+            let Some(oldest) = self.segments.pop_front() else {
+                break;
+            };
+            warn!(
+                "Spool exceeds {} bytes, evicting segment {}",
+                self.max_bytes, oldest.seq
+            );
+            self.remove_segment(&oldest)?;
+        }
+
+        Ok(())
+    }
+
+    /// Read the oldest batch without removing it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the segment cannot be read.
+    pub fn peek(&self) -> Result<Option<SpooledBatch>, SinqttError> {
+        let Some(segment) = self.segments.front() else {
+            return Ok(None);
+        };
+
+        let content = fs::read_to_string(&segment.path)?;
+        let mut lines = content.lines();
+        let bucket = lines
+            .next()
+            .and_then(|header| header.strip_prefix(BUCKET_HEADER))
+            .map(str::trim)
+            .filter(|b| !b.is_empty())
+            .map(String::from);
+        let lines = lines.filter(|l| !l.is_empty()).map(String::from).collect();
+
+        Ok(Some(SpooledBatch { bucket, lines }))
+    }
+
+    /// Remove the oldest batch.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the segment file cannot be deleted.
+    pub fn pop(&mut self) -> Result<(), SinqttError> {
+        if let Some(segment) = self.segments.pop_front() {
+            self.remove_segment(&segment)?;
+        }
+        Ok(())
+    }
+
+    /// Check if the spool has no pending batches.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// Number of pending batches.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.segments.len()
+    }
+
+    /// Total size of pending segments in bytes.
+    #[must_use]
+    pub const fn total_bytes(&self) -> u64 {
+        self.total_bytes
+    }
+
+    /// Get the spool directory.
+    #[must_use]
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    fn segment_path(&self, seq: u64) -> PathBuf {
+        self.directory
+            .join(format!("{seq:020}.{SEGMENT_EXTENSION}"))
+    }
+
+    fn remove_segment(&mut self, segment: &Segment) -> Result<(), SinqttError> {
+        self.total_bytes = self.total_bytes.saturating_sub(segment.size);
+        match fs::remove_file(&segment.path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Flush directory metadata so that a rename into it survives a crash.
+#[cfg(unix)]
+fn sync_directory(directory: &Path) -> std::io::Result<()> {
+    fs::File::open(directory)?.sync_all()
+}
+
+/// Directories cannot be opened for syncing on this platform.
+#[cfg(not(unix))]
+fn sync_directory(_directory: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_config(directory: &Path, max_bytes: u64) -> SpoolConfig {
+        SpoolConfig {
+            directory: directory.to_path_buf(),
+            max_bytes,
+            replay_interval_ms: 1000,
+        }
+    }
+
+    fn lines(values: &[&str]) -> Vec<String> {
+        values.iter().map(|s| (*s).to_string()).collect()
+    }
+
+    #[test]
+    fn test_push_and_peek_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spool = Spool::open(&make_config(dir.path(), 1024 * 1024)).unwrap();
+
+        spool
+            .push(Some("bucket1"), &lines(&["m value=1i", "m value=2i"]))
+            .unwrap();
+
+        let batch = spool.peek().unwrap().unwrap();
+        assert_eq!(batch.bucket.as_deref(), Some("bucket1"));
+        assert_eq!(batch.lines, lines(&["m value=1i", "m value=2i"]));
+    }
+
+    #[test]
+    fn test_default_bucket_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spool = Spool::open(&make_config(dir.path(), 1024 * 1024)).unwrap();
+
+        spool.push(None, &lines(&["m value=1i"])).unwrap();
+
+        let batch = spool.peek().unwrap().unwrap();
+        assert_eq!(batch.bucket, None);
+    }
+
+    #[test]
+    fn test_pop_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spool = Spool::open(&make_config(dir.path(), 1024 * 1024)).unwrap();
+
+        spool.push(None, &lines(&["first value=1i"])).unwrap();
+        spool.push(None, &lines(&["second value=2i"])).unwrap();
+        assert_eq!(spool.len(), 2);
+
+        assert_eq!(spool.peek().unwrap().unwrap().lines[0], "first value=1i");
+        spool.pop().unwrap();
+        assert_eq!(spool.peek().unwrap().unwrap().lines[0], "second value=2i");
+        spool.pop().unwrap();
+        assert!(spool.is_empty());
+        assert_eq!(spool.total_bytes(), 0);
+    }
+
+    #[test]
+    fn test_reopen_preserves_segments() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = make_config(dir.path(), 1024 * 1024);
+        {
+            let mut spool = Spool::open(&config).unwrap();
+            spool.push(None, &lines(&["first value=1i"])).unwrap();
+            spool.push(None, &lines(&["second value=2i"])).unwrap();
+        }
+
+        let mut spool = Spool::open(&config).unwrap();
+        assert_eq!(spool.len(), 2);
+        assert_eq!(spool.peek().unwrap().unwrap().lines[0], "first value=1i");
+
+        // New segments continue after the existing ones
+        spool.push(None, &lines(&["third value=3i"])).unwrap();
+        spool.pop().unwrap();
+        spool.pop().unwrap();
+        assert_eq!(spool.peek().unwrap().unwrap().lines[0], "third value=3i");
+    }
+
+    #[test]
+    fn test_evicts_oldest_over_budget() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spool = Spool::open(&make_config(dir.path(), 60)).unwrap();
+
+        spool.push(None, &lines(&["first value=1i"])).unwrap();
+        spool.push(None, &lines(&["second value=2i"])).unwrap();
+        spool.push(None, &lines(&["third value=3i"])).unwrap();
+
+        assert!(spool.total_bytes() <= 60);
+        assert_eq!(spool.len(), 2);
+        assert_eq!(spool.peek().unwrap().unwrap().lines[0], "second value=2i");
+    }
+
+    #[test]
+    fn test_rejects_batch_over_budget() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spool = Spool::open(&make_config(dir.path(), 30)).unwrap();
+
+        spool.push(None, &lines(&["first value=1i"])).unwrap();
+        let result = spool.push(None, &lines(&["second value=2i", "third value=3i"]));
+
+        assert!(result.is_err());
+        assert_eq!(spool.len(), 1);
+        assert_eq!(spool.peek().unwrap().unwrap().lines[0], "first value=1i");
+    }
+
+    #[test]
+    fn test_never_evicts_new_segment() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spool = Spool::open(&make_config(dir.path(), 40)).unwrap();
+
+        spool.push(None, &lines(&["first value=1i"])).unwrap();
+        spool
+            .push(None, &lines(&["second value=2i", "x=1i"]))
+            .unwrap();
+
+        assert_eq!(spool.len(), 1);
+        assert_eq!(spool.peek().unwrap().unwrap().lines[0], "second value=2i");
+    }
+
+    #[test]
+    fn test_open_removes_temporary_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("00000000000000000000.tmp"), "partial").unwrap();
+
+        let spool = Spool::open(&make_config(dir.path(), 1024)).unwrap();
+        assert!(spool.is_empty());
+        assert!(!dir.path().join("00000000000000000000.tmp").exists());
+    }
+}
@@ -0,0 +1,248 @@
+//! MQTT subscription planning.
+//!
+//! Points may use overlapping topic filters (e.g. `sensors/#` and
+//! `sensors/+/temp`). Subscribing to both makes the broker deliver matching
+//! publishes twice, so the requested filters are reduced to a minimal set
+//! that covers all of them, each at the highest QoS required by a filter it
+//! covers. Filters that only partially overlap cannot be merged and are
+//! reported by [`partial_overlaps`].
+
+use std::collections::BTreeMap;
+
+/// Topic filter subscribed at a QoS level.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Subscription {
+    pub filter: String,
+    pub qos: u8,
+}
+
+impl Subscription {
+    /// Create a subscription to `filter` at `qos`.
+    #[must_use]
+    pub fn new(filter: impl Into<String>, qos: u8) -> Self {
+        Self {
+            filter: filter.into(),
+            qos,
+        }
+    }
+}
+
+/// Reduce the requested subscriptions to a minimal covering set.
+///
+/// A filter is dropped when another requested filter matches every topic it
+/// matches; the covering filter is subscribed at the maximum QoS of the
+/// filters it replaces. Shared subscriptions (`$share/<group>/<filter>`) are
+/// only merged with filters of the same group. Filters that only partially
+/// overlap (e.g. `a/+/c` and `a/b/+`) are both kept, see [`partial_overlaps`].
+/// The result is sorted by filter.
+#[must_use]
+pub fn plan_subscriptions(requested: impl IntoIterator<Item = Subscription>) -> Vec<Subscription> {
+    // Identical filters are merged first, keeping the highest QoS
+    let mut filters: BTreeMap<String, u8> = BTreeMap::new();
+    for subscription in requested {
+        let qos = filters.entry(subscription.filter).or_default();
+        *qos = (*qos).max(subscription.qos);
+    }
+
+    let mut planned: Vec<Subscription> = Vec::new();
+    for filter in filters.keys() {
+        // Distinct filters never cover each other, so the widest one is kept
+        let covered = filters
+            .keys()
+            .any(|other| other != filter && covers(other, filter));
+        if covered {
+            continue;
+        }
+
+        let qos = filters
+            .iter()
+            .filter(|(other, _)| covers(filter, other))
+            .map(|(_, qos)| *qos)
+            .max()
+            .unwrap_or_default();
+        planned.push(Subscription::new(filter.clone(), qos));
+    }
+    planned
+}
+
+/// Pairs of planned subscriptions that match some topics in common.
+///
+/// Planned filters never cover each other, so these only partially overlap,
+/// and the broker delivers a publish matching both once per subscription.
+#[must_use]
+pub fn partial_overlaps(planned: &[Subscription]) -> Vec<(&str, &str)> {
+    planned
+        .iter()
+        .enumerate()
+        .flat_map(|(i, first)| {
+            planned[i + 1..]
+                .iter()
+                .filter(|second| overlaps(&first.filter, &second.filter))
+                .map(|second| (first.filter.as_str(), second.filter.as_str()))
+        })
+        .collect()
+}
+
+/// Check whether some topic matches both subscriptions.
+///
+/// Share groups are ignored: a client holding both subscriptions receives
+/// such a topic through each of them.
+#[must_use]
+pub fn overlaps(first: &str, second: &str) -> bool {
+    let first: Vec<&str> = split_share(first).1.split('/').collect();
+    let second: Vec<&str> = split_share(second).1.split('/').collect();
+
+    // Wildcards at the first level do not match topics starting with `$`
+    let system = |levels: &[&str]| levels[0].starts_with('$');
+    let wildcard = |levels: &[&str]| matches!(levels[0], "+" | "#");
+    if (system(&first) && wildcard(&second)) || (system(&second) && wildcard(&first)) {
+        return false;
+    }
+
+    let mut i = 0;
+    loop {
+        match (first.get(i).copied(), second.get(i).copied()) {
+            // `a/#` also matches `a`
+            (Some("#"), _) | (_, Some("#")) | (None, None) => return true,
+            (Some(a), Some(b)) if a == "+" || b == "+" || a == b => i += 1,
+            _ => return false,
+        }
+    }
+}
+
+/// Check whether subscription `general` matches every topic that `specific` matches.
+#[must_use]
+pub fn covers(general: &str, specific: &str) -> bool {
+    let (general_group, general) = split_share(general);
+    let (specific_group, specific) = split_share(specific);
+    if general_group != specific_group {
+        return false;
+    }
+
+    let general: Vec<&str> = general.split('/').collect();
+    let specific: Vec<&str> = specific.split('/').collect();
+
+    // Wildcards at the first level do not match topics starting with `$`
+    if specific[0].starts_with('$') && matches!(general[0], "+" | "#") {
+        return false;
+    }
+
+    for (i, level) in general.iter().enumerate() {
+        match (*level, specific.get(i).copied()) {
+            // `a/#` also matches `a`
+            ("#", _) => return true,
+            (_, None | Some("#")) => return false,
+            ("+", Some(_)) => {}
+            (level, Some(other)) if level == other => {}
+            _ => return false,
+        }
+    }
+    general.len() == specific.len()
+}
+
+/// Split a subscription into its share group, if any, and topic filter.
+fn split_share(subscription: &str) -> (Option<&str>, &str) {
+    subscription
+        .strip_prefix("$share/")
+        .and_then(|shared| shared.split_once('/'))
+        .map_or((None, subscription), |(group, filter)| {
+            (Some(group), filter)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plan(requested: &[(&str, u8)]) -> Vec<(String, u8)> {
+        plan_subscriptions(
+            requested
+                .iter()
+                .map(|(filter, qos)| Subscription::new(*filter, *qos)),
+        )
+        .into_iter()
+        .map(|s| (s.filter, s.qos))
+        .collect()
+    }
+
+    #[test]
+    fn test_covers() {
+        assert!(covers("sensors/#", "sensors/+/temp"));
+        assert!(covers("sensors/#", "sensors"));
+        assert!(covers("sensors/+/temp", "sensors/a/temp"));
+        assert!(covers("#", "sensors/#"));
+        assert!(covers("a/b", "a/b"));
+
+        assert!(!covers("sensors/+/temp", "sensors/#"));
+        assert!(!covers("sensors/a/temp", "sensors/+/temp"));
+        assert!(!covers("sensors/+", "sensors/a/temp"));
+        assert!(!covers("sensors/+/temp", "sensors/a"));
+        assert!(!covers("#", "$SYS/broker/uptime"));
+        assert!(!covers("+/#", "$SYS/#"));
+    }
+
+    #[test]
+    fn test_covers_shared_subscriptions() {
+        assert!(covers("$share/g/sensors/#", "$share/g/sensors/a"));
+        assert!(!covers("$share/g/sensors/#", "$share/h/sensors/a"));
+        assert!(!covers("sensors/#", "$share/g/sensors/a"));
+        assert!(!covers("$share/g/sensors/#", "sensors/a"));
+    }
+
+    #[test]
+    fn test_plan_removes_covered_filters() {
+        assert_eq!(
+            plan(&[("sensors/+/temp", 1), ("sensors/#", 0), ("other/x", 1)]),
+            vec![("other/x".to_string(), 1), ("sensors/#".to_string(), 1)]
+        );
+    }
+
+    #[test]
+    fn test_plan_merges_identical_filters() {
+        assert_eq!(
+            plan(&[("a/b", 0), ("a/b", 2), ("a/c", 1)]),
+            vec![("a/b".to_string(), 2), ("a/c".to_string(), 1)]
+        );
+    }
+
+    #[test]
+    fn test_plan_keeps_overlapping_but_not_covering_filters() {
+        assert_eq!(
+            plan(&[("a/+/c", 1), ("a/b/+", 2)]),
+            vec![("a/+/c".to_string(), 1), ("a/b/+".to_string(), 2)]
+        );
+    }
+
+    #[test]
+    fn test_overlaps() {
+        assert!(overlaps("a/+/c", "a/b/+"));
+        assert!(overlaps("a/#", "a"));
+        assert!(overlaps("+/b", "a/#"));
+        assert!(overlaps("$share/g/a/+", "a/b"));
+
+        assert!(!overlaps("a/+/c", "a/b/d"));
+        assert!(!overlaps("a/+", "a/b/c"));
+        assert!(!overlaps("a/b", "a"));
+        assert!(!overlaps("#", "$SYS/broker"));
+        assert!(!overlaps("$SYS/+", "+/broker"));
+    }
+
+    #[test]
+    fn test_partial_overlaps() {
+        let planned = plan_subscriptions([
+            Subscription::new("a/+/c", 1),
+            Subscription::new("a/b/+", 2),
+            Subscription::new("x/#", 0),
+            Subscription::new("x/y", 0),
+        ]);
+        assert_eq!(partial_overlaps(&planned), vec![("a/+/c", "a/b/+")]);
+    }
+
+    #[test]
+    fn test_plan_transitive_cover() {
+        assert_eq!(
+            plan(&[("#", 0), ("a/#", 1), ("a/b", 2)]),
+            vec![("#".to_string(), 2)]
+        );
+    }
+}
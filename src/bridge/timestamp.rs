@@ -0,0 +1,379 @@
+//! Timestamp extraction from message values.
+
+use chrono::{
+    DateTime, FixedOffset, Local, LocalResult, NaiveDateTime, Offset, TimeDelta, TimeZone, Utc,
+};
+use chrono_tz::Tz;
+use serde_json::Value;
+use std::str::FromStr;
+
+/// Format of a timestamp value extracted from a message.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum TimestampFormat {
+    /// Numbers are treated as Unix time with the precision guessed from
+    /// their magnitude; strings are parsed as RFC 3339.
+    #[default]
+    Auto,
+    /// Unix time in seconds (fractions allowed).
+    Seconds,
+    /// Unix time in milliseconds.
+    Milliseconds,
+    /// Unix time in microseconds.
+    Microseconds,
+    /// Unix time in nanoseconds.
+    Nanoseconds,
+    /// RFC 3339 / ISO 8601 date and time with offset.
+    Rfc3339,
+    /// Custom `strftime` pattern, e.g. `%Y-%m-%d %H:%M:%S`.
+    Pattern(String),
+}
+
+impl FromStr for TimestampFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "s" | "unix" | "unix_s" => Ok(Self::Seconds),
+            "ms" | "unix_ms" => Ok(Self::Milliseconds),
+            "us" | "unix_us" => Ok(Self::Microseconds),
+            "ns" | "unix_ns" => Ok(Self::Nanoseconds),
+            "rfc3339" | "iso8601" => Ok(Self::Rfc3339),
+            pattern if pattern.contains('%') => Ok(Self::Pattern(pattern.to_string())),
+            other => Err(format!("Unknown timestamp format: {other}")),
+        }
+    }
+}
+
+/// Timezone applied to patterns that do not contain an offset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimestampZone {
+    #[default]
+    Utc,
+    Local,
+    Fixed(FixedOffset),
+    /// IANA timezone such as `Europe/Prague`, following its DST rules.
+    Named(Tz),
+}
+
+impl FromStr for TimestampZone {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "UTC" | "utc" | "Z" => Ok(Self::Utc),
+            "local" => Ok(Self::Local),
+            name => DateTime::parse_from_str(
+                &format!("1970-01-01T00:00:00{name}"),
+                "%Y-%m-%dT%H:%M:%S%:z",
+            )
+            .map(|dt| Self::Fixed(*dt.offset()))
+            .or_else(|_| name.parse::<Tz>().map(Self::Named))
+            .map_err(|_| {
+                format!(
+                    "Invalid timezone '{name}', expected UTC, local, an offset like +02:00 \
+                     or an IANA name like Europe/Prague"
+                )
+            }),
+        }
+    }
+}
+
+/// Convert a message value to a timestamp in nanoseconds since the Unix epoch.
+///
+/// Returns `None` if the value cannot be interpreted in the given format.
+#[must_use]
+pub fn parse_timestamp(
+    value: &Value,
+    format: &TimestampFormat,
+    zone: TimestampZone,
+) -> Option<i64> {
+    match format {
+        TimestampFormat::Auto => match value {
+            Value::String(s) => unix_nanos(value, None).or_else(|| parse_rfc3339(s)),
+            _ => unix_nanos(value, None),
+        },
+        TimestampFormat::Seconds => unix_nanos(value, Some(1_000_000_000)),
+        TimestampFormat::Milliseconds => unix_nanos(value, Some(1_000_000)),
+        TimestampFormat::Microseconds => unix_nanos(value, Some(1_000)),
+        TimestampFormat::Nanoseconds => unix_nanos(value, Some(1)),
+        TimestampFormat::Rfc3339 => parse_rfc3339(value.as_str()?),
+        TimestampFormat::Pattern(pattern) => parse_pattern(value.as_str()?, pattern, zone),
+    }
+}
+
+/// Scale a numeric (or numeric string) Unix timestamp to nanoseconds.
+///
+/// When `scale` is `None`, the precision is guessed from the magnitude.
+/// Integers are scaled exactly; fractional values are rounded to the nanosecond.
+fn unix_nanos(value: &Value, scale: Option<i64>) -> Option<i64> {
+    let (int, float) = match value {
+        Value::Number(n) => (n.as_i64(), n.as_f64()?),
+        Value::String(s) => {
+            let s = s.trim();
+            (s.parse::<i64>().ok(), s.parse::<f64>().ok()?)
+        }
+        _ => return None,
+    };
+    let scale = scale.unwrap_or_else(|| auto_scale(float.abs()));
+
+    match int {
+        Some(i) => i.checked_mul(scale),
+        None => {
+            let nanos = float * scale as f64;
+            (nanos.is_finite() && nanos.abs() < i64::MAX as f64).then(|| nanos.round() as i64)
+        }
+    }
+}
+
+/// Guess the nanosecond scale of a Unix timestamp from its magnitude.
+fn auto_scale(magnitude: f64) -> i64 {
+    if magnitude < 1e11 {
+        1_000_000_000
+    } else if magnitude < 1e14 {
+        1_000_000
+    } else if magnitude < 1e17 {
+        1_000
+    } else {
+        1
+    }
+}
+
+fn parse_rfc3339(s: &str) -> Option<i64> {
+    DateTime::parse_from_rfc3339(s.trim())
+        .ok()?
+        .timestamp_nanos_opt()
+}
+
+fn parse_pattern(s: &str, pattern: &str, zone: TimestampZone) -> Option<i64> {
+    let s = s.trim();
+
+    // Patterns containing an offset specifier carry their own timezone
+    if let Ok(dt) = DateTime::parse_from_str(s, pattern) {
+        return dt.timestamp_nanos_opt();
+    }
+
+    let naive = NaiveDateTime::parse_from_str(s, pattern).ok()?;
+    match zone {
+        TimestampZone::Utc => Utc.from_utc_datetime(&naive).timestamp_nanos_opt(),
+        TimestampZone::Local => local_nanos(&Local, &naive),
+        TimestampZone::Fixed(offset) => local_nanos(&offset, &naive),
+        TimestampZone::Named(tz) => local_nanos(&tz, &naive),
+    }
+}
+
+/// Convert a local time in the given zone to nanoseconds since the Unix epoch.
+///
+/// Around DST transitions, a local time that occurs twice resolves to its
+/// first occurrence, and a local time skipped by the clocks moving forward
+/// is read with the offset in effect before the gap, i.e. shifted forward by
+/// the length of the gap.
+fn local_nanos<Z: TimeZone>(zone: &Z, naive: &NaiveDateTime) -> Option<i64> {
+    match zone.from_local_datetime(naive) {
+        LocalResult::Single(dt) | LocalResult::Ambiguous(dt, _) => dt.timestamp_nanos_opt(),
+        LocalResult::None => {
+            let before = zone
+                .from_local_datetime(&(*naive - TimeDelta::hours(1)))
+                .earliest()?;
+            let offset = before.offset().fix().local_minus_utc();
+            (*naive - TimeDelta::seconds(offset.into()))
+                .and_utc()
+                .timestamp_nanos_opt()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const NANOS: i64 = 1_700_000_000_000_000_000;
+
+    #[test]
+    fn test_format_from_str() {
+        assert_eq!("s".parse(), Ok(TimestampFormat::Seconds));
+        assert_eq!("ms".parse(), Ok(TimestampFormat::Milliseconds));
+        assert_eq!("us".parse(), Ok(TimestampFormat::Microseconds));
+        assert_eq!("ns".parse(), Ok(TimestampFormat::Nanoseconds));
+        assert_eq!("rfc3339".parse(), Ok(TimestampFormat::Rfc3339));
+        assert_eq!(
+            "%Y-%m-%d %H:%M:%S".parse(),
+            Ok(TimestampFormat::Pattern("%Y-%m-%d %H:%M:%S".to_string()))
+        );
+        assert!("weeks".parse::<TimestampFormat>().is_err());
+    }
+
+    #[test]
+    fn test_zone_from_str() {
+        assert_eq!("UTC".parse(), Ok(TimestampZone::Utc));
+        assert_eq!("local".parse(), Ok(TimestampZone::Local));
+        assert_eq!(
+            "+02:00".parse(),
+            Ok(TimestampZone::Fixed(FixedOffset::east_opt(7200).unwrap()))
+        );
+        assert_eq!(
+            "Europe/Prague".parse(),
+            Ok(TimestampZone::Named(Tz::Europe__Prague))
+        );
+        assert!("Mars/Olympus".parse::<TimestampZone>().is_err());
+    }
+
+    fn prague(local: &str) -> Option<i64> {
+        let format = TimestampFormat::Pattern("%Y-%m-%d %H:%M:%S".to_string());
+        parse_timestamp(&json!(local), &format, "Europe/Prague".parse().unwrap())
+    }
+
+    fn utc(rfc3339: &str) -> Option<i64> {
+        parse_rfc3339(rfc3339)
+    }
+
+    #[test]
+    fn test_named_zone_follows_dst() {
+        assert_eq!(prague("2024-01-15 12:00:00"), utc("2024-01-15T11:00:00Z"));
+        assert_eq!(prague("2024-07-15 12:00:00"), utc("2024-07-15T10:00:00Z"));
+    }
+
+    #[test]
+    fn test_named_zone_ambiguous_time_takes_first_occurrence() {
+        // Clocks go back from 03:00 CEST to 02:00 CET on 27 October 2024
+        assert_eq!(prague("2024-10-27 02:30:00"), utc("2024-10-27T00:30:00Z"));
+        assert_eq!(prague("2024-10-27 03:30:00"), utc("2024-10-27T02:30:00Z"));
+    }
+
+    #[test]
+    fn test_named_zone_skipped_time_shifts_forward() {
+        // Clocks go forward from 02:00 CET to 03:00 CEST on 31 March 2024
+        assert_eq!(prague("2024-03-31 02:30:00"), utc("2024-03-31T01:30:00Z"));
+        assert_eq!(prague("2024-03-31 01:59:59"), utc("2024-03-31T00:59:59Z"));
+        assert_eq!(prague("2024-03-31 03:00:00"), utc("2024-03-31T01:00:00Z"));
+    }
+
+    #[test]
+    fn test_unix_units() {
+        let zone = TimestampZone::Utc;
+        assert_eq!(
+            parse_timestamp(&json!(1_700_000_000), &TimestampFormat::Seconds, zone),
+            Some(NANOS)
+        );
+        assert_eq!(
+            parse_timestamp(
+                &json!(1_700_000_000_000_i64),
+                &TimestampFormat::Milliseconds,
+                zone
+            ),
+            Some(NANOS)
+        );
+        assert_eq!(
+            parse_timestamp(
+                &json!(1_700_000_000_000_000_i64),
+                &TimestampFormat::Microseconds,
+                zone
+            ),
+            Some(NANOS)
+        );
+        assert_eq!(
+            parse_timestamp(&json!(NANOS), &TimestampFormat::Nanoseconds, zone),
+            Some(NANOS)
+        );
+    }
+
+    #[test]
+    fn test_unix_seconds_fraction_and_string() {
+        let zone = TimestampZone::Utc;
+        assert_eq!(
+            parse_timestamp(&json!(1_700_000_000.5), &TimestampFormat::Seconds, zone),
+            Some(NANOS + 500_000_000)
+        );
+        assert_eq!(
+            parse_timestamp(&json!("1700000000"), &TimestampFormat::Seconds, zone),
+            Some(NANOS)
+        );
+    }
+
+    #[test]
+    fn test_auto_detects_precision() {
+        let zone = TimestampZone::Utc;
+        let auto = TimestampFormat::Auto;
+        assert_eq!(
+            parse_timestamp(&json!(1_700_000_000), &auto, zone),
+            Some(NANOS)
+        );
+        assert_eq!(
+            parse_timestamp(&json!(1_700_000_000_000_i64), &auto, zone),
+            Some(NANOS)
+        );
+        assert_eq!(
+            parse_timestamp(&json!(1_700_000_000_000_000_i64), &auto, zone),
+            Some(NANOS)
+        );
+        assert_eq!(parse_timestamp(&json!(NANOS), &auto, zone), Some(NANOS));
+        assert_eq!(
+            parse_timestamp(&json!("2023-11-14T22:13:20Z"), &auto, zone),
+            Some(NANOS)
+        );
+    }
+
+    #[test]
+    fn test_rfc3339_with_offset() {
+        assert_eq!(
+            parse_timestamp(
+                &json!("2023-11-15T00:13:20+02:00"),
+                &TimestampFormat::Rfc3339,
+                TimestampZone::Utc
+            ),
+            Some(NANOS)
+        );
+    }
+
+    #[test]
+    fn test_pattern_with_zone() {
+        let format = TimestampFormat::Pattern("%Y-%m-%d %H:%M:%S".to_string());
+        assert_eq!(
+            parse_timestamp(&json!("2023-11-14 22:13:20"), &format, TimestampZone::Utc),
+            Some(NANOS)
+        );
+        assert_eq!(
+            parse_timestamp(
+                &json!("2023-11-15 00:13:20"),
+                &format,
+                "+02:00".parse().unwrap()
+            ),
+            Some(NANOS)
+        );
+    }
+
+    #[test]
+    fn test_pattern_with_offset_specifier() {
+        let format = TimestampFormat::Pattern("%d.%m.%Y %H:%M:%S %z".to_string());
+        assert_eq!(
+            parse_timestamp(
+                &json!("15.11.2023 00:13:20 +0200"),
+                &format,
+                TimestampZone::Utc
+            ),
+            Some(NANOS)
+        );
+    }
+
+    #[test]
+    fn test_unparsable_values() {
+        let zone = TimestampZone::Utc;
+        assert_eq!(
+            parse_timestamp(&json!("yesterday"), &TimestampFormat::Auto, zone),
+            None
+        );
+        assert_eq!(
+            parse_timestamp(&json!(null), &TimestampFormat::Seconds, zone),
+            None
+        );
+        assert_eq!(
+            parse_timestamp(&json!(42), &TimestampFormat::Rfc3339, zone),
+            None
+        );
+        assert_eq!(
+            parse_timestamp(&json!(f64::MAX), &TimestampFormat::Seconds, zone),
+            None
+        );
+    }
+}
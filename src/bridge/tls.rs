@@ -0,0 +1,403 @@
+//! TLS client configuration shared by the MQTT, `InfluxDB` and HTTP clients.
+
+use crate::error::{ConfigError, SinqttError};
+use std::path::Path;
+
+/// Certificate files and handshake settings of a TLS client.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TlsOptions<'a> {
+    /// CA certificates to trust; the webpki roots when not set.
+    pub cafile: Option<&'a Path>,
+    /// Client certificate chain for mutual TLS.
+    pub certfile: Option<&'a Path>,
+    /// Private key of the client certificate.
+    pub keyfile: Option<&'a Path>,
+    /// Accept any server certificate.
+    pub insecure_skip_verify: bool,
+    /// Name to verify the server certificate against instead of the host.
+    ///
+    /// The SNI is still taken from the host the client connects to.
+    pub verify_name: Option<&'a str>,
+    /// ALPN protocols offered in the handshake.
+    pub alpn: &'a [String],
+}
+
+impl TlsOptions<'_> {
+    /// Check if any setting differs from the default TLS client.
+    #[must_use]
+    pub fn is_configured(&self) -> bool {
+        self.cafile.is_some()
+            || self.certfile.is_some()
+            || self.keyfile.is_some()
+            || self.insecure_skip_verify
+            || self.verify_name.is_some()
+            || !self.alpn.is_empty()
+    }
+}
+
+/// Create an HTTP client using the TLS settings.
+///
+/// # Errors
+///
+/// Returns an error if the certificate files cannot be loaded or TLS support
+/// is not compiled in.
+pub fn http_client(options: TlsOptions<'_>) -> Result<reqwest::Client, SinqttError> {
+    if !options.is_configured() {
+        return Ok(reqwest::Client::new());
+    }
+    http_client_with_tls(options)
+}
+
+#[cfg(feature = "tls")]
+fn http_client_with_tls(options: TlsOptions<'_>) -> Result<reqwest::Client, SinqttError> {
+    let config = client_config(options)?;
+    Ok(reqwest::Client::builder()
+        .use_preconfigured_tls(config)
+        .build()?)
+}
+
+#[cfg(not(feature = "tls"))]
+fn http_client_with_tls(_options: TlsOptions<'_>) -> Result<reqwest::Client, SinqttError> {
+    Err(validation_error(
+        "TLS support not compiled in. Enable the 'tls' feature.".to_string(),
+    ))
+}
+
+fn validation_error(message: String) -> SinqttError {
+    SinqttError::Config(ConfigError::Validation(message))
+}
+
+/// Create a rustls client configuration from PEM files.
+///
+/// # Errors
+///
+/// Returns an error if a file cannot be read, contains no valid certificate
+/// or key, or the client certificate does not match its key.
+#[cfg(feature = "tls")]
+pub fn client_config(options: TlsOptions<'_>) -> Result<rustls::ClientConfig, SinqttError> {
+    use rustls::client::WebPkiServerVerifier;
+    use rustls::pki_types::ServerName;
+    use std::fs;
+    use std::sync::Arc;
+
+    let mut root_cert_store = rustls::RootCertStore::empty();
+    match options.cafile {
+        Some(cafile) => {
+            let ca_cert = fs::read(cafile)?;
+            let certs = rustls_pemfile::certs(&mut ca_cert.as_slice())
+                .filter_map(|r| r.ok())
+                .collect::<Vec<_>>();
+            if certs.is_empty() {
+                return Err(validation_error(format!(
+                    "No CA certificate found in {:?}",
+                    cafile
+                )));
+            }
+
+            for cert in certs {
+                root_cert_store
+                    .add(cert)
+                    .map_err(|e| validation_error(format!("Invalid CA certificate: {}", e)))?;
+            }
+        }
+        None => root_cert_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    }
+    let root_cert_store = Arc::new(root_cert_store);
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| validation_error(format!("TLS configuration error: {}", e)))?
+        .with_root_certificates(root_cert_store.clone());
+
+    let mut client_config = match (options.certfile, options.keyfile) {
+        (Some(certfile), Some(keyfile)) => {
+            let cert_chain = fs::read(certfile)?;
+            let key = fs::read(keyfile)?;
+
+            let certs = rustls_pemfile::certs(&mut cert_chain.as_slice())
+                .filter_map(|r| r.ok())
+                .collect::<Vec<_>>();
+            if certs.is_empty() {
+                return Err(validation_error(format!(
+                    "No certificate found in {:?}",
+                    certfile
+                )));
+            }
+            let key = rustls_pemfile::private_key(&mut key.as_slice())
+                .map_err(|e| validation_error(format!("Invalid key file: {}", e)))?
+                .ok_or_else(|| validation_error("No private key found in key file".to_string()))?;
+
+            builder
+                .with_client_auth_cert(certs, key)
+                .map_err(|e| match e {
+                    rustls::Error::InconsistentKeys(rustls::InconsistentKeys::KeyMismatch) => {
+                        validation_error(format!(
+                            "Client certificate {:?} does not match key file {:?}",
+                            certfile, keyfile
+                        ))
+                    }
+                    e => validation_error(format!("TLS client auth error: {}", e)),
+                })?
+        }
+        _ => builder.with_no_client_auth(),
+    };
+
+    if options.insecure_skip_verify {
+        client_config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(danger::NoVerification(provider)));
+    } else if let Some(verify_name) = options.verify_name {
+        let name = ServerName::try_from(verify_name.to_string())
+            .map_err(|_| validation_error(format!("Invalid TLS verify name '{}'", verify_name)))?;
+        let verifier = WebPkiServerVerifier::builder_with_provider(root_cert_store, provider)
+            .build()
+            .map_err(|e| validation_error(format!("TLS configuration error: {}", e)))?;
+        client_config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(danger::VerifyNameOverride { verifier, name }));
+    }
+
+    client_config.alpn_protocols = options
+        .alpn
+        .iter()
+        .map(|protocol| protocol.as_bytes().to_vec())
+        .collect();
+
+    Ok(client_config)
+}
+
+#[cfg(feature = "tls")]
+mod danger {
+    use rustls::client::WebPkiServerVerifier;
+    use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+    use rustls::crypto::{CryptoProvider, verify_tls12_signature, verify_tls13_signature};
+    use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+    use rustls::{DigitallySignedStruct, Error, SignatureScheme};
+    use std::sync::Arc;
+
+    /// Verifier accepting any server certificate.
+    ///
+    /// Handshake signatures are still checked, so the server must hold the
+    /// key of the certificate it presents.
+    #[derive(Debug)]
+    pub(super) struct NoVerification(pub(super) Arc<CryptoProvider>);
+
+    impl ServerCertVerifier for NoVerification {
+        fn verify_server_cert(
+            &self,
+            _end_entity: &CertificateDer<'_>,
+            _intermediates: &[CertificateDer<'_>],
+            _server_name: &ServerName<'_>,
+            _ocsp_response: &[u8],
+            _now: UnixTime,
+        ) -> Result<ServerCertVerified, Error> {
+            Ok(ServerCertVerified::assertion())
+        }
+
+        fn verify_tls12_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, Error> {
+            verify_tls12_signature(
+                message,
+                cert,
+                dss,
+                &self.0.signature_verification_algorithms,
+            )
+        }
+
+        fn verify_tls13_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, Error> {
+            verify_tls13_signature(
+                message,
+                cert,
+                dss,
+                &self.0.signature_verification_algorithms,
+            )
+        }
+
+        fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+            self.0.signature_verification_algorithms.supported_schemes()
+        }
+    }
+
+    /// Verifier checking the server certificate against a fixed name.
+    #[derive(Debug)]
+    pub(super) struct VerifyNameOverride {
+        pub(super) verifier: Arc<WebPkiServerVerifier>,
+        pub(super) name: ServerName<'static>,
+    }
+
+    impl ServerCertVerifier for VerifyNameOverride {
+        fn verify_server_cert(
+            &self,
+            end_entity: &CertificateDer<'_>,
+            intermediates: &[CertificateDer<'_>],
+            _server_name: &ServerName<'_>,
+            ocsp_response: &[u8],
+            now: UnixTime,
+        ) -> Result<ServerCertVerified, Error> {
+            self.verifier.verify_server_cert(
+                end_entity,
+                intermediates,
+                &self.name,
+                ocsp_response,
+                now,
+            )
+        }
+
+        fn verify_tls12_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, Error> {
+            self.verifier.verify_tls12_signature(message, cert, dss)
+        }
+
+        fn verify_tls13_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, Error> {
+            self.verifier.verify_tls13_signature(message, cert, dss)
+        }
+
+        fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+            self.verifier.supported_verify_schemes()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_client_without_settings() {
+        assert!(!TlsOptions::default().is_configured());
+        assert!(http_client(TlsOptions::default()).is_ok());
+    }
+
+    #[cfg(not(feature = "tls"))]
+    #[test]
+    fn test_tls_settings_without_feature_returns_error() {
+        let options = TlsOptions {
+            insecure_skip_verify: true,
+            ..TlsOptions::default()
+        };
+        assert!(http_client(options).is_err());
+    }
+
+    #[cfg(feature = "tls")]
+    mod with_tls {
+        use super::*;
+        use std::path::PathBuf;
+
+        fn fixture(name: &str) -> PathBuf {
+            PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/fixtures/tls")
+                .join(name)
+        }
+
+        #[test]
+        fn test_client_config_with_ca() {
+            let ca = fixture("ca.crt");
+            let options = TlsOptions {
+                cafile: Some(&ca),
+                ..TlsOptions::default()
+            };
+            let config = client_config(options).unwrap();
+            assert!(!config.client_auth_cert_resolver.has_certs());
+            assert!(http_client(options).is_ok());
+        }
+
+        #[test]
+        fn test_client_config_without_ca() {
+            let config = client_config(TlsOptions::default()).unwrap();
+            assert!(config.alpn_protocols.is_empty());
+        }
+
+        #[test]
+        fn test_client_config_with_client_cert() {
+            let (cert, key) = (fixture("client.crt"), fixture("client.key"));
+            let options = TlsOptions {
+                certfile: Some(&cert),
+                keyfile: Some(&key),
+                ..TlsOptions::default()
+            };
+            let config = client_config(options).unwrap();
+            assert!(config.client_auth_cert_resolver.has_certs());
+        }
+
+        #[test]
+        fn test_client_config_mismatched_key() {
+            let (cert, key) = (fixture("client.crt"), fixture("other.key"));
+            let options = TlsOptions {
+                certfile: Some(&cert),
+                keyfile: Some(&key),
+                ..TlsOptions::default()
+            };
+            let err = client_config(options).unwrap_err();
+            assert!(err.to_string().contains("does not match key file"));
+        }
+
+        #[test]
+        fn test_client_config_invalid_key() {
+            let (cert, key) = (fixture("client.crt"), fixture("ca.crt"));
+            let options = TlsOptions {
+                certfile: Some(&cert),
+                keyfile: Some(&key),
+                ..TlsOptions::default()
+            };
+            assert!(client_config(options).is_err());
+        }
+
+        #[test]
+        fn test_client_config_empty_ca() {
+            let ca = fixture("client.key");
+            let options = TlsOptions {
+                cafile: Some(&ca),
+                ..TlsOptions::default()
+            };
+            let err = client_config(options).unwrap_err();
+            assert!(err.to_string().contains("No CA certificate found"));
+        }
+
+        #[test]
+        fn test_client_config_verify_name_and_alpn() {
+            let alpn = vec!["x-amzn-mqtt-ca".to_string()];
+            let options = TlsOptions {
+                verify_name: Some("broker.internal"),
+                alpn: &alpn,
+                ..TlsOptions::default()
+            };
+            let config = client_config(options).unwrap();
+            assert_eq!(config.alpn_protocols, vec![b"x-amzn-mqtt-ca".to_vec()]);
+
+            let options = TlsOptions {
+                verify_name: Some("not a name"),
+                ..TlsOptions::default()
+            };
+            assert!(client_config(options).is_err());
+        }
+
+        #[test]
+        fn test_insecure_skip_verify() {
+            let options = TlsOptions {
+                insecure_skip_verify: true,
+                ..TlsOptions::default()
+            };
+            assert!(options.is_configured());
+            assert!(http_client(options).is_ok());
+        }
+    }
+}
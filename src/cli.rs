@@ -24,4 +24,8 @@ pub struct Args {
     /// Daemon mode: retry on error instead of exiting
     #[arg(short = 'd', long = "daemon")]
     pub daemon: bool,
+
+    /// Reload configuration when the file changes (SIGHUP always reloads)
+    #[arg(short = 'w', long = "watch")]
+    pub watch: bool,
 }
@@ -1,19 +1,27 @@
 //! Configuration types for sinqtt.
 
+use serde::de::value::{MapAccessDeserializer, SeqAccessDeserializer, StrDeserializer};
+use serde::de::{self, Deserializer, MapAccess, SeqAccess, Visitor};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
 use std::path::PathBuf;
 
 /// Root configuration structure.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
-    pub mqtt: MqttConfig,
+    /// MQTT broker connection, or a list of named connections.
+    #[serde(deserialize_with = "one_or_many")]
+    pub mqtt: Vec<MqttConfig>,
     pub influxdb: InfluxDBConfig,
     #[serde(default)]
     pub http: Option<HttpConfig>,
     #[serde(default)]
     pub base64decode: Option<Base64DecodeConfig>,
+    #[serde(default)]
+    pub server: Option<ServerConfig>,
     pub points: Vec<PointConfig>,
 }
 
@@ -21,18 +29,251 @@ pub struct Config {
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct MqttConfig {
+    /// Broker name, required when several brokers are configured.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Broker hostname, or a `ws://` / `wss://` URL to connect over WebSockets.
     pub host: String,
+    #[serde(default = "default_mqtt_port")]
     pub port: u16,
+    /// Fallback brokers as `host` or `host:port`, tried when `host` is unreachable.
+    #[serde(default)]
+    pub failover_hosts: Vec<String>,
+    /// Order in which the broker and its fallbacks are tried.
+    #[serde(default)]
+    pub failover: FailoverStrategy,
+    /// Delay between reconnection attempts.
+    #[serde(default)]
+    pub reconnect: ReconnectConfig,
+    /// Transport to the broker.
+    #[serde(default)]
+    pub transport: MqttTransport,
+    /// WebSocket request path; defaults to `/mqtt`.
+    #[serde(default)]
+    pub path: Option<String>,
+    /// Extra WebSocket handshake headers, e.g. for proxy authentication.
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// MQTT protocol version.
+    #[serde(default)]
+    pub protocol: MqttProtocol,
     #[serde(default)]
     pub username: Option<String>,
     #[serde(default)]
     pub password: Option<String>,
+    /// File containing the password, read again on every connection.
+    #[serde(default)]
+    pub password_file: Option<PathBuf>,
+    /// Send a signed JWT as the password (requires the `jwt` feature).
+    #[serde(default)]
+    pub jwt: Option<JwtConfig>,
+    /// Connect with TLS; implied by `cafile`.
+    #[serde(default)]
+    pub tls: bool,
+    /// CA certificates to trust instead of the webpki roots.
     #[serde(default)]
     pub cafile: Option<PathBuf>,
     #[serde(default)]
     pub certfile: Option<PathBuf>,
     #[serde(default)]
     pub keyfile: Option<PathBuf>,
+    /// Name to verify the broker certificate against instead of `host`.
+    ///
+    /// The SNI sent in the handshake is still taken from `host`.
+    #[serde(default)]
+    pub tls_verify_name: Option<String>,
+    /// ALPN protocols offered in the TLS handshake, e.g. `x-amzn-mqtt-ca`.
+    #[serde(default)]
+    pub alpn: Vec<String>,
+    /// Stable client identifier; defaults to `sinqtt-<pid>`.
+    #[serde(default)]
+    pub client_id: Option<String>,
+    /// Start a new session on connect; `false` keeps subscriptions and
+    /// queued messages across reconnects and restarts.
+    #[serde(default = "default_clean_session")]
+    pub clean_session: bool,
+    /// Seconds the broker keeps the session after disconnecting (v5 only).
+    #[serde(default)]
+    pub session_expiry_secs: Option<u32>,
+    /// Acknowledge QoS 1/2 messages only after their points are written or spooled.
+    #[serde(default)]
+    pub ack_after_write: bool,
+    /// Subscribe to all topics as `$share/<share_group>/<topic>` so that
+    /// bridge instances in the same group split the messages between them.
+    #[serde(default)]
+    pub share_group: Option<String>,
+}
+
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+fn default_clean_session() -> bool {
+    true
+}
+
+/// Order in which broker endpoints are tried when connecting fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailoverStrategy {
+    /// Move to the next endpoint on failure and back to `host` after a
+    /// disconnect, so the primary broker is preferred.
+    #[default]
+    Ordered,
+    /// Move to the next endpoint on every failure or disconnect.
+    RoundRobin,
+}
+
+/// JSON Web Token generated as the MQTT password, e.g. for cloud IoT brokers.
+///
+/// A new token is signed for every connection, and the connection is renewed
+/// before the token expires.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct JwtConfig {
+    /// PEM private key to sign the token with.
+    pub keyfile: PathBuf,
+    #[serde(default)]
+    pub algorithm: JwtAlgorithm,
+    /// `aud` claim, e.g. the cloud project ID.
+    #[serde(default)]
+    pub audience: Option<String>,
+    /// `iss` claim.
+    #[serde(default)]
+    pub issuer: Option<String>,
+    /// `sub` claim.
+    #[serde(default)]
+    pub subject: Option<String>,
+    /// Token lifetime in seconds.
+    #[serde(default = "default_jwt_expiry_secs")]
+    pub expiry_secs: u64,
+}
+
+fn default_jwt_expiry_secs() -> u64 {
+    3600
+}
+
+/// Signing algorithm of generated JWTs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+pub enum JwtAlgorithm {
+    /// RSASSA-PKCS1-v1_5 with SHA-256, using an RSA key.
+    #[default]
+    RS256,
+    /// ECDSA with P-256 and SHA-256, using an EC key.
+    ES256,
+}
+
+/// Reconnection backoff for MQTT connections.
+///
+/// The delay doubles after every failed attempt up to `max_delay_ms`, with
+/// jitter, and is reset once connected.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReconnectConfig {
+    #[serde(default = "default_reconnect_initial_delay_ms")]
+    pub initial_delay_ms: u64,
+    #[serde(default = "default_reconnect_max_delay_ms")]
+    pub max_delay_ms: u64,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            initial_delay_ms: default_reconnect_initial_delay_ms(),
+            max_delay_ms: default_reconnect_max_delay_ms(),
+        }
+    }
+}
+
+fn default_reconnect_initial_delay_ms() -> u64 {
+    1000
+}
+
+fn default_reconnect_max_delay_ms() -> u64 {
+    30_000
+}
+
+/// Transport to the MQTT broker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MqttTransport {
+    /// Plain TCP, or TLS when `tls` or `cafile` is set.
+    #[default]
+    Tcp,
+    /// WebSockets, secured with TLS when `tls` or `cafile` is set or the
+    /// host is a `wss://` URL.
+    Websocket,
+}
+
+impl MqttConfig {
+    /// Broker name, or `default` for an unnamed broker.
+    #[must_use]
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or("default")
+    }
+
+    /// Connection settings for `host` followed by each of the `failover_hosts`.
+    ///
+    /// Fallbacks without a port use `port`; fallback URLs are used as they are.
+    #[must_use]
+    pub fn endpoints(&self) -> Vec<MqttConfig> {
+        let primary = MqttConfig {
+            failover_hosts: Vec::new(),
+            ..self.clone()
+        };
+        let fallbacks = self.failover_hosts.iter().map(|entry| {
+            let (host, port) = split_host_port(entry);
+            MqttConfig {
+                host: host.to_string(),
+                port: port.unwrap_or(self.port),
+                ..primary.clone()
+            }
+        });
+        std::iter::once(primary.clone()).chain(fallbacks).collect()
+    }
+
+    /// Check if the connection is secured with TLS.
+    #[must_use]
+    pub fn uses_tls(&self) -> bool {
+        self.tls || self.cafile.is_some()
+    }
+
+    /// Check if the broker is reached over WebSockets.
+    #[must_use]
+    pub fn is_websocket(&self) -> bool {
+        self.transport == MqttTransport::Websocket
+            || self.host.starts_with("ws://")
+            || self.host.starts_with("wss://")
+    }
+}
+
+/// Split `host:port` into host and port; URLs and bare IPv6 addresses have no port.
+fn split_host_port(entry: &str) -> (&str, Option<u16>) {
+    if entry.contains("://") {
+        return (entry, None);
+    }
+    match entry.rsplit_once(':') {
+        Some((host, port)) if !host.contains(':') => match port.parse() {
+            Ok(port) => (host, Some(port)),
+            Err(_) => (entry, None),
+        },
+        Some((host, port)) if host.starts_with('[') && host.ends_with(']') => match port.parse() {
+            Ok(port) => (&host[1..host.len() - 1], Some(port)),
+            Err(_) => (entry, None),
+        },
+        _ => (entry, None),
+    }
+}
+
+/// MQTT protocol version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MqttProtocol {
+    /// MQTT 3.1.1.
+    #[default]
+    V3,
+    /// MQTT 5, with message properties, session expiry and reason codes.
+    V5,
 }
 
 /// InfluxDB configuration.
@@ -43,16 +284,143 @@ pub struct InfluxDBConfig {
     #[serde(default = "default_influxdb_port")]
     pub port: u16,
     pub token: String,
+    #[serde(default)]
     pub org: String,
     pub bucket: String,
     #[serde(default)]
+    pub api_version: InfluxDBApiVersion,
+    #[serde(default)]
+    pub accept_partial: Option<bool>,
+    #[serde(default)]
+    pub no_sync: Option<bool>,
+    #[serde(default)]
     pub enable_gzip: bool,
+    #[serde(default)]
+    pub batch: BatchConfig,
+    #[serde(default)]
+    pub spool: Option<SpoolConfig>,
+    #[serde(default)]
+    pub retry: RetryConfig,
+    /// CA certificate to verify the server with, instead of the webpki roots.
+    #[serde(default)]
+    pub cafile: Option<PathBuf>,
+    /// Client certificate presented for mutual TLS, together with `keyfile`.
+    #[serde(default)]
+    pub certfile: Option<PathBuf>,
+    #[serde(default)]
+    pub keyfile: Option<PathBuf>,
+    /// Accept any server certificate; only for testing.
+    #[serde(default)]
+    pub insecure_skip_verify: bool,
 }
 
 fn default_influxdb_port() -> u16 {
     8181
 }
 
+/// `InfluxDB` write API flavor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InfluxDBApiVersion {
+    /// v2-compatible `/api/v2/write` with `org` and `bucket` (InfluxDB 2.x, Cloud).
+    #[default]
+    V2,
+    /// Native `/api/v3/write_lp` with `db` (InfluxDB 3).
+    V3,
+}
+
+/// Batching configuration for `InfluxDB` writes.
+///
+/// Points are grouped per bucket and flushed when any threshold is reached.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatchConfig {
+    #[serde(default = "default_batch_max_lines")]
+    pub max_lines: usize,
+    #[serde(default = "default_batch_max_bytes")]
+    pub max_bytes: usize,
+    #[serde(default = "default_batch_max_latency_ms")]
+    pub max_latency_ms: u64,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            max_lines: default_batch_max_lines(),
+            max_bytes: default_batch_max_bytes(),
+            max_latency_ms: default_batch_max_latency_ms(),
+        }
+    }
+}
+
+fn default_batch_max_lines() -> usize {
+    1000
+}
+
+fn default_batch_max_bytes() -> usize {
+    1024 * 1024
+}
+
+fn default_batch_max_latency_ms() -> u64 {
+    1000
+}
+
+/// Retry policy for failed `InfluxDB` writes.
+///
+/// Connection errors, 408, 429 and 5xx responses are retried with jittered
+/// exponential backoff; other errors fail immediately.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetryConfig {
+    #[serde(default = "default_retry_max_retries")]
+    pub max_retries: u32,
+    #[serde(default = "default_retry_initial_delay_ms")]
+    pub initial_delay_ms: u64,
+    #[serde(default = "default_retry_max_delay_ms")]
+    pub max_delay_ms: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: default_retry_max_retries(),
+            initial_delay_ms: default_retry_initial_delay_ms(),
+            max_delay_ms: default_retry_max_delay_ms(),
+        }
+    }
+}
+
+fn default_retry_max_retries() -> u32 {
+    3
+}
+
+fn default_retry_initial_delay_ms() -> u64 {
+    500
+}
+
+fn default_retry_max_delay_ms() -> u64 {
+    30_000
+}
+
+/// On-disk spool for batches that could not be delivered to `InfluxDB`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SpoolConfig {
+    pub directory: PathBuf,
+    #[serde(default = "default_spool_max_bytes")]
+    pub max_bytes: u64,
+    #[serde(default = "default_spool_replay_interval_ms")]
+    pub replay_interval_ms: u64,
+}
+
+fn default_spool_max_bytes() -> u64 {
+    256 * 1024 * 1024
+}
+
+fn default_spool_replay_interval_ms() -> u64 {
+    5000
+}
+
 /// HTTP forwarding configuration.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
@@ -63,6 +431,17 @@ pub struct HttpConfig {
     pub username: Option<String>,
     #[serde(default)]
     pub password: Option<String>,
+    /// CA certificate to verify the server with, instead of the webpki roots.
+    #[serde(default)]
+    pub cafile: Option<PathBuf>,
+    /// Client certificate presented for mutual TLS, together with `keyfile`.
+    #[serde(default)]
+    pub certfile: Option<PathBuf>,
+    #[serde(default)]
+    pub keyfile: Option<PathBuf>,
+    /// Accept any server certificate; only for testing.
+    #[serde(default)]
+    pub insecure_skip_verify: bool,
 }
 
 /// Base64 decoding configuration.
@@ -73,16 +452,72 @@ pub struct Base64DecodeConfig {
     pub target: String,
 }
 
+/// Embedded HTTP server configuration.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig {
+    #[serde(default = "default_server_listen")]
+    pub listen: String,
+    #[serde(default)]
+    pub health: HealthConfig,
+}
+
+fn default_server_listen() -> String {
+    "0.0.0.0:9464".to_string()
+}
+
+/// Thresholds for the liveness and readiness endpoints.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct HealthConfig {
+    /// Seconds `InfluxDB` writes may keep failing before the bridge is not ready.
+    #[serde(default = "default_write_staleness_secs")]
+    pub write_staleness_secs: u64,
+    /// Seconds the MQTT session may be down before the bridge is not live.
+    #[serde(default)]
+    pub max_disconnected_secs: Option<u64>,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            write_staleness_secs: default_write_staleness_secs(),
+            max_disconnected_secs: None,
+        }
+    }
+}
+
+fn default_write_staleness_secs() -> u64 {
+    300
+}
+
 /// Point configuration for mapping MQTT topics to InfluxDB points.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct PointConfig {
     pub measurement: String,
     pub topic: String,
+    /// QoS to subscribe to the topic with.
+    #[serde(default = "default_point_qos")]
+    pub qos: u8,
+    /// Names of the brokers to subscribe on; all brokers when empty.
+    #[serde(default, rename = "broker", deserialize_with = "one_or_many")]
+    pub brokers: Vec<String>,
     #[serde(default)]
     pub bucket: Option<String>,
     #[serde(default)]
     pub schedule: Option<String>,
+    /// Condition the message must satisfy, e.g. `$.payload.type == "env"`.
+    #[serde(default)]
+    pub when: Option<String>,
+    #[serde(default, alias = "iterate")]
+    pub foreach: Option<String>,
+    #[serde(default)]
+    pub timestamp: Option<String>,
+    #[serde(default)]
+    pub timestamp_format: Option<String>,
+    #[serde(default)]
+    pub timezone: Option<String>,
     pub fields: HashMap<String, FieldSpec>,
     #[serde(default)]
     pub tags: HashMap<String, String>,
@@ -90,12 +525,40 @@ pub struct PointConfig {
     pub httpcontent: HashMap<String, String>,
 }
 
-/// Field specification - either a simple string or typed config.
+fn default_point_qos() -> u8 {
+    1
+}
+
+impl PointConfig {
+    /// Check if the point applies to messages from the named broker.
+    #[must_use]
+    pub fn applies_to(&self, broker: &str) -> bool {
+        self.brokers.is_empty() || self.brokers.iter().any(|b| b == broker)
+    }
+}
+
+/// Field specification - either a simple string, typed config or flatten config.
+///
+/// Fields whose name ends with `*` flatten a JSON object into multiple fields;
+/// the part of the name before `*` is used as a prefix.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum FieldSpec {
     Simple(String),
     Typed(FieldConfig),
+    Flatten(FlattenConfig),
+}
+
+impl FieldSpec {
+    /// Get the value specification (JSONPath, expression or literal).
+    #[must_use]
+    pub fn value(&self) -> &str {
+        match self {
+            Self::Simple(s) => s,
+            Self::Typed(config) => &config.value,
+            Self::Flatten(config) => &config.value,
+        }
+    }
 }
 
 /// Typed field configuration with optional type conversion.
@@ -106,3 +569,89 @@ pub struct FieldConfig {
     #[serde(rename = "type")]
     pub field_type: Option<String>,
 }
+
+/// Flattening of a JSON object into multiple fields.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FlattenConfig {
+    pub value: String,
+    #[serde(rename = "type", default)]
+    pub field_type: Option<String>,
+    #[serde(default = "default_flatten_separator")]
+    pub separator: String,
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    #[serde(default)]
+    pub max_depth: Option<usize>,
+    #[serde(default)]
+    pub arrays: FlattenArrays,
+}
+
+impl FlattenConfig {
+    /// Create a flatten configuration with default options.
+    #[must_use]
+    pub fn new(value: impl Into<String>) -> Self {
+        Self {
+            value: value.into(),
+            field_type: None,
+            separator: default_flatten_separator(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            max_depth: None,
+            arrays: FlattenArrays::default(),
+        }
+    }
+}
+
+fn default_flatten_separator() -> String {
+    ".".to_string()
+}
+
+/// Handling of arrays when flattening.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FlattenArrays {
+    /// Flatten elements using their index as the key, e.g. `values.0`.
+    #[default]
+    Index,
+    /// Skip arrays entirely.
+    Skip,
+    /// Store arrays as JSON strings.
+    Json,
+}
+
+/// Deserialize either a single value or a list of values.
+///
+/// Errors from the values themselves are reported as is, instead of the
+/// generic message an untagged enum would produce.
+fn one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    struct OneOrMany<T>(PhantomData<T>);
+
+    impl<'de, T: Deserialize<'de>> Visitor<'de> for OneOrMany<T> {
+        type Value = Vec<T>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a value or a list of values")
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+            T::deserialize(StrDeserializer::new(value)).map(|v| vec![v])
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+            T::deserialize(MapAccessDeserializer::new(map)).map(|v| vec![v])
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+            Vec::deserialize(SeqAccessDeserializer::new(seq))
+        }
+    }
+
+    deserializer.deserialize_any(OneOrMany(PhantomData))
+}
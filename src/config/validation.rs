@@ -1,14 +1,38 @@
 //! Configuration validation.
 
-use super::types::Config;
+use super::types::{Config, FieldSpec, InfluxDBApiVersion, MqttConfig, MqttProtocol};
+use crate::bridge::{PointPlan, TimestampFormat, TimestampZone};
 use crate::error::ConfigError;
+use crate::expr::validate_condition;
 use jsonpath_rust::parser::parse_json_path;
+use std::collections::HashSet;
+use std::path::Path;
 
 /// Validate the configuration.
 pub fn validate_config(config: &Config) -> Result<(), ConfigError> {
     // Validate MQTT config
-    if config.mqtt.host.is_empty() {
-        return Err(ConfigError::Validation("MQTT host cannot be empty".into()));
+    if config.mqtt.is_empty() {
+        return Err(ConfigError::Validation(
+            "At least one MQTT broker must be configured".into(),
+        ));
+    }
+    if config.mqtt.len() > 1 {
+        let mut names = HashSet::new();
+        for mqtt in &config.mqtt {
+            let Some(name) = mqtt.name.as_deref().filter(|name| !name.is_empty()) else {
+                return Err(ConfigError::Validation(
+                    "MQTT brokers must be named when several are configured".into(),
+                ));
+            };
+            if !names.insert(name) {
+                return Err(ConfigError::Validation(format!(
+                    "MQTT broker name '{name}' is used more than once"
+                )));
+            }
+        }
+    }
+    for mqtt in &config.mqtt {
+        validate_mqtt(mqtt)?;
     }
 
     // Validate InfluxDB config
@@ -22,7 +46,7 @@ pub fn validate_config(config: &Config) -> Result<(), ConfigError> {
             "InfluxDB token cannot be empty".into(),
         ));
     }
-    if config.influxdb.org.is_empty() {
+    if config.influxdb.api_version == InfluxDBApiVersion::V2 && config.influxdb.org.is_empty() {
         return Err(ConfigError::Validation(
             "InfluxDB org cannot be empty".into(),
         ));
@@ -33,6 +57,70 @@ pub fn validate_config(config: &Config) -> Result<(), ConfigError> {
         ));
     }
 
+    if config.influxdb.batch.max_lines == 0 {
+        return Err(ConfigError::Validation(
+            "InfluxDB batch max_lines must be greater than 0".into(),
+        ));
+    }
+    if config.influxdb.batch.max_bytes == 0 {
+        return Err(ConfigError::Validation(
+            "InfluxDB batch max_bytes must be greater than 0".into(),
+        ));
+    }
+    if config.influxdb.batch.max_latency_ms == 0 {
+        return Err(ConfigError::Validation(
+            "InfluxDB batch max_latency_ms must be greater than 0".into(),
+        ));
+    }
+
+    if config.influxdb.api_version == InfluxDBApiVersion::V2
+        && (config.influxdb.accept_partial.is_some() || config.influxdb.no_sync.is_some())
+    {
+        return Err(ConfigError::Validation(
+            "InfluxDB accept_partial and no_sync require api_version v3".into(),
+        ));
+    }
+
+    if config.influxdb.retry.max_delay_ms < config.influxdb.retry.initial_delay_ms {
+        return Err(ConfigError::Validation(
+            "InfluxDB retry max_delay_ms must not be less than initial_delay_ms".into(),
+        ));
+    }
+
+    if let Some(spool) = &config.influxdb.spool {
+        if spool.directory.as_os_str().is_empty() {
+            return Err(ConfigError::Validation(
+                "InfluxDB spool directory cannot be empty".into(),
+            ));
+        }
+        if spool.max_bytes == 0 {
+            return Err(ConfigError::Validation(
+                "InfluxDB spool max_bytes must be greater than 0".into(),
+            ));
+        }
+    }
+
+    validate_client_cert(
+        "InfluxDB",
+        config.influxdb.certfile.as_deref(),
+        config.influxdb.keyfile.as_deref(),
+    )?;
+    validate_tls_files(
+        config.influxdb.cafile.as_deref(),
+        config.influxdb.certfile.as_deref(),
+        config.influxdb.keyfile.as_deref(),
+    )?;
+
+    // Validate HTTP forwarding config
+    if let Some(http) = &config.http {
+        validate_client_cert("HTTP", http.certfile.as_deref(), http.keyfile.as_deref())?;
+        validate_tls_files(
+            http.cafile.as_deref(),
+            http.certfile.as_deref(),
+            http.keyfile.as_deref(),
+        )?;
+    }
+
     // Validate points
     if config.points.is_empty() {
         return Err(ConfigError::Validation(
@@ -59,6 +147,29 @@ pub fn validate_config(config: &Config) -> Result<(), ConfigError> {
                 i
             )));
         }
+        if point.qos > 2 {
+            return Err(ConfigError::Validation(format!(
+                "Point {i} qos must be 0, 1 or 2"
+            )));
+        }
+        if let Some(shared) = point.topic.strip_prefix("$share/") {
+            let valid = shared
+                .split_once('/')
+                .is_some_and(|(group, filter)| is_valid_share_group(group) && !filter.is_empty());
+            if !valid {
+                return Err(ConfigError::Validation(format!(
+                    "Point {i} topic '{}' must have the form $share/<group>/<topic>",
+                    point.topic
+                )));
+            }
+        }
+        for broker in &point.brokers {
+            if !config.mqtt.iter().any(|mqtt| mqtt.name() == broker) {
+                return Err(ConfigError::Validation(format!(
+                    "Point {i} broker '{broker}' is not configured"
+                )));
+            }
+        }
         if point.fields.is_empty() {
             return Err(ConfigError::Validation(format!(
                 "Point {} must have at least one field",
@@ -66,38 +177,285 @@ pub fn validate_config(config: &Config) -> Result<(), ConfigError> {
             )));
         }
 
+        // Validate flatten fields
+        for (name, spec) in &point.fields {
+            if let FieldSpec::Flatten(flatten) = spec {
+                if !name.ends_with('*') {
+                    return Err(ConfigError::Validation(format!(
+                        "Point {i} field '{name}' uses flatten options, its name must end with '*'"
+                    )));
+                }
+                if flatten.max_depth == Some(0) {
+                    return Err(ConfigError::Validation(format!(
+                        "Point {i} field '{name}' max_depth must be greater than 0"
+                    )));
+                }
+            }
+            if name.ends_with('*') && !spec.value().contains("$.") {
+                return Err(ConfigError::Validation(format!(
+                    "Point {i} field '{name}' must flatten a JSONPath expression"
+                )));
+            }
+        }
+
+        // Validate expressions in fields, tags and the timestamp
+        let expressions = point
+            .fields
+            .iter()
+            .map(|(name, spec)| (format!("field '{name}'"), spec.value()))
+            .chain(
+                point
+                    .tags
+                    .iter()
+                    .map(|(name, spec)| (format!("tag '{name}'"), spec.as_str())),
+            )
+            .chain(
+                point
+                    .timestamp
+                    .iter()
+                    .map(|spec| ("timestamp".to_string(), spec.as_str())),
+            );
+        for (target, spec) in expressions {
+            if spec.starts_with('=') {
+                validate_condition(spec).map_err(|e| {
+                    ConfigError::Validation(format!("Point {i} {target} is invalid: {e}"))
+                })?;
+            }
+        }
+
         // Validate schedule if present
         if let Some(schedule) = &point.schedule {
             validate_cron_schedule(schedule)?;
         }
+
+        // Validate condition if present
+        if let Some(condition) = &point.when {
+            validate_condition(condition).map_err(|e| {
+                ConfigError::Validation(format!("Point {i} when condition is invalid: {e}"))
+            })?;
+        }
+
+        // Validate foreach JSONPath if present
+        if let Some(foreach) = &point.foreach {
+            if !foreach.contains("$.") {
+                return Err(ConfigError::Validation(format!(
+                    "Point {i} foreach must be a JSONPath expression"
+                )));
+            }
+            validate_jsonpath(foreach)?;
+        }
+
+        // Validate timestamp extraction if present
+        if let Some(timestamp) = &point.timestamp
+            && timestamp.contains("$.")
+            && !timestamp.starts_with('=')
+        {
+            validate_jsonpath(timestamp)?;
+        }
+        if let Some(format) = &point.timestamp_format {
+            format
+                .parse::<TimestampFormat>()
+                .map_err(|e| ConfigError::Validation(format!("Point {i} timestamp_format: {e}")))?;
+        }
+        if let Some(timezone) = &point.timezone {
+            timezone
+                .parse::<TimestampZone>()
+                .map_err(|e| ConfigError::Validation(format!("Point {i} timezone: {e}")))?;
+        }
+
+        // Compile the point as the bridge does, which also checks the
+        // JSONPaths of tags and HTTP content
+        PointPlan::compile(point)?;
     }
 
-    // Validate TLS file paths if present
-    if let Some(cafile) = &config.mqtt.cafile
-        && !cafile.exists()
+    // Validate HTTP server address if present
+    if let Some(server) = &config.server
+        && server.listen.parse::<std::net::SocketAddr>().is_err()
     {
-        return Err(ConfigError::FileNotFound(
-            cafile.to_string_lossy().to_string(),
+        return Err(ConfigError::Validation(format!(
+            "Server listen address is invalid: {}",
+            server.listen
+        )));
+    }
+
+    Ok(())
+}
+
+/// Validate a single MQTT broker connection.
+fn validate_mqtt(mqtt: &MqttConfig) -> Result<(), ConfigError> {
+    for endpoint in mqtt.endpoints() {
+        validate_endpoint(&endpoint)?;
+    }
+    if mqtt.reconnect.initial_delay_ms == 0 {
+        return Err(ConfigError::Validation(
+            "MQTT reconnect initial_delay_ms must be greater than 0".into(),
+        ));
+    }
+    if mqtt.reconnect.max_delay_ms < mqtt.reconnect.initial_delay_ms {
+        return Err(ConfigError::Validation(
+            "MQTT reconnect max_delay_ms must not be less than initial_delay_ms".into(),
+        ));
+    }
+
+    let password_sources = [
+        mqtt.password.is_some(),
+        mqtt.password_file.is_some(),
+        mqtt.jwt.is_some(),
+    ];
+    if password_sources.iter().filter(|&&set| set).count() > 1 {
+        return Err(ConfigError::Validation(
+            "MQTT password, password_file and jwt are mutually exclusive".into(),
+        ));
+    }
+    if mqtt.protocol == MqttProtocol::V3
+        && mqtt.username.is_none()
+        && password_sources.contains(&true)
+    {
+        return Err(ConfigError::Validation(
+            "MQTT username is required with a password for protocol v3".into(),
+        ));
+    }
+    if let Some(jwt) = &mqtt.jwt
+        && jwt.expiry_secs == 0
+    {
+        return Err(ConfigError::Validation(
+            "MQTT jwt expiry_secs must be greater than 0".into(),
+        ));
+    }
+
+    if !mqtt.clean_session && mqtt.client_id.is_none() {
+        return Err(ConfigError::Validation(
+            "MQTT client_id is required when clean_session is false".into(),
+        ));
+    }
+    if mqtt.client_id.as_deref() == Some("") {
+        return Err(ConfigError::Validation(
+            "MQTT client_id cannot be empty".into(),
+        ));
+    }
+    if mqtt.protocol != MqttProtocol::V5 && mqtt.session_expiry_secs.is_some() {
+        return Err(ConfigError::Validation(
+            "MQTT session_expiry_secs requires protocol v5".into(),
         ));
     }
-    if let Some(certfile) = &config.mqtt.certfile
-        && !certfile.exists()
+    if let Some(group) = &mqtt.share_group
+        && !is_valid_share_group(group)
+    {
+        return Err(ConfigError::Validation(format!(
+            "MQTT share_group '{group}' must be non-empty and not contain '/', '+' or '#'"
+        )));
+    }
+
+    // Validate credential file paths if present
+    if let Some(password_file) = &mqtt.password_file
+        && !password_file.exists()
     {
         return Err(ConfigError::FileNotFound(
-            certfile.to_string_lossy().to_string(),
+            password_file.to_string_lossy().to_string(),
         ));
     }
-    if let Some(keyfile) = &config.mqtt.keyfile
-        && !keyfile.exists()
+    if let Some(jwt) = &mqtt.jwt
+        && !jwt.keyfile.exists()
     {
         return Err(ConfigError::FileNotFound(
-            keyfile.to_string_lossy().to_string(),
+            jwt.keyfile.to_string_lossy().to_string(),
+        ));
+    }
+
+    // Validate TLS settings
+    validate_client_cert("MQTT", mqtt.certfile.as_deref(), mqtt.keyfile.as_deref())?;
+    let tls_settings =
+        mqtt.certfile.is_some() || mqtt.tls_verify_name.is_some() || !mqtt.alpn.is_empty();
+    if tls_settings && !mqtt.uses_tls() && !mqtt.host.starts_with("wss://") {
+        return Err(ConfigError::Validation(
+            "MQTT certfile, tls_verify_name and alpn require tls or cafile".into(),
         ));
     }
+    if let Some(name) = &mqtt.tls_verify_name
+        && name.is_empty()
+    {
+        return Err(ConfigError::Validation(
+            "MQTT tls_verify_name cannot be empty".into(),
+        ));
+    }
+    if mqtt.alpn.iter().any(|p| p.is_empty() || p.len() > 255) {
+        return Err(ConfigError::Validation(
+            "MQTT alpn protocols must be between 1 and 255 bytes".into(),
+        ));
+    }
+    validate_tls_files(
+        mqtt.cafile.as_deref(),
+        mqtt.certfile.as_deref(),
+        mqtt.keyfile.as_deref(),
+    )
+}
+
+/// Validate TLS file paths if present.
+fn validate_tls_files(
+    cafile: Option<&Path>,
+    certfile: Option<&Path>,
+    keyfile: Option<&Path>,
+) -> Result<(), ConfigError> {
+    for path in [cafile, certfile, keyfile].into_iter().flatten() {
+        if !path.exists() {
+            return Err(ConfigError::FileNotFound(
+                path.to_string_lossy().to_string(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Validate that a client certificate and key are given together.
+fn validate_client_cert(
+    section: &str,
+    certfile: Option<&Path>,
+    keyfile: Option<&Path>,
+) -> Result<(), ConfigError> {
+    if certfile.is_some() != keyfile.is_some() {
+        return Err(ConfigError::Validation(format!(
+            "{section} certfile and keyfile must be set together"
+        )));
+    }
+    Ok(())
+}
 
+/// Validate the address and transport of a broker endpoint.
+fn validate_endpoint(mqtt: &MqttConfig) -> Result<(), ConfigError> {
+    if mqtt.host.is_empty() {
+        return Err(ConfigError::Validation("MQTT host cannot be empty".into()));
+    }
+    if mqtt.host.contains("://") && !mqtt.is_websocket() {
+        return Err(ConfigError::Validation(format!(
+            "MQTT host URL '{}' must use the ws:// or wss:// scheme",
+            mqtt.host
+        )));
+    }
+    if !mqtt.is_websocket() && (mqtt.path.is_some() || !mqtt.headers.is_empty()) {
+        return Err(ConfigError::Validation(
+            "MQTT path and headers require the websocket transport".into(),
+        ));
+    }
+    if mqtt.path.is_some() && mqtt.host.contains("://") {
+        return Err(ConfigError::Validation(
+            "MQTT path cannot be combined with a host URL".into(),
+        ));
+    }
+    if let Some(path) = &mqtt.path
+        && !path.starts_with('/')
+    {
+        return Err(ConfigError::Validation(format!(
+            "MQTT path '{path}' must start with '/'"
+        )));
+    }
     Ok(())
 }
 
+/// Check that a shared subscription group name is usable in a topic filter.
+fn is_valid_share_group(group: &str) -> bool {
+    !group.is_empty() && !group.contains(['/', '+', '#'])
+}
+
 /// Validate a JSONPath expression.
 pub fn validate_jsonpath(path: &str) -> Result<(), ConfigError> {
     parse_json_path(path).map_err(|e| ConfigError::InvalidJsonPath(format!("{}: {}", path, e)))?;
@@ -1,5 +1,6 @@
 //! Error types for sinqtt.
 
+use std::time::Duration;
 use thiserror::Error;
 
 /// Top-level error type for sinqtt.
@@ -11,11 +12,17 @@ pub enum SinqttError {
     #[error("MQTT error: {0}")]
     Mqtt(#[from] rumqttc::ClientError),
 
+    #[error("MQTT error: {0}")]
+    MqttV5(Box<rumqttc::v5::ClientError>),
+
     #[error("Connection error: {0}")]
     Connection(Box<rumqttc::ConnectionError>),
 
+    #[error("Credentials error: {0}")]
+    Credentials(String),
+
     #[error("InfluxDB error: {0}")]
-    InfluxDb(String),
+    InfluxDb(#[from] InfluxDbError),
 
     #[error("HTTP error: {0}")]
     Http(#[from] reqwest::Error),
@@ -36,6 +43,63 @@ impl From<rumqttc::ConnectionError> for SinqttError {
     }
 }
 
+impl From<rumqttc::v5::ClientError> for SinqttError {
+    fn from(err: rumqttc::v5::ClientError) -> Self {
+        SinqttError::MqttV5(Box::new(err))
+    }
+}
+
+/// `InfluxDB` write errors, classified by whether a retry can succeed.
+#[derive(Error, Debug)]
+pub enum InfluxDbError {
+    /// The request could not be sent or no response was received.
+    #[error("Connection failed: {0}")]
+    Connection(String),
+
+    /// Throttling or server-side failure that may succeed when retried.
+    #[error("Write failed with status {status}: {message}")]
+    Server {
+        status: u16,
+        message: String,
+        retry_after: Option<Duration>,
+    },
+
+    /// Line protocol rejected by `InfluxDB`, e.g. a field type conflict.
+    #[error("Write rejected with status {status}: {message}")]
+    Rejected {
+        status: u16,
+        message: String,
+        lines: Vec<RejectedLine>,
+    },
+
+    /// Any other client error, e.g. authentication or unknown bucket.
+    #[error("Write failed with status {status}: {message}")]
+    Client { status: u16, message: String },
+
+    /// The background batch writer is no longer running.
+    #[error("Batch writer has stopped")]
+    WriterStopped,
+}
+
+impl InfluxDbError {
+    /// Check if retrying the same request may succeed.
+    #[must_use]
+    pub const fn is_retryable(&self) -> bool {
+        matches!(self, Self::Connection(_) | Self::Server { .. })
+    }
+}
+
+/// A line rejected by `InfluxDB`, as reported in the error response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RejectedLine {
+    /// 1-based line number within the request body, if reported.
+    pub line_number: Option<usize>,
+    /// The offending line protocol, if known.
+    pub line: Option<String>,
+    /// The error message for this line.
+    pub message: String,
+}
+
 /// Configuration parsing and validation errors.
 #[derive(Error, Debug)]
 pub enum ConfigError {
@@ -64,8 +128,9 @@ pub enum ConfigError {
 /// Expression parsing and evaluation errors.
 #[derive(Error, Debug)]
 pub enum ExpressionError {
-    #[error("Invalid expression: {0}")]
-    Parse(String),
+    /// Syntax error, with the 1-based column in the expression text.
+    #[error("Invalid expression at column {column}: {message}")]
+    Parse { column: usize, message: String },
 
     #[error("Evaluation error: {0}")]
     Evaluation(String),
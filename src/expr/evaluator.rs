@@ -1,59 +1,451 @@
-//! Expression evaluation using evalexpr.
+//! Expression evaluation.
+//!
+//! Parsed expressions are compiled once into a tree whose JSONPaths and
+//! variables are numbered, then evaluated against the values of a message.
 
 use crate::error::ExpressionError;
-use evalexpr::{ContextWithMutableVariables, HashMapContext, Value, eval_with_context};
+use regex::Regex;
+use std::cmp::Ordering;
 use std::collections::HashMap;
 
-use super::parser::parse_expression;
+use super::functions::{self, Function};
+use super::parser::{BinaryOp, Expr, UnaryOp, parse};
+use super::value::Value;
 
-/// Evaluate a mathematical expression with the given variables.
-///
-/// The expression should start with `=` (which is stripped before evaluation).
-/// JSONPath expressions in the input are converted to variables.
+/// A parsed expression that can be evaluated repeatedly.
 ///
 /// Supports:
-/// - Basic arithmetic: `+`, `-`, `*`, `/`
-/// - Power: `^` (converted to `**` internally)
-/// - Modulo: `%`
-/// - Parentheses for grouping
-/// - Variables in JSON__name format or $.name JSONPath format
-pub fn evaluate_expression(
-    expression: &str,
-    variables: &HashMap<String, f64>,
-) -> Result<f64, ExpressionError> {
-    // Strip leading `=` and whitespace
-    let expr = expression.trim_start_matches('=').trim();
+/// - Arithmetic: `+`, `-`, `*`, `/`, `%` and power `^`
+/// - Comparisons: `==`, `!=`, `<`, `<=`, `>`, `>=`
+/// - Boolean logic: `&&`, `||`, `!`, evaluating the right operand only if
+///   needed
+/// - String literals in double quotes, concatenated with `+`
+/// - Conditionals: `cond ? a : b` and `if(cond, a, b)`
+/// - Existence checks: `exists($.payload.battery)`
+/// - Built-in functions such as `round(x, n)`
+/// - JSONPaths, including bracket notation like `$.payload['pm2.5']`
+///
+/// Integers are exact during evaluation, falling back to floats on overflow,
+/// but a numeric result is a float unless evaluated with
+/// [`Self::evaluate_int`]. Division by zero is an error.
+#[derive(Debug, Clone)]
+pub struct Expression {
+    /// JSONPaths and variable names, in order of first use.
+    variables: Vec<String>,
+    node: Node,
+}
+
+/// Node of a compiled expression.
+#[derive(Debug, Clone)]
+enum Node {
+    Constant(Value),
+    /// Value of the variable with the given index.
+    Variable(usize),
+    /// Whether the variable with the given index has a value.
+    Exists(usize),
+    Unary(UnaryOp, Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+    Conditional(Box<Node>, Box<Node>, Box<Node>),
+    Call(&'static Function, Vec<Node>),
+    /// `regex_replace` with a precompiled pattern.
+    Replace(Box<Node>, Regex, Box<Node>),
+}
+
+impl Expression {
+    /// Parse an expression. A leading `=` is ignored.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ExpressionError::Parse` with the column of the syntax error,
+    /// unknown function or wrong number of arguments.
+    pub fn parse(text: &str) -> Result<Self, ExpressionError> {
+        let mut compiler = Compiler::default();
+        let node = compiler.compile(parse(text)?);
+        Ok(Self {
+            variables: compiler.variables,
+            node,
+        })
+    }
+
+    /// The JSONPaths and variable names referenced by the expression, in
+    /// order of first use.
+    #[must_use]
+    pub fn variables(&self) -> &[String] {
+        &self.variables
+    }
+
+    /// Evaluate the expression to a JSON value.
+    ///
+    /// `values` holds the value of each of [`Self::variables`], `None` for
+    /// paths missing from the message.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ExpressionError::MissingVariable` if the value of a missing
+    /// path is needed for the result, or `ExpressionError::Evaluation` if
+    /// evaluation fails. Operands skipped by `&&`, `||` and `?:` are not read.
+    pub fn evaluate(
+        &self,
+        values: &[Option<&serde_json::Value>],
+    ) -> Result<serde_json::Value, ExpressionError> {
+        match self.evaluate_value(values)? {
+            Value::Int(i) => Value::Float(i as f64),
+            value => value,
+        }
+        .into_json()
+    }
+
+    /// Evaluate the expression like [`Self::evaluate`], but keep an integer
+    /// result as an integer, with every digit.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Self::evaluate`].
+    pub fn evaluate_int(
+        &self,
+        values: &[Option<&serde_json::Value>],
+    ) -> Result<serde_json::Value, ExpressionError> {
+        self.evaluate_value(values)?.into_json()
+    }
+
+    fn evaluate_value(
+        &self,
+        values: &[Option<&serde_json::Value>],
+    ) -> Result<Value, ExpressionError> {
+        let scope = Scope {
+            variables: &self.variables,
+            values,
+        };
+        scope.evaluate(&self.node)
+    }
 
-    if expr.is_empty() {
-        return Err(ExpressionError::Parse("Empty expression".to_string()));
+    /// Evaluate the expression as a boolean condition.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if evaluation fails or the result is not a boolean.
+    pub fn evaluate_condition(
+        &self,
+        values: &[Option<&serde_json::Value>],
+    ) -> Result<bool, ExpressionError> {
+        match self.evaluate(values)? {
+            serde_json::Value::Bool(b) => Ok(b),
+            result => Err(ExpressionError::Evaluation(format!(
+                "Expected boolean result, got: {}",
+                result
+            ))),
+        }
     }
 
-    // Parse the expression and convert JSONPath to variables
-    let (converted_expr, _) = parse_expression(expr);
+    /// Look up the values of the variables by name.
+    fn values<'a>(
+        &self,
+        variables: &'a HashMap<String, serde_json::Value>,
+    ) -> Vec<Option<&'a serde_json::Value>> {
+        self.variables
+            .iter()
+            .map(|name| variables.get(name))
+            .collect()
+    }
+}
+
+/// Compilation of a syntax tree, numbering its variables.
+#[derive(Default)]
+struct Compiler {
+    variables: Vec<String>,
+}
+
+impl Compiler {
+    /// Index of a variable, adding it on first use.
+    fn variable(&mut self, name: String) -> usize {
+        if let Some(i) = self.variables.iter().position(|v| *v == name) {
+            return i;
+        }
+        self.variables.push(name);
+        self.variables.len() - 1
+    }
 
-    // Build the evaluation context
-    let mut context = HashMapContext::new();
-    for (name, value) in variables {
-        context
-            .set_value(name.clone(), Value::Float(*value))
-            .map_err(|e: evalexpr::EvalexprError| ExpressionError::Evaluation(e.to_string()))?;
+    fn compile(&mut self, expr: Expr) -> Node {
+        match expr {
+            Expr::Integer(n) => Node::Constant(Value::Int(n)),
+            Expr::Number(n) => Node::Constant(Value::Float(n)),
+            Expr::String(s) => Node::Constant(Value::String(s)),
+            Expr::Bool(b) => Node::Constant(Value::Boolean(b)),
+            Expr::Path(name) | Expr::Identifier(name) => Node::Variable(self.variable(name)),
+            Expr::Exists(name) => Node::Exists(self.variable(name)),
+            Expr::Unary(op, operand) => Node::Unary(op, Box::new(self.compile(*operand))),
+            Expr::Binary(op, left, right) => Node::Binary(
+                op,
+                Box::new(self.compile(*left)),
+                Box::new(self.compile(*right)),
+            ),
+            Expr::Conditional(condition, then, otherwise) => Node::Conditional(
+                Box::new(self.compile(*condition)),
+                Box::new(self.compile(*then)),
+                Box::new(self.compile(*otherwise)),
+            ),
+            Expr::Call(function, args) => self.call(function, args),
+        }
     }
 
-    // Evaluate the expression
-    let result = eval_with_context(&converted_expr, &context)
-        .map_err(|e| ExpressionError::Evaluation(e.to_string()))?;
+    /// Compile a call; a literal `regex_replace` pattern is compiled once.
+    fn call(&mut self, function: &'static Function, args: Vec<Expr>) -> Node {
+        let regex = match args.as_slice() {
+            [_, Expr::String(pattern), _] if function.name == "regex_replace" => {
+                Regex::new(pattern).ok()
+            }
+            _ => None,
+        };
+        let args: Vec<Node> = args.into_iter().map(|arg| self.compile(arg)).collect();
+        match (regex, <[Node; 3]>::try_from(args)) {
+            (Some(regex), Ok([input, _, replacement])) => {
+                Node::Replace(Box::new(input), regex, Box::new(replacement))
+            }
+            (_, Ok(args)) => Node::Call(function, args.into()),
+            (_, Err(args)) => Node::Call(function, args),
+        }
+    }
+}
+
+/// Variable values of one evaluation.
+struct Scope<'a> {
+    variables: &'a [String],
+    values: &'a [Option<&'a serde_json::Value>],
+}
+
+impl Scope<'_> {
+    fn evaluate(&self, node: &Node) -> Result<Value, ExpressionError> {
+        match node {
+            Node::Constant(value) => Ok(value.clone()),
+            Node::Variable(i) => match self.values.get(*i).copied().flatten() {
+                Some(value) => Ok(Value::from_json(value)),
+                None => Err(ExpressionError::MissingVariable(self.variables[*i].clone())),
+            },
+            Node::Exists(i) => Ok(Value::Boolean(
+                self.values.get(*i).copied().flatten().is_some(),
+            )),
+            Node::Unary(UnaryOp::Negate, operand) => {
+                let value = self.evaluate(operand)?;
+                match value {
+                    Value::Int(x) => Ok(x
+                        .checked_neg()
+                        .map_or(Value::Float(-(x as f64)), Value::Int)),
+                    Value::Float(x) => Ok(Value::Float(-x)),
+                    _ => Err(operand_error("-", &value)),
+                }
+            }
+            Node::Unary(UnaryOp::Not, operand) => Ok(Value::Boolean(!self.boolean(operand, "!")?)),
+            Node::Binary(BinaryOp::And, left, right) => self.logical(left, right, false, "&&"),
+            Node::Binary(BinaryOp::Or, left, right) => self.logical(left, right, true, "||"),
+            Node::Binary(op, left, right) => {
+                binary(*op, self.evaluate(left)?, self.evaluate(right)?)
+            }
+            Node::Conditional(condition, then, otherwise) => {
+                if self.boolean(condition, "?")? {
+                    self.evaluate(then)
+                } else {
+                    self.evaluate(otherwise)
+                }
+            }
+            Node::Call(function, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.evaluate(arg))
+                    .collect::<Result<Vec<_>, _>>()?;
+                (function.call)(&args)
+            }
+            Node::Replace(input, regex, replacement) => {
+                functions::replace_all(regex, &self.evaluate(input)?, &self.evaluate(replacement)?)
+            }
+        }
+    }
+
+    /// Evaluate `&&` or `||`, whose result is `decisive` as soon as one
+    /// operand is.
+    ///
+    /// The right operand is only evaluated if the left one does not decide
+    /// the result. A left operand reading a missing value may still be
+    /// decided by the right one, so `missing == 1 || true` is true.
+    fn logical(
+        &self,
+        left: &Node,
+        right: &Node,
+        decisive: bool,
+        operator: &str,
+    ) -> Result<Value, ExpressionError> {
+        match self.boolean(left, operator) {
+            Ok(value) if value == decisive => Ok(Value::Boolean(decisive)),
+            Ok(_) => self.boolean(right, operator).map(Value::Boolean),
+            Err(missing @ ExpressionError::MissingVariable(_)) => {
+                match self.boolean(right, operator) {
+                    Ok(value) if value == decisive => Ok(Value::Boolean(decisive)),
+                    _ => Err(missing),
+                }
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Evaluate an operand of a boolean operator.
+    fn boolean(&self, node: &Node, operator: &str) -> Result<bool, ExpressionError> {
+        let value = self.evaluate(node)?;
+        value
+            .as_bool()
+            .ok_or_else(|| operand_error(operator, &value))
+    }
+}
+
+fn operand_error(operator: &str, value: &Value) -> ExpressionError {
+    ExpressionError::Evaluation(format!(
+        "'{operator}' cannot be applied to a {}",
+        value.type_name()
+    ))
+}
 
-    // Convert result to f64
+/// Apply an arithmetic or comparison operator.
+///
+/// Integer operands give integer results, except for `/`, negative powers
+/// and overflow.
+fn binary(op: BinaryOp, left: Value, right: Value) -> Result<Value, ExpressionError> {
+    match (op, &left, &right) {
+        (BinaryOp::Equal, ..) => Ok(Value::Boolean(equal(&left, &right))),
+        (BinaryOp::NotEqual, ..) => Ok(Value::Boolean(!equal(&left, &right))),
+        (BinaryOp::Add, Value::String(a), Value::String(b)) => Ok(Value::String(format!("{a}{b}"))),
+        (
+            BinaryOp::Less | BinaryOp::LessEqual | BinaryOp::Greater | BinaryOp::GreaterEqual,
+            Value::String(a),
+            Value::String(b),
+        ) => Ok(Value::Boolean(compare(op, a.partial_cmp(b)))),
+        (_, Value::Int(a), Value::Int(b)) if op != BinaryOp::Divide => integer(op, *a, *b),
+        _ => match (left.as_number(), right.as_number()) {
+            (Some(a), Some(b)) => float(op, a, b),
+            _ => Err(ExpressionError::Evaluation(format!(
+                "'{}' cannot be applied to a {} and a {}",
+                op.symbol(),
+                left.type_name(),
+                right.type_name()
+            ))),
+        },
+    }
+}
+
+/// Compare values for equality, integers and floats by their value.
+fn equal(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Int(_), Value::Float(_)) | (Value::Float(_), Value::Int(_)) => {
+            left.as_number() == right.as_number()
+        }
+        _ => left == right,
+    }
+}
+
+/// Apply an operator to integers, as floats on overflow.
+fn integer(op: BinaryOp, a: i64, b: i64) -> Result<Value, ExpressionError> {
+    let result = match op {
+        BinaryOp::Add => a.checked_add(b),
+        BinaryOp::Subtract => a.checked_sub(b),
+        BinaryOp::Multiply => a.checked_mul(b),
+        BinaryOp::Modulo if b == 0 => return Err(division_by_zero(op)),
+        BinaryOp::Modulo => a.checked_rem(b),
+        BinaryOp::Power => match u32::try_from(b) {
+            Ok(exponent) => a.checked_pow(exponent),
+            Err(_) => return float(op, a as f64, b as f64),
+        },
+        _ => return Ok(Value::Boolean(compare(op, Some(a.cmp(&b))))),
+    };
     match result {
-        Value::Float(f) => Ok(f),
-        Value::Int(i) => Ok(i as f64),
-        _ => Err(ExpressionError::Evaluation(format!(
-            "Expected numeric result, got: {:?}",
+        Some(result) => Ok(Value::Int(result)),
+        None => float(op, a as f64, b as f64),
+    }
+}
+
+/// Apply an operator to floats.
+fn float(op: BinaryOp, a: f64, b: f64) -> Result<Value, ExpressionError> {
+    let result = match op {
+        BinaryOp::Add => a + b,
+        BinaryOp::Subtract => a - b,
+        BinaryOp::Multiply => a * b,
+        BinaryOp::Divide | BinaryOp::Modulo if b == 0.0 => return Err(division_by_zero(op)),
+        BinaryOp::Divide => a / b,
+        BinaryOp::Modulo => a % b,
+        BinaryOp::Power => a.powf(b),
+        _ => return Ok(Value::Boolean(compare(op, a.partial_cmp(&b)))),
+    };
+    Ok(Value::Float(result))
+}
+
+fn division_by_zero(op: BinaryOp) -> ExpressionError {
+    ExpressionError::Evaluation(format!("division by zero in '{}'", op.symbol()))
+}
+
+/// Check an ordering against a comparison operator; unordered values, such
+/// as NaN, never compare.
+fn compare(op: BinaryOp, ordering: Option<Ordering>) -> bool {
+    match (op, ordering) {
+        (BinaryOp::Less, Some(ordering)) => ordering.is_lt(),
+        (BinaryOp::LessEqual, Some(ordering)) => ordering.is_le(),
+        (BinaryOp::Greater, Some(ordering)) => ordering.is_gt(),
+        (BinaryOp::GreaterEqual, Some(ordering)) => ordering.is_ge(),
+        _ => false,
+    }
+}
+
+/// Evaluate a numeric expression with the given variables.
+///
+/// Variables are keyed by the JSONPath or variable name as written in the
+/// expression, e.g. `$.payload.temp`.
+pub fn evaluate_expression(
+    expression: &str,
+    variables: &HashMap<String, f64>,
+) -> Result<f64, ExpressionError> {
+    let variables = variables
+        .iter()
+        .map(|(name, value)| (name.clone(), serde_json::Value::from(*value)))
+        .collect();
+
+    match evaluate(expression, &variables)? {
+        serde_json::Value::Number(n) => n.as_f64().ok_or_else(|| {
+            ExpressionError::Evaluation(format!("Expected numeric result, got: {n}"))
+        }),
+        result => Err(ExpressionError::Evaluation(format!(
+            "Expected numeric result, got: {}",
             result
         ))),
     }
 }
 
+/// Evaluate an expression with the given variables to a JSON value.
+///
+/// Variables are keyed by the JSONPath or variable name as written in the
+/// expression; paths missing from the message are left out. See
+/// [`Expression`] for the supported syntax.
+///
+/// Reading the value of a missing path is an error, unless `&&`, `||` or
+/// `?:` do not need the operand containing it.
+pub fn evaluate(
+    expression: &str,
+    variables: &HashMap<String, serde_json::Value>,
+) -> Result<serde_json::Value, ExpressionError> {
+    let expression = Expression::parse(expression)?;
+    expression.evaluate(&expression.values(variables))
+}
+
+/// Evaluate a boolean condition with the given variables.
+///
+/// Supports the same syntax as [`evaluate`].
+pub fn evaluate_condition(
+    condition: &str,
+    variables: &HashMap<String, serde_json::Value>,
+) -> Result<bool, ExpressionError> {
+    let expression = Expression::parse(condition)?;
+    expression.evaluate_condition(&expression.values(variables))
+}
+
+/// Check that an expression or condition is syntactically valid.
+pub fn validate_condition(condition: &str) -> Result<(), ExpressionError> {
+    Expression::parse(condition).map(|_| ())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -96,7 +488,7 @@ mod tests {
     #[test]
     fn test_expression_with_variable() {
         let mut vars = HashMap::new();
-        vars.insert("JSON__payload".to_string(), 100.0);
+        vars.insert("$.payload".to_string(), 100.0);
         let result = evaluate_expression("= $.payload * 2", &vars).unwrap();
         assert!((result - 200.0).abs() < f64::EPSILON);
     }
@@ -104,7 +496,7 @@ mod tests {
     #[test]
     fn test_expression_with_nested_variable() {
         let mut vars = HashMap::new();
-        vars.insert("JSON__payload_value".to_string(), 50.0);
+        vars.insert("$.payload.value".to_string(), 50.0);
         let result = evaluate_expression("= $.payload.value + 100", &vars).unwrap();
         assert!((result - 150.0).abs() < f64::EPSILON);
     }
@@ -112,7 +504,7 @@ mod tests {
     #[test]
     fn test_celsius_to_fahrenheit_0c() {
         let mut vars = HashMap::new();
-        vars.insert("JSON__payload".to_string(), 0.0);
+        vars.insert("$.payload".to_string(), 0.0);
         let result = evaluate_expression("= 32 + ($.payload * 9 / 5)", &vars).unwrap();
         assert!((result - 32.0).abs() < f64::EPSILON);
     }
@@ -120,7 +512,7 @@ mod tests {
     #[test]
     fn test_celsius_to_fahrenheit_100c() {
         let mut vars = HashMap::new();
-        vars.insert("JSON__payload".to_string(), 100.0);
+        vars.insert("$.payload".to_string(), 100.0);
         let result = evaluate_expression("= 32 + ($.payload * 9 / 5)", &vars).unwrap();
         assert!((result - 212.0).abs() < f64::EPSILON);
     }
@@ -128,7 +520,7 @@ mod tests {
     #[test]
     fn test_celsius_to_fahrenheit_37c() {
         let mut vars = HashMap::new();
-        vars.insert("JSON__payload".to_string(), 37.0);
+        vars.insert("$.payload".to_string(), 37.0);
         let result = evaluate_expression("= 32 + ($.payload * 9 / 5)", &vars).unwrap();
         assert!((result - 98.6).abs() < 0.01);
     }
@@ -182,4 +574,298 @@ mod tests {
         let result = evaluate_expression("= (1 + 2", &vars);
         assert!(result.is_err());
     }
+
+    fn condition_vars(pairs: &[(&str, serde_json::Value)]) -> HashMap<String, serde_json::Value> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_condition_string_equality() {
+        let vars = condition_vars(&[("$.payload.type", serde_json::json!("env"))]);
+        assert!(evaluate_condition(r#"$.payload.type == "env""#, &vars).unwrap());
+        assert!(!evaluate_condition(r#"$.payload.type == "power""#, &vars).unwrap());
+    }
+
+    #[test]
+    fn test_condition_comparison_and_logic() {
+        let vars = condition_vars(&[
+            ("$.payload.temp", serde_json::json!(21.5)),
+            ("$.payload.valid", serde_json::json!(true)),
+        ]);
+        assert!(evaluate_condition("$.payload.temp > 20 && $.payload.valid", &vars).unwrap());
+        assert!(evaluate_condition("$.payload.temp > 30 || $.payload.valid", &vars).unwrap());
+        assert!(!evaluate_condition("$.payload.temp * 2 <= 40", &vars).unwrap());
+    }
+
+    #[test]
+    fn test_condition_exists() {
+        let vars = condition_vars(&[("$.payload.battery", serde_json::json!(87))]);
+        assert!(evaluate_condition("exists($.payload.battery)", &vars).unwrap());
+        assert!(!evaluate_condition("exists($.payload.voltage)", &vars).unwrap());
+        assert!(
+            evaluate_condition(
+                "!exists($.payload.voltage) && $.payload.battery == 87",
+                &vars
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_condition_missing_variable() {
+        let vars = HashMap::new();
+        assert!(evaluate_condition(r#"$.payload.type == "env""#, &vars).is_err());
+    }
+
+    #[test]
+    fn test_condition_non_boolean_result() {
+        let vars = HashMap::new();
+        assert!(evaluate_condition("1 + 2", &vars).is_err());
+    }
+
+    #[test]
+    fn test_validate_condition() {
+        assert!(validate_condition(r#"$.payload.type == "env" && exists($.payload.t)"#).is_ok());
+        assert!(validate_condition("$.payload.type == (").is_err());
+        assert!(validate_condition("  ").is_err());
+    }
+
+    #[test]
+    fn test_evaluate_string_result() {
+        let vars = condition_vars(&[("$.payload.rssi", serde_json::json!(-60))]);
+        assert_eq!(
+            evaluate(r#"= $.payload.rssi > -70 ? "good" : "poor""#, &vars).unwrap(),
+            serde_json::json!("good")
+        );
+    }
+
+    #[test]
+    fn test_evaluate_concatenation() {
+        let vars = condition_vars(&[("$.payload.id", serde_json::json!("a1"))]);
+        assert_eq!(
+            evaluate(r#"= "sensor-" + $.payload.id"#, &vars).unwrap(),
+            serde_json::json!("sensor-a1")
+        );
+    }
+
+    #[test]
+    fn test_evaluate_numbers_are_floats() {
+        let vars = condition_vars(&[("$.payload", serde_json::json!(1))]);
+        assert_eq!(
+            evaluate("= $.payload + 1", &vars).unwrap(),
+            serde_json::json!(2.0)
+        );
+        assert_eq!(
+            evaluate("= $.payload / 2", &vars).unwrap(),
+            serde_json::json!(0.5)
+        );
+        assert!(evaluate_condition("$.payload == 1", &vars).unwrap());
+        assert!(evaluate_condition("$.payload < 1.5", &vars).unwrap());
+    }
+
+    #[test]
+    fn test_evaluate_int() {
+        let expression = Expression::parse("= $.payload * 2 ^ 3 % 5").unwrap();
+        let one = serde_json::json!(1);
+        assert_eq!(
+            expression.evaluate_int(&[Some(&one)]).unwrap(),
+            serde_json::json!(3)
+        );
+
+        // Integers above 2^53 keep every digit
+        let expression = Expression::parse("= $.counter + 2").unwrap();
+        let counter = serde_json::json!(9_007_199_254_740_993_i64);
+        assert_eq!(
+            expression.evaluate_int(&[Some(&counter)]).unwrap(),
+            serde_json::json!(9_007_199_254_740_995_i64)
+        );
+
+        // Division, negative powers and overflow give floats
+        let eval_int = |text| Expression::parse(text).unwrap().evaluate_int(&[]).unwrap();
+        assert_eq!(eval_int("= 1 / 2"), serde_json::json!(0.5));
+        assert_eq!(eval_int("= 2 ^ -1"), serde_json::json!(0.5));
+        assert_eq!(
+            eval_int("= 9223372036854775807 + 1"),
+            serde_json::json!(9_223_372_036_854_775_808.0)
+        );
+    }
+
+    #[test]
+    fn test_division_by_zero() {
+        let vars = condition_vars(&[("$.payload", serde_json::json!(0))]);
+        for expression in [
+            "= 1 / $.payload",
+            "= 1 % $.payload",
+            "= 1.5 / $.payload",
+            "= 1.5 % $.payload",
+        ] {
+            assert!(
+                matches!(
+                    evaluate(expression, &vars),
+                    Err(ExpressionError::Evaluation(message)) if message.starts_with("division by zero")
+                ),
+                "{expression}"
+            );
+        }
+        assert!(evaluate("= log(2, 1)", &vars).is_err());
+    }
+
+    #[test]
+    fn test_regex_replace_patterns() {
+        let vars = condition_vars(&[
+            ("$.payload.id", serde_json::json!("a-b")),
+            ("$.payload.pattern", serde_json::json!("-")),
+        ]);
+        assert_eq!(
+            evaluate(
+                r#"= regex_replace($.payload.id, $.payload.pattern, "_")"#,
+                &vars
+            )
+            .unwrap(),
+            serde_json::json!("a_b")
+        );
+        assert_eq!(
+            evaluate(r#"= regex_replace($.payload.id, "([a-z])", "<$1>")"#, &vars).unwrap(),
+            serde_json::json!("<a>-<b>")
+        );
+        let literal = Expression::parse(r#"= regex_replace($.payload.id, "-", "_")"#).unwrap();
+        assert!(matches!(literal.node, Node::Replace(..)));
+    }
+
+    #[test]
+    fn test_expression_ternary() {
+        let mut vars = HashMap::new();
+        vars.insert("$.payload".to_string(), 5.0);
+        let result = evaluate_expression("= $.payload > 3 ? $.payload * 2 : 0", &vars).unwrap();
+        assert!((result - 10.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_distinct_paths_do_not_collide() {
+        let vars = condition_vars(&[
+            ("$.payload.a_b", serde_json::json!(1)),
+            ("$.payload.a.b", serde_json::json!(2)),
+        ]);
+        assert_eq!(
+            evaluate("= $.payload.a_b * 10 + $.payload.a.b", &vars).unwrap(),
+            serde_json::json!(12.0)
+        );
+    }
+
+    #[test]
+    fn test_power_of_nested_parentheses() {
+        let vars = HashMap::new();
+        let result = evaluate_expression("= (2 * (1 + 2)) ^ 2", &vars).unwrap();
+        assert!((result - 36.0).abs() < f64::EPSILON);
+        let result = evaluate_expression("= -2 ^ 2 + 2 ^ -1", &vars).unwrap();
+        assert!((result + 3.5).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_string_escapes() {
+        let vars = HashMap::new();
+        assert_eq!(
+            evaluate(r#"= "say \"hi\" \\ " + "ok""#, &vars).unwrap(),
+            serde_json::json!(r#"say "hi" \ ok"#)
+        );
+    }
+
+    #[test]
+    fn test_compiled_expression_variables() {
+        let expression =
+            Expression::parse("= $.payload['pm2.5'] > 10 && exists($.payload.x)").unwrap();
+        assert_eq!(
+            expression.variables(),
+            ["$.payload['pm2.5']", "$.payload.x"]
+        );
+
+        let pm = serde_json::json!(12);
+        assert!(
+            expression
+                .evaluate_condition(&[Some(&pm), Some(&pm)])
+                .unwrap()
+        );
+        assert!(!expression.evaluate_condition(&[Some(&pm), None]).unwrap());
+        assert!(!expression.evaluate_condition(&[None, None]).unwrap());
+        assert!(matches!(
+            expression.evaluate(&[None, Some(&pm)]),
+            Err(ExpressionError::MissingVariable(name)) if name == "$.payload['pm2.5']"
+        ));
+    }
+
+    #[test]
+    fn test_parse_error_has_column() {
+        let err = validate_condition("= $.payload.type == (").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid expression at column 22: unexpected end of expression"
+        );
+    }
+
+    #[test]
+    fn test_unknown_function_is_rejected() {
+        let err = validate_condition("= len($.payload.id) > 2").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid expression at column 3: unknown function 'len'"
+        );
+        let err = validate_condition("= round($.payload, 1, 2)").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid expression at column 3: round() takes 1 or 2 arguments, got 3"
+        );
+    }
+
+    #[test]
+    fn test_evaluate_if() {
+        let vars = condition_vars(&[
+            ("$.payload.full", serde_json::json!(false)),
+            ("$.payload.level", serde_json::json!(40)),
+        ]);
+        assert_eq!(
+            evaluate("= if($.payload.full, 100, $.payload.level)", &vars).unwrap(),
+            serde_json::json!(40.0)
+        );
+    }
+
+    #[test]
+    fn test_type_errors() {
+        let vars = condition_vars(&[("$.payload.id", serde_json::json!("a1"))]);
+        assert_eq!(
+            evaluate("= $.payload.id * 2", &vars)
+                .unwrap_err()
+                .to_string(),
+            "Evaluation error: '*' cannot be applied to a string and a number"
+        );
+        assert!(evaluate_condition("$.payload.id && true", &vars).is_err());
+        assert!(evaluate_condition(r#"$.payload.id < "b1""#, &vars).unwrap());
+    }
+
+    #[test]
+    fn test_missing_value_behind_short_circuit() {
+        let vars = HashMap::new();
+        assert!(evaluate_condition("!exists(x) || x == 1", &vars).unwrap());
+        assert!(!evaluate_condition("exists(x) && x == 1", &vars).unwrap());
+        assert!(evaluate_condition("missing == 1 || true", &vars).unwrap());
+        assert!(!evaluate_condition("missing == 1 && false", &vars).unwrap());
+        assert!(evaluate_condition("true || missing == 1", &vars).unwrap());
+        assert!(matches!(
+            evaluate_condition("missing == 1 || false", &vars),
+            Err(ExpressionError::MissingVariable(name)) if name == "missing"
+        ));
+        assert_eq!(
+            evaluate("= exists(x) ? x : 0", &vars).unwrap(),
+            serde_json::json!(0.0)
+        );
+
+        let vars = condition_vars(&[("x", serde_json::json!(1))]);
+        assert!(evaluate_condition("!exists(x) || x == 1", &vars).unwrap());
+        assert_eq!(
+            evaluate("= exists(x) ? x : 0", &vars).unwrap(),
+            serde_json::json!(1.0)
+        );
+    }
 }
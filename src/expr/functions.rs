@@ -0,0 +1,599 @@
+//! Built-in functions available in expressions.
+//!
+//! Arguments that must be integers, such as bit positions, are also accepted
+//! as floats without a fractional part. `abs`, `clamp`, `min` and `max` of
+//! integers are integers; the other numeric functions return floats.
+//! The number of arguments is checked when the expression is parsed.
+
+use super::value::Value;
+use crate::error::ExpressionError;
+use regex::Regex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A built-in function and the number of arguments it takes.
+#[derive(Debug)]
+pub(super) struct Function {
+    pub(super) name: &'static str,
+    pub(super) min_args: usize,
+    /// `usize::MAX` for functions taking any number of arguments.
+    pub(super) max_args: usize,
+    pub(super) call: fn(&[Value]) -> Result<Value, ExpressionError>,
+}
+
+impl PartialEq for Function {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl Function {
+    /// Describe the accepted number of arguments, e.g. `1 or 2 arguments`.
+    pub(super) fn arity(&self) -> String {
+        let (count, last) = match (self.min_args, self.max_args) {
+            (min, max) if min == max => (min.to_string(), max),
+            (min, usize::MAX) => (format!("at least {min}"), min),
+            (min, max) if max == min + 1 => (format!("{min} or {max}"), max),
+            (min, max) => (format!("{min} to {max}"), max),
+        };
+        let noun = if last == 1 { "argument" } else { "arguments" };
+        format!("{count} {noun}")
+    }
+
+    /// Check if the function takes the given number of arguments.
+    pub(super) fn accepts(&self, count: usize) -> bool {
+        (self.min_args..=self.max_args).contains(&count)
+    }
+}
+
+/// Look up a built-in function by name.
+pub(super) fn lookup(name: &str) -> Option<&'static Function> {
+    FUNCTIONS.iter().find(|function| function.name == name)
+}
+
+/// Every built-in function.
+static FUNCTIONS: &[Function] = &[
+    Function {
+        name: "round",
+        min_args: 1,
+        max_args: 2,
+        call: |args| {
+            let x = number(args, 0, "round")?;
+            let digits = match args.len() {
+                2 => integer(args, 1, "round")?,
+                _ => 0,
+            };
+            let factor =
+                10f64.powi(i32::try_from(digits).map_err(|_| fail("round: invalid digits"))?);
+            Ok(Value::Float((x * factor).round() / factor))
+        },
+    },
+    Function {
+        name: "clamp",
+        min_args: 3,
+        max_args: 3,
+        call: |args| {
+            if let Some(&[x, lo, hi]) = integers(args).as_deref() {
+                if lo > hi {
+                    return Err(fail("clamp: lower bound is greater than upper bound"));
+                }
+                return Ok(Value::Int(x.clamp(lo, hi)));
+            }
+            let (x, lo, hi) = (
+                number(args, 0, "clamp")?,
+                number(args, 1, "clamp")?,
+                number(args, 2, "clamp")?,
+            );
+            if lo > hi {
+                return Err(fail("clamp: lower bound is greater than upper bound"));
+            }
+            Ok(Value::Float(x.clamp(lo, hi)))
+        },
+    },
+    Function {
+        name: "min",
+        min_args: 1,
+        max_args: usize::MAX,
+        call: |args| {
+            if let Some(values) = integers(args) {
+                return Ok(Value::Int(values.into_iter().fold(i64::MAX, i64::min)));
+            }
+            let values = numbers(args, "min")?;
+            Ok(Value::Float(
+                values.into_iter().fold(f64::INFINITY, f64::min),
+            ))
+        },
+    },
+    Function {
+        name: "max",
+        min_args: 1,
+        max_args: usize::MAX,
+        call: |args| {
+            if let Some(values) = integers(args) {
+                return Ok(Value::Int(values.into_iter().fold(i64::MIN, i64::max)));
+            }
+            let values = numbers(args, "max")?;
+            Ok(Value::Float(
+                values.into_iter().fold(f64::NEG_INFINITY, f64::max),
+            ))
+        },
+    },
+    Function {
+        name: "abs",
+        min_args: 1,
+        max_args: 1,
+        call: |args| match args[0] {
+            Value::Int(x) => Ok(x
+                .checked_abs()
+                .map_or(Value::Float((x as f64).abs()), Value::Int)),
+            _ => Ok(Value::Float(number(args, 0, "abs")?.abs())),
+        },
+    },
+    Function {
+        name: "sqrt",
+        min_args: 1,
+        max_args: 1,
+        call: |args| {
+            let x = number(args, 0, "sqrt")?;
+            if x < 0.0 {
+                return Err(fail("sqrt: negative argument"));
+            }
+            Ok(Value::Float(x.sqrt()))
+        },
+    },
+    Function {
+        name: "log",
+        min_args: 1,
+        max_args: 2,
+        call: |args| {
+            let x = number(args, 0, "log")?;
+            if x <= 0.0 {
+                return Err(fail("log: argument must be positive"));
+            }
+            match args.len() {
+                2 => Ok(Value::Float(x.log(number(args, 1, "log")?))),
+                _ => Ok(Value::Float(x.ln())),
+            }
+        },
+    },
+    Function {
+        name: "dew_point",
+        min_args: 2,
+        max_args: 2,
+        call: |args| {
+            let (t, rh) = (number(args, 0, "dew_point")?, number(args, 1, "dew_point")?);
+            if rh <= 0.0 || rh > 100.0 {
+                return Err(fail("dew_point: humidity must be in (0, 100]"));
+            }
+            Ok(Value::Float(dew_point(t, rh)))
+        },
+    },
+    Function {
+        name: "heat_index",
+        min_args: 2,
+        max_args: 2,
+        call: |args| {
+            let (t, rh) = (
+                number(args, 0, "heat_index")?,
+                number(args, 1, "heat_index")?,
+            );
+            Ok(Value::Float(heat_index(t, rh)))
+        },
+    },
+    Function {
+        name: "convert",
+        min_args: 3,
+        max_args: 3,
+        call: |args| {
+            let x = number(args, 0, "convert")?;
+            let (from, to) = (string(args, 1, "convert")?, string(args, 2, "convert")?);
+            convert(x, from, to).map(Value::Float)
+        },
+    },
+    Function {
+        name: "bit",
+        min_args: 2,
+        max_args: 2,
+        call: |args| {
+            let x = integer(args, 0, "bit")?;
+            let n = bit_position(integer(args, 1, "bit")?, "bit")?;
+            Ok(Value::Boolean((x >> n) & 1 == 1))
+        },
+    },
+    Function {
+        name: "bits",
+        min_args: 3,
+        max_args: 3,
+        call: |args| {
+            let x = integer(args, 0, "bits")?;
+            let from = bit_position(integer(args, 1, "bits")?, "bits")?;
+            let len = integer(args, 2, "bits")?;
+            if !(1..=64).contains(&len) || i64::from(from) + len > 64 {
+                return Err(fail("bits: field must lie within 64 bits"));
+            }
+            let mask = u64::MAX >> (64 - len);
+            i64::try_from((x as u64 >> from) & mask)
+                .map(Value::Int)
+                .map_err(|_| fail("bits: value does not fit into a signed 64-bit integer"))
+        },
+    },
+    Function {
+        name: "hex_to_int",
+        min_args: 1,
+        max_args: 1,
+        call: |args| {
+            let s = string(args, 0, "hex_to_int")?;
+            let digits = s.trim();
+            let digits = digits
+                .strip_prefix("0x")
+                .or_else(|| digits.strip_prefix("0X"))
+                .unwrap_or(digits);
+            let value = u64::from_str_radix(digits, 16)
+                .map_err(|_| fail(&format!("hex_to_int: invalid hex string '{s}'")))?;
+            i64::try_from(value)
+                .map(Value::Int)
+                .map_err(|_| fail(&format!("hex_to_int: '{s}' is out of range")))
+        },
+    },
+    Function {
+        name: "lower",
+        min_args: 1,
+        max_args: 1,
+        call: |args| Ok(Value::String(string(args, 0, "lower")?.to_lowercase())),
+    },
+    Function {
+        name: "upper",
+        min_args: 1,
+        max_args: 1,
+        call: |args| Ok(Value::String(string(args, 0, "upper")?.to_uppercase())),
+    },
+    Function {
+        name: "substr",
+        min_args: 2,
+        max_args: 3,
+        call: |args| {
+            let s = string(args, 0, "substr")?;
+            let start = usize::try_from(integer(args, 1, "substr")?)
+                .map_err(|_| fail("substr: start must not be negative"))?;
+            let chars = s.chars().skip(start);
+            let result = match args.len() {
+                3 => {
+                    let len = usize::try_from(integer(args, 2, "substr")?)
+                        .map_err(|_| fail("substr: length must not be negative"))?;
+                    chars.take(len).collect()
+                }
+                _ => chars.collect(),
+            };
+            Ok(Value::String(result))
+        },
+    },
+    Function {
+        name: "regex_replace",
+        min_args: 3,
+        max_args: 3,
+        call: |args| {
+            let pattern = string(args, 1, "regex_replace")?;
+            let regex = Regex::new(pattern)
+                .map_err(|e| fail(&format!("regex_replace: invalid pattern: {e}")))?;
+            replace_all(&regex, &args[0], &args[2])
+        },
+    },
+    Function {
+        name: "now",
+        min_args: 0,
+        max_args: 0,
+        call: |_| {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs_f64())
+                .unwrap_or(0.0);
+            Ok(Value::Float(now))
+        },
+    },
+];
+
+/// Replace all matches of `regex` for `regex_replace`.
+///
+/// Calls with a literal pattern compile it once and call this directly.
+pub(super) fn replace_all(
+    regex: &Regex,
+    s: &Value,
+    replacement: &Value,
+) -> Result<Value, ExpressionError> {
+    let s = s
+        .as_str()
+        .ok_or_else(|| fail("regex_replace: argument 1 must be a string"))?;
+    let replacement = replacement
+        .as_str()
+        .ok_or_else(|| fail("regex_replace: argument 3 must be a string"))?;
+    Ok(Value::String(
+        regex.replace_all(s, replacement).into_owned(),
+    ))
+}
+
+fn fail(message: &str) -> ExpressionError {
+    ExpressionError::Evaluation(message.to_string())
+}
+
+fn number(args: &[Value], index: usize, name: &str) -> Result<f64, ExpressionError> {
+    args[index]
+        .as_number()
+        .ok_or_else(|| fail(&format!("{name}: argument {} must be a number", index + 1)))
+}
+
+fn numbers(args: &[Value], name: &str) -> Result<Vec<f64>, ExpressionError> {
+    (0..args.len()).map(|i| number(args, i, name)).collect()
+}
+
+/// The arguments as integers, if all of them are.
+fn integers(args: &[Value]) -> Option<Vec<i64>> {
+    args.iter()
+        .map(|arg| match arg {
+            Value::Int(i) => Some(*i),
+            _ => None,
+        })
+        .collect()
+}
+
+fn integer(args: &[Value], index: usize, name: &str) -> Result<i64, ExpressionError> {
+    if let Value::Int(i) = args[index] {
+        return Ok(i);
+    }
+    let x = number(args, index, name)?;
+    if !x.is_finite() || x.fract() != 0.0 {
+        return Err(fail(&format!(
+            "{name}: argument {} must be an integer",
+            index + 1
+        )));
+    }
+    Ok(x as i64)
+}
+
+fn string<'a>(args: &'a [Value], index: usize, name: &str) -> Result<&'a str, ExpressionError> {
+    args[index]
+        .as_str()
+        .ok_or_else(|| fail(&format!("{name}: argument {} must be a string", index + 1)))
+}
+
+fn bit_position(n: i64, name: &str) -> Result<u32, ExpressionError> {
+    u32::try_from(n)
+        .ok()
+        .filter(|&n| n < 64)
+        .ok_or_else(|| fail(&format!("{name}: bit position must be between 0 and 63")))
+}
+
+/// Dew point in °C from temperature in °C and relative humidity in %,
+/// using the Magnus formula.
+fn dew_point(t: f64, rh: f64) -> f64 {
+    const A: f64 = 17.62;
+    const B: f64 = 243.12;
+    let gamma = (rh / 100.0).ln() + A * t / (B + t);
+    B * gamma / (A - gamma)
+}
+
+/// Heat index in °C from temperature in °C and relative humidity in %,
+/// using the NOAA Rothfusz regression.
+fn heat_index(t: f64, rh: f64) -> f64 {
+    let f = t * 9.0 / 5.0 + 32.0;
+    let simple = 0.5 * (f + 61.0 + (f - 68.0) * 1.2 + rh * 0.094);
+    let hi = if (simple + f) / 2.0 < 80.0 {
+        simple
+    } else {
+        let mut hi = -42.379 + 2.049_015_23 * f + 10.143_331_27 * rh
+            - 0.224_755_41 * f * rh
+            - 0.006_837_83 * f * f
+            - 0.054_817_17 * rh * rh
+            + 0.001_228_74 * f * f * rh
+            + 0.000_852_82 * f * rh * rh
+            - 0.000_001_99 * f * f * rh * rh;
+        if rh < 13.0 && (80.0..=112.0).contains(&f) {
+            hi -= (13.0 - rh) / 4.0 * ((17.0 - (f - 95.0).abs()) / 17.0).sqrt();
+        } else if rh > 85.0 && (80.0..=87.0).contains(&f) {
+            hi += (rh - 85.0) / 10.0 * ((87.0 - f) / 5.0);
+        }
+        hi
+    };
+    (hi - 32.0) * 5.0 / 9.0
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Quantity {
+    Temperature,
+    Pressure,
+    Speed,
+    Length,
+    Energy,
+}
+
+/// Look up a unit as its quantity and factor to the base unit.
+///
+/// Temperatures are handled separately as their scales have offsets.
+fn unit(name: &str) -> Option<(Quantity, f64)> {
+    use Quantity::*;
+    let unit = match name.to_lowercase().as_str() {
+        "c" | "k" | "f" => (Temperature, 1.0),
+        "pa" => (Pressure, 1.0),
+        "hpa" | "mbar" => (Pressure, 100.0),
+        "kpa" => (Pressure, 1000.0),
+        "bar" => (Pressure, 100_000.0),
+        "psi" => (Pressure, 6_894.757_293_168),
+        "inhg" => (Pressure, 3_386.389),
+        "mmhg" => (Pressure, 133.322_387_415),
+        "m/s" => (Speed, 1.0),
+        "km/h" => (Speed, 1.0 / 3.6),
+        "mph" => (Speed, 0.447_04),
+        "kn" => (Speed, 1852.0 / 3600.0),
+        "mm" => (Length, 0.001),
+        "cm" => (Length, 0.01),
+        "m" => (Length, 1.0),
+        "km" => (Length, 1000.0),
+        "in" => (Length, 0.0254),
+        "ft" => (Length, 0.3048),
+        "mi" => (Length, 1_609.344),
+        "j" => (Energy, 1.0),
+        "wh" => (Energy, 3600.0),
+        "kwh" => (Energy, 3_600_000.0),
+        _ => return None,
+    };
+    Some(unit)
+}
+
+/// Convert a value between units of the same quantity.
+fn convert(x: f64, from: &str, to: &str) -> Result<f64, ExpressionError> {
+    let unknown = |name: &str| fail(&format!("convert: unknown unit '{name}'"));
+    let (from_quantity, from_factor) = unit(from).ok_or_else(|| unknown(from))?;
+    let (to_quantity, to_factor) = unit(to).ok_or_else(|| unknown(to))?;
+    if from_quantity != to_quantity {
+        return Err(fail(&format!("convert: cannot convert '{from}' to '{to}'")));
+    }
+
+    if from_quantity == Quantity::Temperature {
+        let kelvin = match from.to_lowercase().as_str() {
+            "c" => x + 273.15,
+            "f" => (x - 32.0) * 5.0 / 9.0 + 273.15,
+            _ => x,
+        };
+        return Ok(match to.to_lowercase().as_str() {
+            "c" => kelvin - 273.15,
+            "f" => (kelvin - 273.15) * 9.0 / 5.0 + 32.0,
+            _ => kelvin,
+        });
+    }
+    Ok(x * from_factor / to_factor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::Expression;
+    use serde_json::json;
+
+    /// Evaluate with integer results kept, to check which functions return integers.
+    fn eval(expr: &str) -> serde_json::Value {
+        Expression::parse(expr).unwrap().evaluate_int(&[]).unwrap()
+    }
+
+    fn eval_number(expr: &str) -> f64 {
+        eval(expr).as_f64().unwrap()
+    }
+
+    fn fails(expr: &str) -> bool {
+        Expression::parse(expr)
+            .and_then(|expression| expression.evaluate(&[]))
+            .is_err()
+    }
+
+    #[test]
+    fn test_round() {
+        assert_eq!(eval_number("round(2.567, 2.0)"), 2.57);
+        assert_eq!(eval_number("round(2.5)"), 3.0);
+        assert_eq!(eval_number("round(1234.0, -2.0)"), 1200.0);
+        assert!(fails("round(2.5, 0.5)"));
+    }
+
+    #[test]
+    fn test_clamp_min_max_abs() {
+        assert_eq!(eval_number("clamp(120.0, 0.0, 100.0)"), 100.0);
+        assert_eq!(eval_number("clamp(-5.0, 0.0, 100.0)"), 0.0);
+        assert!(fails("clamp(1.0, 10.0, 0.0)"));
+        assert_eq!(eval_number("min(3.0, 1.5, 2.0)"), 1.5);
+        assert_eq!(eval_number("max(3.0, 1.5, 2.0)"), 3.0);
+        assert_eq!(eval_number("abs(-4.5)"), 4.5);
+        assert_eq!(eval("clamp(120, 0, 100)"), json!(100));
+        assert_eq!(eval("min(3, 1, 2)"), json!(1));
+        assert_eq!(eval("max(3, 1.5)"), json!(3.0));
+        assert_eq!(eval("abs(-4)"), json!(4));
+    }
+
+    #[test]
+    fn test_sqrt_and_log() {
+        assert_eq!(eval_number("sqrt(16.0)"), 4.0);
+        assert!(fails("sqrt(-1.0)"));
+        assert!((eval_number("log(2.718281828459045)") - 1.0).abs() < 1e-12);
+        assert!((eval_number("log(1000.0, 10.0)") - 3.0).abs() < 1e-12);
+        assert!(fails("log(0.0)"));
+    }
+
+    #[test]
+    fn test_dew_point() {
+        // 20 °C at 50 % relative humidity has a dew point of about 9.3 °C
+        assert!((eval_number("dew_point(20.0, 50.0)") - 9.26).abs() < 0.05);
+        assert!((eval_number("dew_point(25.0, 100.0)") - 25.0).abs() < 1e-9);
+        assert!(fails("dew_point(20.0, 0.0)"));
+    }
+
+    #[test]
+    fn test_heat_index() {
+        // 32 °C at 70 % relative humidity feels like about 41 °C
+        assert!((eval_number("heat_index(32.0, 70.0)") - 40.9).abs() < 0.5);
+        // Below the regression range the simple formula stays close to the temperature
+        assert!((eval_number("heat_index(20.0, 50.0)") - 19.6).abs() < 0.5);
+    }
+
+    #[test]
+    fn test_convert() {
+        assert!((eval_number(r#"convert(100.0, "c", "f")"#) - 212.0).abs() < 1e-9);
+        assert!((eval_number(r#"convert(32.0, "F", "C")"#)).abs() < 1e-9);
+        assert!((eval_number(r#"convert(0.0, "c", "k")"#) - 273.15).abs() < 1e-9);
+        assert!((eval_number(r#"convert(1013.25, "hpa", "inhg")"#) - 29.92).abs() < 0.01);
+        assert!((eval_number(r#"convert(36.0, "km/h", "m/s")"#) - 10.0).abs() < 1e-9);
+        assert!((eval_number(r#"convert(1.5, "kwh", "wh")"#) - 1500.0).abs() < 1e-9);
+        assert!(fails(r#"convert(1.0, "c", "m")"#));
+        assert!(fails(r#"convert(1.0, "c", "parsec")"#));
+    }
+
+    #[test]
+    fn test_bit_and_bits() {
+        assert_eq!(eval("bit(5.0, 0.0)"), json!(true));
+        assert_eq!(eval("bit(5.0, 1.0)"), json!(false));
+        assert_eq!(eval_number("bits(180.0, 4.0, 4.0)"), 11.0);
+        assert_eq!(eval_number("bits(180.0, 0.0, 4.0)"), 4.0);
+        assert_eq!(eval("bits(180, 4, 4)"), json!(11));
+        assert!(fails("bit(5.0, 64.0)"));
+        assert!(fails("bits(5.0, 60.0, 8.0)"));
+        assert!(fails("bit(5.5, 0.0)"));
+    }
+
+    #[test]
+    fn test_hex_to_int() {
+        assert_eq!(eval_number(r#"hex_to_int("0x1F")"#), 31.0);
+        assert_eq!(eval_number(r#"hex_to_int("ff")"#), 255.0);
+        // Integers above 2^53 keep every digit
+        assert_eq!(eval(r#"hex_to_int("0x7FFFFFFFFFFFFFFF")"#), json!(i64::MAX));
+        assert!(fails(r#"hex_to_int("ffffffffffffffff")"#));
+        assert!(fails(r#"hex_to_int("xyz")"#));
+    }
+
+    #[test]
+    fn test_string_functions() {
+        assert_eq!(eval(r#"lower("ABC")"#), json!("abc"));
+        assert_eq!(eval(r#"upper("abc")"#), json!("ABC"));
+        assert_eq!(eval(r#"substr("sensor-42", 7.0)"#), json!("42"));
+        assert_eq!(eval(r#"substr("sensor-42", 0.0, 6.0)"#), json!("sensor"));
+        assert_eq!(eval(r#"regex_replace("a-b-c", "-", "_")"#), json!("a_b_c"));
+        assert!(fails(r#"regex_replace("a", "(", "")"#));
+    }
+
+    #[test]
+    fn test_now() {
+        let now = eval_number("now()");
+        assert!(now > 1_600_000_000.0);
+        assert!(fails("now(1.0)"));
+    }
+
+    #[test]
+    fn test_argument_count() {
+        assert!(fails("abs(1.0, 2.0)"));
+        assert!(fails("clamp(1.0)"));
+        assert!(fails("min()"));
+    }
+
+    #[test]
+    fn test_arity() {
+        assert_eq!(lookup("abs").unwrap().arity(), "1 argument");
+        assert_eq!(lookup("round").unwrap().arity(), "1 or 2 arguments");
+        assert_eq!(lookup("clamp").unwrap().arity(), "3 arguments");
+        assert_eq!(lookup("now").unwrap().arity(), "0 arguments");
+        assert_eq!(lookup("max").unwrap().arity(), "at least 1 argument");
+        assert!(lookup("pow").is_none());
+    }
+}
@@ -0,0 +1,452 @@
+//! Expression tokenizer.
+//!
+//! Splits expression text into tokens, keeping the byte offset of each token
+//! for error messages. JSONPaths are single tokens, including bracket
+//! selectors such as `$.payload['pm2.5']` and filters such as
+//! `$.items[?@.id == 'a'].value`.
+
+use crate::error::ExpressionError;
+
+/// A token of the expression language.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Token {
+    /// Number without fraction or exponent that fits into an `i64`.
+    Integer(i64),
+    Number(f64),
+    String(String),
+    /// JSONPath as written, e.g. `$.payload.temp`.
+    Path(String),
+    /// Identifier or function name, e.g. `round`.
+    Identifier(String),
+    LeftParen,
+    RightParen,
+    Comma,
+    Question,
+    Colon,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    Caret,
+    Not,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    And,
+    Or,
+    End,
+}
+
+impl Token {
+    /// Describe the token for error messages.
+    pub(super) fn describe(&self) -> String {
+        let symbol = match self {
+            Token::Integer(n) => return format!("number {n}"),
+            Token::Number(n) => return format!("number {n}"),
+            Token::String(s) => return format!("string \"{s}\""),
+            Token::Path(p) => return format!("path '{p}'"),
+            Token::Identifier(i) => return format!("'{i}'"),
+            Token::End => return "end of expression".to_string(),
+            Token::LeftParen => "(",
+            Token::RightParen => ")",
+            Token::Comma => ",",
+            Token::Question => "?",
+            Token::Colon => ":",
+            Token::Plus => "+",
+            Token::Minus => "-",
+            Token::Star => "*",
+            Token::Slash => "/",
+            Token::Percent => "%",
+            Token::Caret => "^",
+            Token::Not => "!",
+            Token::Equal => "==",
+            Token::NotEqual => "!=",
+            Token::Less => "<",
+            Token::LessEqual => "<=",
+            Token::Greater => ">",
+            Token::GreaterEqual => ">=",
+            Token::And => "&&",
+            Token::Or => "||",
+        };
+        format!("'{symbol}'")
+    }
+}
+
+/// Create a parse error at a byte offset of the expression text.
+pub(super) fn error_at(text: &str, offset: usize, message: impl Into<String>) -> ExpressionError {
+    let column = text
+        .get(..offset)
+        .map_or(offset, |prefix| prefix.chars().count())
+        + 1;
+    ExpressionError::Parse {
+        column,
+        message: message.into(),
+    }
+}
+
+/// Split expression text into tokens with their byte offsets.
+///
+/// A single leading `=` marking the text as an expression is skipped. The
+/// token list always ends with [`Token::End`].
+pub(super) fn tokenize(text: &str) -> Result<Vec<(Token, usize)>, ExpressionError> {
+    let mut lexer = Lexer { text, pos: 0 };
+    lexer.skip_whitespace();
+    if lexer.peek() == Some('=') && lexer.peek_at(1) != Some('=') {
+        lexer.pos += 1;
+    }
+
+    let mut tokens = Vec::new();
+    loop {
+        lexer.skip_whitespace();
+        let start = lexer.pos;
+        let Some(c) = lexer.peek() else {
+            tokens.push((Token::End, start));
+            return Ok(tokens);
+        };
+        let token = match c {
+            '0'..='9' => lexer.number()?,
+            '"' => lexer.string()?,
+            '$' => lexer.path()?,
+            c if is_identifier_start(c) => lexer.identifier(),
+            _ => lexer.symbol()?,
+        };
+        tokens.push((token, start));
+    }
+}
+
+struct Lexer<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl Lexer<'_> {
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn peek_at(&self, n: usize) -> Option<char> {
+        self.text[self.pos..].chars().nth(n)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn eat_while(&mut self, f: impl Fn(char) -> bool) {
+        while self.peek().is_some_and(&f) {
+            self.bump();
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.eat_while(char::is_whitespace);
+    }
+
+    fn error(&self, offset: usize, message: impl Into<String>) -> ExpressionError {
+        error_at(self.text, offset, message)
+    }
+
+    fn number(&mut self) -> Result<Token, ExpressionError> {
+        let start = self.pos;
+        self.eat_while(|c| c.is_ascii_digit());
+        if self.peek() == Some('.') && self.peek_at(1).is_some_and(|c| c.is_ascii_digit()) {
+            self.bump();
+            self.eat_while(|c| c.is_ascii_digit());
+        }
+        if matches!(self.peek(), Some('e' | 'E')) {
+            let digits_at = match self.peek_at(1) {
+                Some('+' | '-') => 2,
+                _ => 1,
+            };
+            if self.peek_at(digits_at).is_some_and(|c| c.is_ascii_digit()) {
+                for _ in 0..digits_at {
+                    self.bump();
+                }
+                self.eat_while(|c| c.is_ascii_digit());
+            }
+        }
+        if self
+            .peek()
+            .is_some_and(|c| is_identifier_char(c) || c == '.')
+        {
+            return Err(self.error(self.pos, "invalid number"));
+        }
+
+        let literal = &self.text[start..self.pos];
+        if let Ok(n) = literal.parse::<i64>() {
+            return Ok(Token::Integer(n));
+        }
+        match literal.parse::<f64>() {
+            Ok(n) if n.is_finite() => Ok(Token::Number(n)),
+            _ => Err(self.error(start, format!("number {literal} is out of range"))),
+        }
+    }
+
+    fn string(&mut self) -> Result<Token, ExpressionError> {
+        let start = self.pos;
+        self.bump();
+        let mut value = String::new();
+        loop {
+            match self.bump() {
+                Some('"') => return Ok(Token::String(value)),
+                Some('\\') => {
+                    let escape = self.pos;
+                    match self.bump() {
+                        Some(c @ ('"' | '\\')) => value.push(c),
+                        Some(c) => {
+                            return Err(self.error(escape - 1, format!("invalid escape '\\{c}'")));
+                        }
+                        None => break,
+                    }
+                }
+                Some(c) => value.push(c),
+                None => break,
+            }
+        }
+        Err(self.error(start, "unterminated string"))
+    }
+
+    /// Read a JSONPath: `$` followed by `.name`, `..name`, `.*` and
+    /// bracketed selectors.
+    fn path(&mut self) -> Result<Token, ExpressionError> {
+        let start = self.pos;
+        self.bump();
+        loop {
+            match self.peek() {
+                Some('.') => {
+                    let dot = self.pos;
+                    self.bump();
+                    if self.peek() == Some('.') {
+                        self.bump();
+                        if self.peek() == Some('[') {
+                            continue;
+                        }
+                    }
+                    if self.peek() == Some('*') {
+                        self.bump();
+                    } else if self.peek().is_some_and(is_identifier_char) {
+                        self.eat_while(is_identifier_char);
+                    } else {
+                        return Err(self.error(dot, "expected member name after '.'"));
+                    }
+                }
+                Some('[') => self.bracket()?,
+                _ => break,
+            }
+        }
+
+        let path = &self.text[start..self.pos];
+        jsonpath_rust::parser::parse_json_path(path)
+            .map_err(|_| self.error(start, format!("invalid JSONPath '{path}'")))?;
+        Ok(Token::Path(path.to_string()))
+    }
+
+    /// Skip a bracketed selector, including nested brackets and quotes.
+    fn bracket(&mut self) -> Result<(), ExpressionError> {
+        let start = self.pos;
+        let mut depth = 0usize;
+        while let Some(c) = self.bump() {
+            match c {
+                '[' => depth += 1,
+                ']' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                }
+                '\'' | '"' => {
+                    let quote = self.pos - 1;
+                    loop {
+                        match self.bump() {
+                            Some('\\') => {
+                                self.bump();
+                            }
+                            Some(q) if q == c => break,
+                            Some(_) => {}
+                            None => return Err(self.error(quote, "unterminated string")),
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Err(self.error(start, "unclosed '['"))
+    }
+
+    fn identifier(&mut self) -> Token {
+        let start = self.pos;
+        self.eat_while(is_identifier_char);
+        Token::Identifier(self.text[start..self.pos].to_string())
+    }
+
+    fn symbol(&mut self) -> Result<Token, ExpressionError> {
+        let start = self.pos;
+        let c = self.bump().unwrap_or_default();
+        let next = self.peek();
+        let (token, double) = match (c, next) {
+            ('=', Some('=')) => (Token::Equal, true),
+            ('!', Some('=')) => (Token::NotEqual, true),
+            ('<', Some('=')) => (Token::LessEqual, true),
+            ('>', Some('=')) => (Token::GreaterEqual, true),
+            ('&', Some('&')) => (Token::And, true),
+            ('|', Some('|')) => (Token::Or, true),
+            ('!', _) => (Token::Not, false),
+            ('<', _) => (Token::Less, false),
+            ('>', _) => (Token::Greater, false),
+            ('(', _) => (Token::LeftParen, false),
+            (')', _) => (Token::RightParen, false),
+            (',', _) => (Token::Comma, false),
+            ('?', _) => (Token::Question, false),
+            (':', _) => (Token::Colon, false),
+            ('+', _) => (Token::Plus, false),
+            ('-', _) => (Token::Minus, false),
+            ('*', _) => (Token::Star, false),
+            ('/', _) => (Token::Slash, false),
+            ('%', _) => (Token::Percent, false),
+            ('^', _) => (Token::Caret, false),
+            ('=', _) => {
+                return Err(self.error(start, "unexpected '=', use '==' for comparison"));
+            }
+            _ => return Err(self.error(start, format!("unexpected character '{c}'"))),
+        };
+        if double {
+            self.bump();
+        }
+        Ok(token)
+    }
+}
+
+fn is_identifier_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(text: &str) -> Vec<Token> {
+        tokenize(text)
+            .unwrap()
+            .into_iter()
+            .map(|(token, _)| token)
+            .collect()
+    }
+
+    fn column(text: &str) -> usize {
+        match tokenize(text) {
+            Err(ExpressionError::Parse { column, .. }) => column,
+            other => panic!("expected parse error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_tokenize_arithmetic() {
+        assert_eq!(
+            tokens("= 32 + $.payload * 1.8"),
+            vec![
+                Token::Integer(32),
+                Token::Plus,
+                Token::Path("$.payload".into()),
+                Token::Star,
+                Token::Number(1.8),
+                Token::End,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_offsets() {
+        let offsets: Vec<usize> = tokenize("= a >= 10")
+            .unwrap()
+            .into_iter()
+            .map(|(_, offset)| offset)
+            .collect();
+        assert_eq!(offsets, vec![2, 4, 7, 9]);
+    }
+
+    #[test]
+    fn test_tokenize_bracket_paths() {
+        assert_eq!(
+            tokens("$.payload['pm2.5'] + $.topic[1]"),
+            vec![
+                Token::Path("$.payload['pm2.5']".into()),
+                Token::Plus,
+                Token::Path("$.topic[1]".into()),
+                Token::End,
+            ]
+        );
+        assert_eq!(
+            tokens("$.payload.items[?@.id == 'a]'].value"),
+            vec![
+                Token::Path("$.payload.items[?@.id == 'a]'].value".into()),
+                Token::End,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_strings_and_identifiers() {
+        assert_eq!(
+            tokens(r#"round(x, 2) == "a \"b\"""#),
+            vec![
+                Token::Identifier("round".into()),
+                Token::LeftParen,
+                Token::Identifier("x".into()),
+                Token::Comma,
+                Token::Integer(2),
+                Token::RightParen,
+                Token::Equal,
+                Token::String("a \"b\"".into()),
+                Token::End,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_integers() {
+        assert_eq!(
+            tokens("7 + 7.0 + 9223372036854775808"),
+            vec![
+                Token::Integer(7),
+                Token::Plus,
+                Token::Number(7.0),
+                Token::Plus,
+                Token::Number(9_223_372_036_854_775_808.0),
+                Token::End,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_scientific_numbers() {
+        assert_eq!(
+            tokens("1e3 - 2.5E-2"),
+            vec![
+                Token::Number(1000.0),
+                Token::Minus,
+                Token::Number(0.025),
+                Token::End,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_errors() {
+        assert_eq!(column("= 1 + \"abc"), 7);
+        assert_eq!(column("= $.payload['a' + 1"), 12);
+        assert_eq!(column("= $.payload. + 1"), 12);
+        assert_eq!(column("= 1 # 2"), 5);
+        assert_eq!(column("= a = 1"), 5);
+        assert_eq!(column("= 12abc"), 5);
+    }
+}
@@ -1,7 +1,11 @@
 //! Expression parsing and evaluation module.
 
 mod evaluator;
+mod functions;
+mod lexer;
 mod parser;
+mod value;
 
-pub use evaluator::evaluate_expression;
-pub use parser::{extract_variables, jsonpath_to_variable, parse_expression, variable_to_jsonpath};
+pub use evaluator::{
+    Expression, evaluate, evaluate_condition, evaluate_expression, validate_condition,
+};
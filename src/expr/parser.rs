@@ -1,178 +1,537 @@
-//! Expression parsing utilities.
+//! Expression parser.
 //!
-//! Converts JSONPath expressions to variable names and back.
+//! Builds an abstract syntax tree from the tokens of an expression. From
+//! lowest to highest precedence, the grammar is:
+//!
+//! - Conditional: `cond ? a : b`, nesting to the right
+//! - Logical or: `||`
+//! - Logical and: `&&`
+//! - Comparison: `==`, `!=`, `<`, `<=`, `>`, `>=`
+//! - Additive: `+`, `-`
+//! - Multiplicative: `*`, `/`, `%`
+//! - Unary: `-`, `!`
+//! - Power: `^`, nesting to the right
+//! - Primary: numbers, strings, `true`, `false`, JSONPaths, identifiers,
+//!   function calls and parenthesized expressions
+//!
+//! Calls must name a built-in function and pass it a valid number of
+//! arguments; `if(cond, a, b)` is parsed as a conditional. Literal
+//! `regex_replace` patterns are checked as well.
 
+use super::functions::{Function, lookup};
+use super::lexer::{Token, error_at, tokenize};
+use crate::error::ExpressionError;
 use regex::Regex;
-use std::sync::LazyLock;
 
-/// Regex to match JSONPath expressions in text.
-/// Matches patterns like $.payload, $.payload.temp, $.topic[1], etc.
-static JSONPATH_REGEX: LazyLock<Regex> =
-    LazyLock::new(|| Regex::new(r"\$\.[\w.\[\]']+").expect("invalid JSONPATH_REGEX pattern"));
+/// Node of the syntax tree of an expression.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Expr {
+    Integer(i64),
+    Number(f64),
+    String(String),
+    Bool(bool),
+    /// JSONPath as written, e.g. `$.payload['pm2.5']`.
+    Path(String),
+    /// Variable name, e.g. `x`.
+    Identifier(String),
+    /// Existence check of a JSONPath or variable, `exists($.payload.battery)`.
+    Exists(String),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+    Call(&'static Function, Vec<Expr>),
+}
 
-/// Regex to match power operator patterns like "2 ^ 3" or "var ^ 5".
-static POWER_REGEX: LazyLock<Regex> = LazyLock::new(|| {
-    Regex::new(r"(\w+|\d+\.?\d*|\([^)]+\))\s*\^\s*(\w+|\d+\.?\d*|\([^)]+\))")
-        .expect("invalid POWER_REGEX pattern")
-});
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum UnaryOp {
+    Negate,
+    Not,
+}
 
-/// Convert a JSONPath expression to a variable name.
-///
-/// This matches the Python implementation exactly:
-/// - `$` → `JSON_`
-/// - `.` → `_`
-///
-/// Example: `$.payload.temperature` -> `JSON__payload_temperature`
-/// Example: `$.topic[1]` -> `JSON__topic[1]`
-#[must_use]
-pub fn jsonpath_to_variable(path: &str) -> String {
-    path.replace('$', "JSON_").replace('.', "_")
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Power,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    And,
+    Or,
 }
 
-/// Convert a variable name back to a JSONPath expression.
-///
-/// This is the reverse of `jsonpath_to_variable`:
-/// - `JSON_` → `$`
-/// - `_` → `.`
-///
-/// Note: This is a simple string replacement and may not perfectly
-/// reconstruct complex paths with array indices.
-///
-/// Example: `JSON__payload_temperature` -> `$.payload.temperature`
-#[must_use]
-pub fn variable_to_jsonpath(var: &str) -> String {
-    var.replace("JSON_", "$").replace('_', ".")
+impl BinaryOp {
+    /// The operator as written in expressions.
+    pub(super) fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Subtract => "-",
+            BinaryOp::Multiply => "*",
+            BinaryOp::Divide => "/",
+            BinaryOp::Modulo => "%",
+            BinaryOp::Power => "^",
+            BinaryOp::Equal => "==",
+            BinaryOp::NotEqual => "!=",
+            BinaryOp::Less => "<",
+            BinaryOp::LessEqual => "<=",
+            BinaryOp::Greater => ">",
+            BinaryOp::GreaterEqual => ">=",
+            BinaryOp::And => "&&",
+            BinaryOp::Or => "||",
+        }
+    }
 }
 
-/// Parse an expression and extract JSONPath variables.
+/// Parse expression text into a syntax tree.
 ///
-/// Returns the expression with JSONPath converted to variables,
-/// and a list of the original JSONPath expressions found.
-///
-/// Also converts `^` (power) to `math::pow` for evalexpr compatibility.
-#[must_use]
-pub fn parse_expression(text: &str) -> (String, Vec<String>) {
-    let mut result = text.to_string();
-    let mut jsonpaths: Vec<String> = Vec::new();
+/// A leading `=` is ignored. Errors report the column of the offending token.
+pub(super) fn parse(text: &str) -> Result<Expr, ExpressionError> {
+    let tokens = tokenize(text)?;
+    let mut parser = Parser {
+        text,
+        tokens,
+        pos: 0,
+    };
 
-    // Extract all JSONPath expressions
-    for cap in JSONPATH_REGEX.find_iter(text) {
-        let jsonpath = cap.as_str();
-        jsonpaths.push(jsonpath.to_string());
+    if parser.peek() == &Token::End {
+        return Err(parser.error("empty expression"));
+    }
+    let expr = parser.conditional()?;
+    match parser.peek() {
+        Token::End => Ok(expr),
+        Token::RightParen => Err(parser.error("unmatched ')'")),
+        token => Err(parser.error(format!("unexpected {}", token.describe()))),
     }
+}
 
-    // Sort by length descending to replace longer paths first
-    // This prevents $.payload from being replaced before $.payload.offset
-    jsonpaths.sort_by_key(|s| std::cmp::Reverse(s.len()));
+struct Parser<'a> {
+    text: &'a str,
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
 
-    // Convert JSONPath to variables
-    for jsonpath in &jsonpaths {
-        let variable = jsonpath_to_variable(jsonpath);
-        result = result.replace(jsonpath, &variable);
+impl Parser<'_> {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].0
     }
 
-    // Convert ^ (power) to math::pow for evalexpr
-    // e.g., "2 ^ 3" becomes "math::pow(2, 3)"
-    result = convert_power_operator(&result);
+    fn offset(&self) -> usize {
+        self.tokens[self.pos].1
+    }
 
-    (result, jsonpaths)
-}
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.pos].0.clone();
+        if token != Token::End {
+            self.pos += 1;
+        }
+        token
+    }
 
-/// Convert ^ operator to math::pow() function calls.
-/// e.g., "2 ^ 3" becomes "math::pow(2, 3)"
-fn convert_power_operator(expr: &str) -> String {
-    let mut result = expr.to_string();
-    while let Some(cap) = POWER_REGEX.captures(&result) {
-        // These capture groups are guaranteed to exist after a successful match
-        let Some(full_match) = cap.get(0) else { break };
-        let Some(base) = cap.get(1) else { break };
-        let Some(exp) = cap.get(2) else { break };
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == token {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
 
-        let replacement = format!("math::pow({}, {})", base.as_str(), exp.as_str());
-        result = result.replacen(full_match.as_str(), &replacement, 1);
+    /// Error at the current token.
+    fn error(&self, message: impl Into<String>) -> ExpressionError {
+        error_at(self.text, self.offset(), message)
     }
 
-    result
-}
+    fn conditional(&mut self) -> Result<Expr, ExpressionError> {
+        let condition = self.binary(0)?;
+        let question = self.offset();
+        if !self.eat(&Token::Question) {
+            return Ok(condition);
+        }
+        let then = self.conditional()?;
+        if !self.eat(&Token::Colon) {
+            return Err(error_at(
+                self.text,
+                question,
+                format!("missing ':' for '?', found {}", self.peek().describe()),
+            ));
+        }
+        let otherwise = self.conditional()?;
+        Ok(Expr::Conditional(
+            Box::new(condition),
+            Box::new(then),
+            Box::new(otherwise),
+        ))
+    }
 
-/// Extract variable names from an expression.
-///
-/// Returns a list of `JSON__` prefixed variable names found in the expression.
-#[must_use]
-pub fn extract_variables(text: &str) -> Vec<String> {
-    let (_, jsonpaths) = parse_expression(text);
-    jsonpaths.iter().map(|p| jsonpath_to_variable(p)).collect()
+    /// Parse left-associative binary operators from the given precedence level up.
+    fn binary(&mut self, level: usize) -> Result<Expr, ExpressionError> {
+        const LEVELS: &[&[(Token, BinaryOp)]] = &[
+            &[(Token::Or, BinaryOp::Or)],
+            &[(Token::And, BinaryOp::And)],
+            &[
+                (Token::Equal, BinaryOp::Equal),
+                (Token::NotEqual, BinaryOp::NotEqual),
+                (Token::Less, BinaryOp::Less),
+                (Token::LessEqual, BinaryOp::LessEqual),
+                (Token::Greater, BinaryOp::Greater),
+                (Token::GreaterEqual, BinaryOp::GreaterEqual),
+            ],
+            &[
+                (Token::Plus, BinaryOp::Add),
+                (Token::Minus, BinaryOp::Subtract),
+            ],
+            &[
+                (Token::Star, BinaryOp::Multiply),
+                (Token::Slash, BinaryOp::Divide),
+                (Token::Percent, BinaryOp::Modulo),
+            ],
+        ];
+
+        let Some(operators) = LEVELS.get(level) else {
+            return self.unary();
+        };
+        let mut left = self.binary(level + 1)?;
+        while let Some((_, op)) = operators.iter().find(|(token, _)| token == self.peek()) {
+            self.advance();
+            let right = self.binary(level + 1)?;
+            left = Expr::Binary(*op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ExpressionError> {
+        let op = match self.peek() {
+            Token::Minus => UnaryOp::Negate,
+            Token::Not => UnaryOp::Not,
+            _ => return self.power(),
+        };
+        self.advance();
+        Ok(Expr::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn power(&mut self) -> Result<Expr, ExpressionError> {
+        let base = self.primary()?;
+        if !self.eat(&Token::Caret) {
+            return Ok(base);
+        }
+        // The exponent may be negated, e.g. `2 ^ -1`
+        let exponent = self.unary()?;
+        Ok(Expr::Binary(
+            BinaryOp::Power,
+            Box::new(base),
+            Box::new(exponent),
+        ))
+    }
+
+    fn primary(&mut self) -> Result<Expr, ExpressionError> {
+        let offset = self.offset();
+        match self.advance() {
+            Token::Integer(n) => Ok(Expr::Integer(n)),
+            Token::Number(n) => Ok(Expr::Number(n)),
+            Token::String(s) => Ok(Expr::String(s)),
+            Token::Path(path) => Ok(Expr::Path(path)),
+            Token::Identifier(name) if self.peek() == &Token::LeftParen => {
+                self.advance();
+                if name == "exists" {
+                    return self.exists(offset);
+                }
+                self.call(&name, offset)
+            }
+            Token::Identifier(name) => Ok(match name.as_str() {
+                "true" => Expr::Bool(true),
+                "false" => Expr::Bool(false),
+                _ => Expr::Identifier(name),
+            }),
+            Token::LeftParen => {
+                let expr = self.conditional()?;
+                if !self.eat(&Token::RightParen) {
+                    return Err(self.unclosed(offset));
+                }
+                Ok(expr)
+            }
+            Token::End => Err(error_at(self.text, offset, "unexpected end of expression")),
+            token => Err(error_at(
+                self.text,
+                offset,
+                format!("expected a value, found {}", token.describe()),
+            )),
+        }
+    }
+
+    /// Parse a call of the function named at `offset` after the opening
+    /// parenthesis.
+    fn call(&mut self, name: &str, offset: usize) -> Result<Expr, ExpressionError> {
+        if name == "if" {
+            let args = self.arguments(offset)?;
+            let [condition, then, otherwise] = <[Expr; 3]>::try_from(args).map_err(|args| {
+                error_at(
+                    self.text,
+                    offset,
+                    format!("if() takes 3 arguments, got {}", args.len()),
+                )
+            })?;
+            return Ok(Expr::Conditional(
+                Box::new(condition),
+                Box::new(then),
+                Box::new(otherwise),
+            ));
+        }
+
+        let Some(function) = lookup(name) else {
+            return Err(error_at(
+                self.text,
+                offset,
+                format!("unknown function '{name}'"),
+            ));
+        };
+        let args = self.arguments(offset)?;
+        if !function.accepts(args.len()) {
+            return Err(error_at(
+                self.text,
+                offset,
+                format!("{name}() takes {}, got {}", function.arity(), args.len()),
+            ));
+        }
+        if function.name == "regex_replace"
+            && let Some(Expr::String(pattern)) = args.get(1)
+            && let Err(e) = Regex::new(pattern)
+        {
+            return Err(error_at(
+                self.text,
+                offset,
+                format!("regex_replace() pattern is invalid: {e}"),
+            ));
+        }
+        Ok(Expr::Call(function, args))
+    }
+
+    /// Parse call arguments after the opening parenthesis.
+    fn arguments(&mut self, call: usize) -> Result<Vec<Expr>, ExpressionError> {
+        let mut args = Vec::new();
+        if self.eat(&Token::RightParen) {
+            return Ok(args);
+        }
+        loop {
+            args.push(self.conditional()?);
+            if self.eat(&Token::RightParen) {
+                return Ok(args);
+            }
+            if !self.eat(&Token::Comma) {
+                return Err(self.unclosed(call));
+            }
+        }
+    }
+
+    fn exists(&mut self, call: usize) -> Result<Expr, ExpressionError> {
+        let name = match self.advance() {
+            Token::Path(name) | Token::Identifier(name) => name,
+            _ => {
+                return Err(error_at(
+                    self.text,
+                    call,
+                    "exists() takes a single JSONPath",
+                ));
+            }
+        };
+        if !self.eat(&Token::RightParen) {
+            return Err(error_at(
+                self.text,
+                call,
+                "exists() takes a single JSONPath",
+            ));
+        }
+        Ok(Expr::Exists(name))
+    }
+
+    /// Error for a parenthesis opened at `offset` that is not closed.
+    fn unclosed(&self, offset: usize) -> ExpressionError {
+        match self.peek() {
+            Token::End => error_at(self.text, offset, "unclosed '('"),
+            token => self.error(format!("expected ')' or ',', found {}", token.describe())),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[test]
-    fn test_jsonpath_to_variable_simple() {
-        assert_eq!(jsonpath_to_variable("$.payload"), "JSON__payload");
+    fn integer(n: i64) -> Box<Expr> {
+        Box::new(Expr::Integer(n))
+    }
+
+    fn function(name: &str) -> &'static Function {
+        lookup(name).unwrap()
+    }
+
+    fn path(p: &str) -> Box<Expr> {
+        Box::new(Expr::Path(p.to_string()))
+    }
+
+    fn error(text: &str) -> (usize, String) {
+        match parse(text) {
+            Err(ExpressionError::Parse { column, message }) => (column, message),
+            other => panic!("expected parse error, got {other:?}"),
+        }
     }
 
     #[test]
-    fn test_jsonpath_to_variable_nested() {
+    fn test_parse_precedence() {
         assert_eq!(
-            jsonpath_to_variable("$.payload.temperature"),
-            "JSON__payload_temperature"
+            parse("= 32 + $.payload * 9 / 5").unwrap(),
+            Expr::Binary(
+                BinaryOp::Add,
+                integer(32),
+                Box::new(Expr::Binary(
+                    BinaryOp::Divide,
+                    Box::new(Expr::Binary(
+                        BinaryOp::Multiply,
+                        path("$.payload"),
+                        integer(9)
+                    )),
+                    integer(5)
+                ))
+            )
         );
     }
 
     #[test]
-    fn test_jsonpath_to_variable_topic_index() {
-        assert_eq!(jsonpath_to_variable("$.topic[1]"), "JSON__topic[1]");
+    fn test_parse_power_of_nested_parentheses() {
+        assert_eq!(
+            parse("(a * (b + c)) ^ 2").unwrap(),
+            Expr::Binary(
+                BinaryOp::Power,
+                Box::new(Expr::Binary(
+                    BinaryOp::Multiply,
+                    Box::new(Expr::Identifier("a".into())),
+                    Box::new(Expr::Binary(
+                        BinaryOp::Add,
+                        Box::new(Expr::Identifier("b".into())),
+                        Box::new(Expr::Identifier("c".into()))
+                    ))
+                )),
+                integer(2)
+            )
+        );
     }
 
     #[test]
-    fn test_jsonpath_to_variable_deeply_nested() {
+    fn test_parse_power_binds_tighter_than_negation() {
+        assert_eq!(
+            parse("-2 ^ 2").unwrap(),
+            Expr::Unary(
+                UnaryOp::Negate,
+                Box::new(Expr::Binary(BinaryOp::Power, integer(2), integer(2)))
+            )
+        );
         assert_eq!(
-            jsonpath_to_variable("$.payload.data.sensor.value"),
-            "JSON__payload_data_sensor_value"
+            parse("2 ^ 3 ^ 2").unwrap(),
+            Expr::Binary(
+                BinaryOp::Power,
+                integer(2),
+                Box::new(Expr::Binary(BinaryOp::Power, integer(3), integer(2)))
+            )
         );
     }
 
     #[test]
-    fn test_variable_to_jsonpath_simple() {
-        assert_eq!(variable_to_jsonpath("JSON__payload"), "$.payload");
+    fn test_parse_conditional_nests_right() {
+        assert_eq!(
+            parse("a ? 1 : b ? 2 : 3").unwrap(),
+            Expr::Conditional(
+                Box::new(Expr::Identifier("a".into())),
+                integer(1),
+                Box::new(Expr::Conditional(
+                    Box::new(Expr::Identifier("b".into())),
+                    integer(2),
+                    integer(3)
+                ))
+            )
+        );
     }
 
     #[test]
-    fn test_variable_to_jsonpath_nested() {
+    fn test_parse_calls_and_exists() {
         assert_eq!(
-            variable_to_jsonpath("JSON__payload_temperature"),
-            "$.payload.temperature"
+            parse(r#"round($.payload['pm2.5'], 1) == 2 && !exists($.payload.x)"#).unwrap(),
+            Expr::Binary(
+                BinaryOp::And,
+                Box::new(Expr::Binary(
+                    BinaryOp::Equal,
+                    Box::new(Expr::Call(
+                        function("round"),
+                        vec![Expr::Path("$.payload['pm2.5']".into()), Expr::Integer(1)]
+                    )),
+                    integer(2)
+                )),
+                Box::new(Expr::Unary(
+                    UnaryOp::Not,
+                    Box::new(Expr::Exists("$.payload.x".into()))
+                ))
+            )
         );
+        assert_eq!(parse("now()").unwrap(), Expr::Call(function("now"), vec![]));
     }
 
     #[test]
-    fn test_parse_expression_simple() {
-        let (expr, paths) = parse_expression("32 + ($.payload * 9 / 5)");
-        assert_eq!(expr, "32 + (JSON__payload * 9 / 5)");
-        assert_eq!(paths, vec!["$.payload"]);
+    fn test_parse_if_as_conditional() {
+        assert_eq!(
+            parse("if(a, 1, 2)").unwrap(),
+            Expr::Conditional(
+                Box::new(Expr::Identifier("a".into())),
+                integer(1),
+                integer(2)
+            )
+        );
     }
 
     #[test]
-    fn test_parse_expression_multiple_vars() {
-        let (expr, paths) = parse_expression("$.payload + $.payload.offset");
-        assert_eq!(expr, "JSON__payload + JSON__payload_offset");
-        assert_eq!(paths.len(), 2);
-        assert!(paths.contains(&"$.payload".to_string()));
-        assert!(paths.contains(&"$.payload.offset".to_string()));
+    fn test_parse_unknown_function_and_arity() {
+        assert_eq!(
+            error("= 1 + pow(2, 3)"),
+            (7, "unknown function 'pow'".to_string())
+        );
+        assert_eq!(
+            error("= abs(1, 2)"),
+            (3, "abs() takes 1 argument, got 2".to_string())
+        );
+        assert_eq!(
+            error("= round()"),
+            (3, "round() takes 1 or 2 arguments, got 0".to_string())
+        );
+        assert_eq!(
+            error("= if(a, 1)"),
+            (3, "if() takes 3 arguments, got 2".to_string())
+        );
+        let (column, message) = error(r#"= regex_replace(a, "(", "")"#);
+        assert_eq!(column, 3);
+        assert!(message.starts_with("regex_replace() pattern is invalid"));
     }
 
     #[test]
-    fn test_parse_expression_power() {
-        let (expr, _) = parse_expression("2 ^ 3");
-        assert_eq!(expr, "math::pow(2, 3)");
+    fn test_parse_literals() {
+        assert_eq!(parse("true").unwrap(), Expr::Bool(true));
+        assert_eq!(parse("2").unwrap(), Expr::Integer(2));
+        assert_eq!(parse("2.5").unwrap(), Expr::Number(2.5));
+        assert_eq!(parse(r#""a""#).unwrap(), Expr::String("a".to_string()));
     }
 
     #[test]
-    fn test_extract_variables() {
-        let vars = extract_variables("$.payload + $.payload.offset");
-        assert!(vars.contains(&"JSON__payload".to_string()));
-        assert!(vars.contains(&"JSON__payload_offset".to_string()));
+    fn test_parse_error_positions() {
+        assert_eq!(error("= (1 + 2").0, 3);
+        assert_eq!(error("= (1 + 2").1, "unclosed '('");
+        assert_eq!(error("= 1 + 2)"), (8, "unmatched ')'".to_string()));
+        assert_eq!(error("= 1 + * 2").0, 7);
+        assert_eq!(error("= a ? b").0, 5);
+        assert_eq!(error("= round(1 2)").0, 11);
+        assert_eq!(error("= 1 +").1, "unexpected end of expression");
+        assert_eq!(error("= exists(1)").0, 3);
+        assert_eq!(error("   ").1, "empty expression");
     }
 }
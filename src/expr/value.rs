@@ -0,0 +1,83 @@
+//! Values computed while evaluating an expression.
+
+use crate::error::ExpressionError;
+
+/// Value of an expression or of one of its operands.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Value {
+    Int(i64),
+    Float(f64),
+    String(String),
+    Boolean(bool),
+    /// JSON `null`.
+    Empty,
+}
+
+impl Value {
+    /// Convert a JSON value; arrays and objects become their JSON text.
+    ///
+    /// Integers that fit into an `i64` stay integers.
+    pub(super) fn from_json(value: &serde_json::Value) -> Self {
+        match value {
+            serde_json::Value::Bool(b) => Value::Boolean(*b),
+            serde_json::Value::Number(n) => match n.as_i64() {
+                Some(i) => Value::Int(i),
+                None => Value::Float(n.as_f64().unwrap_or(f64::NAN)),
+            },
+            serde_json::Value::String(s) => Value::String(s.clone()),
+            serde_json::Value::Null => Value::Empty,
+            other => Value::String(other.to_string()),
+        }
+    }
+
+    /// Convert a result to JSON.
+    ///
+    /// Infinite and NaN results cannot be represented and are errors.
+    pub(super) fn into_json(self) -> Result<serde_json::Value, ExpressionError> {
+        match self {
+            Value::Int(i) => Ok(serde_json::Value::from(i)),
+            Value::Float(f) if !f.is_finite() => Err(ExpressionError::Evaluation(format!(
+                "Expected a finite number, got: {f}"
+            ))),
+            Value::Float(f) => Ok(serde_json::Value::from(f)),
+            Value::String(s) => Ok(serde_json::Value::String(s)),
+            Value::Boolean(b) => Ok(serde_json::Value::Bool(b)),
+            Value::Empty => Err(ExpressionError::Evaluation(
+                "Expected number, string or boolean result, got: null".to_string(),
+            )),
+        }
+    }
+
+    /// Describe the type of the value for error messages.
+    pub(super) fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) | Value::Float(_) => "number",
+            Value::String(_) => "string",
+            Value::Boolean(_) => "boolean",
+            Value::Empty => "null",
+        }
+    }
+
+    /// The value of a number, converting integers to floats.
+    pub(super) fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Int(i) => Some(*i as f64),
+            Value::Float(f) => Some(*f),
+            _ => None,
+        }
+    }
+
+    pub(super) fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub(super) fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,432 @@
+//! Liveness and readiness state of the bridge.
+//!
+//! The MQTT handlers and `InfluxDB` writer record their state in a process-wide
+//! tracker returned by [`health`], which is evaluated by the `/healthz` and
+//! `/readyz` endpoints against the configured thresholds. MQTT session state
+//! is tracked per broker.
+
+use crate::config::HealthConfig;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+static HEALTH: LazyLock<Health> = LazyLock::new(Health::new);
+
+/// Get the process-wide health tracker.
+#[must_use]
+pub fn health() -> &'static Health {
+    &HEALTH
+}
+
+/// Tracker of MQTT session and `InfluxDB` write state.
+#[derive(Debug)]
+pub struct Health {
+    state: Mutex<HealthState>,
+}
+
+#[derive(Debug)]
+struct HealthState {
+    started: Instant,
+    /// MQTT session state by broker name.
+    sessions: BTreeMap<String, Session>,
+    last_write_success: Option<Instant>,
+    last_write_failure: Option<Instant>,
+}
+
+#[derive(Debug, Clone)]
+struct Session {
+    connected: bool,
+    /// Start of the current disconnection, if not connected.
+    disconnected_since: Option<Instant>,
+    pending_subscriptions: usize,
+    rejected_subscriptions: usize,
+}
+
+impl Session {
+    fn new(since: Instant) -> Self {
+        Self {
+            connected: false,
+            disconnected_since: Some(since),
+            pending_subscriptions: 0,
+            rejected_subscriptions: 0,
+        }
+    }
+}
+
+impl HealthState {
+    fn session(&mut self, broker: &str) -> &mut Session {
+        self.sessions
+            .entry(broker.to_string())
+            .or_insert_with(|| Session::new(Instant::now()))
+    }
+
+    /// Sessions to evaluate; a single disconnected one before any broker registered.
+    fn sessions(&self) -> Vec<(&str, Session)> {
+        if self.sessions.is_empty() {
+            return vec![("default", Session::new(self.started))];
+        }
+        self.sessions
+            .iter()
+            .map(|(broker, session)| (broker.as_str(), session.clone()))
+            .collect()
+    }
+}
+
+/// Describe each session, prefixed with the broker name when there are several.
+fn describe(sessions: &[(&str, Session)], detail: impl Fn(&Session) -> String) -> String {
+    match sessions {
+        [(_, session)] => detail(session),
+        _ => sessions
+            .iter()
+            .map(|(broker, session)| format!("{broker}: {}", detail(session)))
+            .collect::<Vec<_>>()
+            .join(", "),
+    }
+}
+
+/// Result of a single health check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: &'static str,
+    pub ok: bool,
+    pub detail: String,
+}
+
+/// Result of evaluating a set of health checks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Status {
+    pub checks: Vec<Check>,
+}
+
+impl Status {
+    /// Check if all checks passed.
+    #[must_use]
+    pub fn is_ok(&self) -> bool {
+        self.checks.iter().all(|c| c.ok)
+    }
+
+    /// Render the checks as plain text, one per line.
+    #[must_use]
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for check in &self.checks {
+            let result = if check.ok { "ok" } else { "failed" };
+            let _ = writeln!(out, "{}: {} ({})", check.name, result, check.detail);
+        }
+        out
+    }
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Health {
+    /// Create a tracker for a bridge that has not connected yet.
+    #[must_use]
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            state: Mutex::new(HealthState {
+                started: now,
+                sessions: BTreeMap::new(),
+                last_write_success: None,
+                last_write_failure: None,
+            }),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, HealthState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Register a broker whose session has not connected yet.
+    pub fn register_broker(&self, broker: &str) {
+        self.state().session(broker);
+    }
+
+    /// Record a successful `ConnAck` and the subscriptions requested after it.
+    pub fn mqtt_connected(&self, broker: &str, subscriptions: usize) {
+        let mut state = self.state();
+        let session = state.session(broker);
+        session.connected = true;
+        session.disconnected_since = None;
+        session.pending_subscriptions = subscriptions;
+        session.rejected_subscriptions = 0;
+    }
+
+    /// Record subscriptions requested while connected, e.g. after a reload.
+    pub fn subscriptions_requested(&self, broker: &str, count: usize) {
+        self.state().session(broker).pending_subscriptions += count;
+    }
+
+    /// Record a lost or refused MQTT connection.
+    pub fn mqtt_disconnected(&self, broker: &str) {
+        let mut state = self.state();
+        let session = state.session(broker);
+        if session.connected || session.disconnected_since.is_none() {
+            session.disconnected_since = Some(Instant::now());
+        }
+        session.connected = false;
+    }
+
+    /// Record a `SubAck` with the number of granted and rejected topic filters.
+    pub fn subscription_acked(&self, broker: &str, granted: usize, rejected: usize) {
+        let mut state = self.state();
+        let session = state.session(broker);
+        session.pending_subscriptions = session
+            .pending_subscriptions
+            .saturating_sub(granted + rejected);
+        session.rejected_subscriptions += rejected;
+    }
+
+    /// Record a successful `InfluxDB` write.
+    pub fn write_succeeded(&self) {
+        self.state().last_write_success = Some(Instant::now());
+    }
+
+    /// Record a failed `InfluxDB` write.
+    pub fn write_failed(&self) {
+        self.state().last_write_failure = Some(Instant::now());
+    }
+
+    /// Evaluate liveness at the given time.
+    ///
+    /// The bridge is live unless an MQTT session has been down for longer
+    /// than `max_disconnected_secs` (when configured).
+    #[must_use]
+    pub fn liveness(&self, config: &HealthConfig, now: Instant) -> Status {
+        let state = self.state();
+        let sessions = state.sessions();
+
+        let down = |session: &Session| {
+            session
+                .disconnected_since
+                .map(|since| now.saturating_duration_since(since))
+        };
+        let check = Check {
+            name: "mqtt_session",
+            ok: sessions.iter().all(|(_, session)| {
+                down(session).is_none_or(|down| {
+                    config
+                        .max_disconnected_secs
+                        .is_none_or(|max| down <= Duration::from_secs(max))
+                })
+            }),
+            detail: describe(&sessions, |session| match down(session) {
+                Some(down) => format!("disconnected for {}s", down.as_secs()),
+                None => "connected".to_string(),
+            }),
+        };
+
+        Status {
+            checks: vec![check],
+        }
+    }
+
+    /// Evaluate readiness at the given time.
+    ///
+    /// The bridge is ready when connected to every broker, all subscriptions
+    /// are acknowledged, and `InfluxDB` writes are not failing for longer
+    /// than `write_staleness_secs` since the last success.
+    #[must_use]
+    pub fn readiness(&self, config: &HealthConfig, now: Instant) -> Status {
+        let state = self.state();
+        let sessions = state.sessions();
+
+        let connection = Check {
+            name: "mqtt_connected",
+            ok: sessions.iter().all(|(_, session)| session.connected),
+            detail: describe(&sessions, |session| {
+                if session.connected {
+                    "connack received".to_string()
+                } else {
+                    "not connected".to_string()
+                }
+            }),
+        };
+
+        let subscriptions = Check {
+            name: "mqtt_subscriptions",
+            ok: sessions.iter().all(|(_, session)| {
+                session.connected
+                    && session.pending_subscriptions == 0
+                    && session.rejected_subscriptions == 0
+            }),
+            detail: describe(&sessions, |session| {
+                format!(
+                    "{} pending, {} rejected",
+                    session.pending_subscriptions, session.rejected_subscriptions
+                )
+            }),
+        };
+
+        // Writes are stale only while failing; an idle bridge stays ready
+        let last_success = state.last_write_success.unwrap_or(state.started);
+        let since_success = now.saturating_duration_since(last_success);
+        let failing = state
+            .last_write_failure
+            .is_some_and(|failure| failure >= last_success);
+        let writes = Check {
+            name: "influxdb_writes",
+            ok: !failing || since_success <= Duration::from_secs(config.write_staleness_secs),
+            detail: match state.last_write_success {
+                Some(_) => format!("last success {}s ago", since_success.as_secs()),
+                None => "no successful write yet".to_string(),
+            },
+        };
+
+        Status {
+            checks: vec![connection, subscriptions, writes],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_config() -> HealthConfig {
+        HealthConfig {
+            write_staleness_secs: 60,
+            max_disconnected_secs: Some(300),
+        }
+    }
+
+    #[test]
+    fn test_not_ready_before_connect() {
+        let health = Health::new();
+        let status = health.readiness(&make_config(), Instant::now());
+
+        assert!(!status.is_ok());
+        assert!(status.render().contains("mqtt_connected: failed"));
+    }
+
+    #[test]
+    fn test_ready_after_subscriptions_acked() {
+        let health = Health::new();
+        health.mqtt_connected("default", 2);
+        assert!(!health.readiness(&make_config(), Instant::now()).is_ok());
+
+        health.subscription_acked("default", 2, 0);
+        assert!(health.readiness(&make_config(), Instant::now()).is_ok());
+    }
+
+    #[test]
+    fn test_not_ready_when_subscription_rejected() {
+        let health = Health::new();
+        health.mqtt_connected("default", 2);
+        health.subscription_acked("default", 1, 1);
+
+        let status = health.readiness(&make_config(), Instant::now());
+        assert!(!status.is_ok());
+        assert!(status.render().contains("0 pending, 1 rejected"));
+    }
+
+    #[test]
+    fn test_not_ready_after_disconnect() {
+        let health = Health::new();
+        health.mqtt_connected("default", 0);
+        health.mqtt_disconnected("default");
+
+        assert!(!health.readiness(&make_config(), Instant::now()).is_ok());
+    }
+
+    #[test]
+    fn test_write_staleness() {
+        let health = Health::new();
+        health.mqtt_connected("default", 0);
+        health.write_succeeded();
+        let now = Instant::now();
+
+        // Idle bridge remains ready
+        assert!(
+            health
+                .readiness(&make_config(), now + Duration::from_secs(600))
+                .is_ok()
+        );
+
+        // Failing writes become stale after the threshold
+        health.write_failed();
+        assert!(health.readiness(&make_config(), now).is_ok());
+        assert!(
+            !health
+                .readiness(&make_config(), now + Duration::from_secs(120))
+                .is_ok()
+        );
+
+        // A later success recovers readiness
+        health.write_succeeded();
+        assert!(health.readiness(&make_config(), Instant::now()).is_ok());
+    }
+
+    #[test]
+    fn test_liveness_disconnected_threshold() {
+        let health = Health::new();
+        let now = Instant::now();
+
+        assert!(health.liveness(&make_config(), now).is_ok());
+        assert!(
+            !health
+                .liveness(&make_config(), now + Duration::from_secs(600))
+                .is_ok()
+        );
+
+        let config = HealthConfig {
+            max_disconnected_secs: None,
+            ..make_config()
+        };
+        assert!(
+            health
+                .liveness(&config, now + Duration::from_secs(600))
+                .is_ok()
+        );
+
+        health.mqtt_connected("default", 0);
+        assert!(
+            health
+                .liveness(&make_config(), now + Duration::from_secs(600))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_ready_when_all_brokers_connected() {
+        let health = Health::new();
+        health.register_broker("east");
+        health.register_broker("west");
+        health.mqtt_connected("east", 0);
+
+        let status = health.readiness(&make_config(), Instant::now());
+        assert!(!status.is_ok());
+        assert!(
+            status
+                .render()
+                .contains("mqtt_connected: failed (east: connack received, west: not connected)")
+        );
+
+        health.mqtt_connected("west", 1);
+        assert!(!health.readiness(&make_config(), Instant::now()).is_ok());
+        health.subscription_acked("west", 1, 0);
+        assert!(health.readiness(&make_config(), Instant::now()).is_ok());
+    }
+
+    #[test]
+    fn test_liveness_fails_when_any_broker_down() {
+        let health = Health::new();
+        let now = Instant::now();
+        health.register_broker("east");
+        health.register_broker("west");
+        health.mqtt_connected("east", 0);
+
+        assert!(health.liveness(&make_config(), now).is_ok());
+        assert!(
+            !health
+                .liveness(&make_config(), now + Duration::from_secs(600))
+                .is_ok()
+        );
+    }
+}
@@ -3,6 +3,10 @@ pub mod cli;
 pub mod config;
 pub mod error;
 pub mod expr;
+pub mod health;
+pub mod metrics;
+pub mod reload;
+pub mod server;
 
 pub use config::{Config, load_config};
-pub use error::{ConfigError, ExpressionError, SinqttError};
+pub use error::{ConfigError, ExpressionError, InfluxDbError, SinqttError};
@@ -2,20 +2,27 @@
 
 use clap::Parser;
 use sinqtt::bridge::{
-    FieldValue, HttpContentBuilder, HttpForwarder, InfluxDBWriter, MessageProcessor, MqttHandler,
-    MqttMessage, Point,
+    BatchWriter, FieldValue, HttpContentBuilder, HttpForwarder, InfluxDBWriter, MessageAck,
+    MessageProcessor, MessageScope, MqttHandler, MqttMessage, Point, PointPlan, Spool,
+    Subscription, partial_overlaps, plan_subscriptions, shared_subscription,
 };
 use sinqtt::cli::Args;
-use sinqtt::config::PointConfig;
+use sinqtt::config::MqttConfig;
 use sinqtt::error::SinqttError;
+use sinqtt::metrics::{ExtractionKind, metrics};
+use sinqtt::reload::ConfigReloader;
+use sinqtt::server::Server;
 use sinqtt::{Config, load_config};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, watch};
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, warn};
 use tracing_subscriber::EnvFilter;
 
+/// Interval at which the configuration file is checked for changes.
+const CONFIG_WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
 #[tokio::main]
 async fn main() -> Result<(), SinqttError> {
     let args = Args::parse();
@@ -34,12 +41,15 @@ async fn main() -> Result<(), SinqttError> {
     let config = load_config(&args.config)?;
 
     if args.test {
+        check_clients(&config)?;
         println!("Configuration file is valid.");
         return Ok(());
     }
 
     info!("Configuration loaded successfully");
-    info!("MQTT broker: {}:{}", config.mqtt.host, config.mqtt.port);
+    for mqtt in &config.mqtt {
+        info!("MQTT broker {}: {}:{}", mqtt.name(), mqtt.host, mqtt.port);
+    }
     info!(
         "InfluxDB: {}:{}",
         config.influxdb.host, config.influxdb.port
@@ -57,9 +67,23 @@ async fn main() -> Result<(), SinqttError> {
         cancel_token_clone.cancel();
     });
 
+    // Start embedded HTTP server if configured
+    if let Some(server_config) = &config.server {
+        let server = Server::bind(server_config).await?;
+        tokio::spawn(server.run(cancel_token.clone()));
+    }
+
+    // Reload configuration on SIGHUP and, if requested, on file change
+    let (mut reloader, config_rx) = ConfigReloader::new(args.config.clone(), config);
+    if args.watch {
+        info!("Watching {:?} for changes", args.config);
+        reloader = reloader.watch_file(CONFIG_WATCH_INTERVAL);
+    }
+    tokio::spawn(reloader.run(cancel_token.clone()));
+
     // Run bridge with retry logic if daemon mode
     loop {
-        match run_bridge(&config, cancel_token.clone()).await {
+        match run_bridge(config_rx.clone(), cancel_token.clone()).await {
             Ok(()) => break,
             Err(e) => {
                 if cancel_token.is_cancelled() {
@@ -85,6 +109,19 @@ async fn main() -> Result<(), SinqttError> {
     Ok(())
 }
 
+/// Create the MQTT, `InfluxDB` and HTTP clients without connecting, so that
+/// TLS certificates, keys and credentials are loaded and checked.
+fn check_clients(config: &Config) -> Result<(), SinqttError> {
+    for mqtt in &config.mqtt {
+        MqttHandler::new(mqtt, Vec::new())?;
+    }
+    InfluxDBWriter::new(&config.influxdb)?;
+    if let Some(http) = &config.http {
+        HttpForwarder::new(http)?;
+    }
+    Ok(())
+}
+
 /// Wait for shutdown signal (Ctrl+C or SIGTERM).
 async fn shutdown_signal() {
     let ctrl_c = async {
@@ -110,68 +147,102 @@ async fn shutdown_signal() {
     }
 }
 
-async fn run_bridge(config: &Config, cancel_token: CancellationToken) -> Result<(), SinqttError> {
-    // Collect unique topics from all point configurations (sorted for determinism)
-    let mut topics: Vec<String> = config
-        .points
+async fn run_bridge(
+    mut config_rx: watch::Receiver<Arc<Config>>,
+    cancel_token: CancellationToken,
+) -> Result<(), SinqttError> {
+    let config = config_rx.borrow_and_update().clone();
+
+    // Create one MQTT handler per broker
+    let mut mqtt_handlers = Vec::with_capacity(config.mqtt.len());
+    for mqtt in &config.mqtt {
+        let topics = plan_topics(&config, mqtt);
+        info!("Subscribing to {} topics on {}", topics.len(), mqtt.name());
+        mqtt_handlers.push(MqttHandler::new(mqtt, topics)?);
+    }
+    let subscriptions: Vec<_> = mqtt_handlers
         .iter()
-        .map(|p| p.topic.clone())
-        .collect::<std::collections::HashSet<_>>()
-        .into_iter()
+        .map(MqttHandler::subscriptions)
         .collect();
-    topics.sort();
 
-    info!("Subscribing to {} unique topics", topics.len());
+    // Build points, processor and HTTP forwarder from the configuration
+    // before spawning the batch writer, which must drain once started
+    let mut pipeline = Pipeline::new(&config)?;
 
-    // Create MQTT handler
-    let mqtt_handler = MqttHandler::new(&config.mqtt, topics)?;
-
-    // Create InfluxDB writer
+    // Create InfluxDB writer with batching
     let influxdb_writer = Arc::new(InfluxDBWriter::new(&config.influxdb)?);
-
-    // Create HTTP forwarder if configured
-    let http_forwarder = config
-        .http
-        .as_ref()
-        .map(|h| Arc::new(HttpForwarder::new(h)));
-    if http_forwarder.is_some() {
-        info!("HTTP forwarding enabled");
-    }
-
-    // Create message processor
-    let processor = Arc::new(MessageProcessor::new(config.base64decode.clone()));
-
-    // Clone config points for the processing task
-    let points = Arc::new(config.points.clone());
+    let spool = match &config.influxdb.spool {
+        Some(spool_config) => {
+            let spool = Spool::open(spool_config)?;
+            info!(
+                "Spool enabled at {:?} ({} batches pending)",
+                spool.directory(),
+                spool.len()
+            );
+            Some((
+                spool,
+                Duration::from_millis(spool_config.replay_interval_ms),
+            ))
+        }
+        None => None,
+    };
+    let (batch_writer, batch_task) = BatchWriter::spawn(
+        influxdb_writer,
+        &config.influxdb.batch,
+        spool,
+        cancel_token.clone(),
+    );
 
     // Create channel for MQTT messages
     let (tx, mut rx) = mpsc::channel::<MqttMessage>(100);
 
     // Spawn message processing task
-    let processor_clone = processor.clone();
-    let influxdb_clone = influxdb_writer.clone();
-    let http_clone = http_forwarder.clone();
-    let points_clone = points.clone();
     let cancel_token_process = cancel_token.clone();
 
     let process_task = tokio::spawn(async move {
+        let mut reload_open = true;
         loop {
             tokio::select! {
                 msg = rx.recv() => {
                     match msg {
                         Some(msg) => {
+                            metrics().set_channel_backlog("mqtt", rx.len());
                             process_message(
                                 &msg,
-                                &points_clone,
-                                &processor_clone,
-                                &influxdb_clone,
-                                http_clone.as_ref().map(std::convert::AsRef::as_ref),
+                                &pipeline.points,
+                                &pipeline.processor,
+                                &batch_writer,
+                                pipeline.http_forwarder.as_ref(),
                             )
                             .await;
                         }
                         None => break,
                     }
                 }
+                changed = config_rx.changed(), if reload_open => {
+                    if changed.is_err() {
+                        reload_open = false;
+                        continue;
+                    }
+                    let config = config_rx.borrow_and_update().clone();
+                    pipeline = match Pipeline::new(&config) {
+                        Ok(pipeline) => pipeline,
+                        Err(e) => {
+                            error!("Failed to apply new configuration: {}", e);
+                            continue;
+                        }
+                    };
+                    // Added or removed brokers only take effect on restart
+                    for handle in &subscriptions {
+                        let Some(mqtt) = config.mqtt.iter().find(|m| m.name() == handle.broker()) else {
+                            continue;
+                        };
+                        if let Err(e) = handle.update(plan_topics(&config, mqtt)).await {
+                            error!("Failed to update subscriptions on {}: {}", handle.broker(), e);
+                        }
+                    }
+                    info!("Applied new configuration with {} points", pipeline.points.len());
+                }
                 () = cancel_token_process.cancelled() => {
                     debug!("Message processor shutting down");
                     break;
@@ -182,88 +253,201 @@ async fn run_bridge(config: &Config, cancel_token: CancellationToken) -> Result<
 
     info!("Bridge started, waiting for messages...");
 
-    // Run MQTT handler with cancellation support
+    // Run MQTT handlers with cancellation support; the bridge stops when any of them does
+    let mut mqtt_tasks = tokio::task::JoinSet::new();
+    for handler in mqtt_handlers {
+        mqtt_tasks.spawn(handler.run(tx.clone()));
+    }
+    drop(tx);
     let mqtt_result = tokio::select! {
-        result = mqtt_handler.run(tx) => result,
+        Some(result) = mqtt_tasks.join_next() => {
+            result.unwrap_or_else(|e| Err(std::io::Error::other(e).into()))
+        }
         () = cancel_token.cancelled() => {
-            info!("MQTT handler shutting down");
+            info!("MQTT handlers shutting down");
             Ok(())
         }
     };
+    mqtt_tasks.abort_all();
 
-    // Wait for processor to finish
+    // Wait for processor to finish, then let the batch writer drain
     let _ = process_task.await;
+    let _ = batch_task.await;
 
     mqtt_result
 }
 
+/// Plan the subscriptions covering the topics of points on a broker (sorted for determinism).
+///
+/// Filters that partially overlap are logged, as the broker delivers messages
+/// matching both of them twice.
+fn plan_topics(config: &Config, mqtt: &MqttConfig) -> Vec<Subscription> {
+    let share_group = mqtt.share_group.as_deref();
+    let planned = plan_subscriptions(
+        config
+            .points
+            .iter()
+            .filter(|p| p.applies_to(mqtt.name()))
+            .map(|p| Subscription::new(shared_subscription(share_group, &p.topic), p.qos)),
+    );
+    for (first, second) in partial_overlaps(&planned) {
+        warn!(
+            "Topic filters {} and {} overlap on {}: messages matching both are received and processed twice",
+            first,
+            second,
+            mqtt.name()
+        );
+    }
+    planned
+}
+
+/// Message processing state rebuilt whenever the configuration is reloaded.
+struct Pipeline {
+    points: Vec<PointPlan>,
+    processor: MessageProcessor,
+    http_forwarder: Option<HttpForwarder>,
+}
+
+impl Pipeline {
+    fn new(config: &Config) -> Result<Self, SinqttError> {
+        let http_forwarder = config.http.as_ref().map(HttpForwarder::new).transpose()?;
+        if http_forwarder.is_some() {
+            info!("HTTP forwarding enabled");
+        }
+
+        Ok(Self {
+            points: config
+                .points
+                .iter()
+                .map(PointPlan::compile)
+                .collect::<Result<_, _>>()?,
+            processor: MessageProcessor::new(config.base64decode.clone()),
+            http_forwarder,
+        })
+    }
+}
+
 async fn process_message(
     msg: &MqttMessage,
-    points: &[PointConfig],
+    points: &[PointPlan],
     processor: &MessageProcessor,
-    influxdb_writer: &InfluxDBWriter,
+    batch_writer: &BatchWriter,
     http_forwarder: Option<&HttpForwarder>,
 ) {
     debug!("Processing message on topic: {}", msg.topic);
 
-    // Parse message once
-    let parsed = match processor.parse_message(&msg.topic, &msg.payload, msg.qos) {
+    // Parse message once; unparseable messages are acknowledged as they can
+    // never be written
+    let parsed = match processor.parse_mqtt_message(msg) {
         Ok(parsed) => parsed,
         Err(e) => {
             warn!("Failed to parse message: {}", e);
+            if let Some(ack) = &msg.ack {
+                ack.complete();
+            }
             return;
         }
     };
+    let scope = MessageScope::new(&parsed);
+
+    // Count the message once per matching subscription
+    let mut subscriptions: Vec<&str> = Vec::new();
+    for point_config in points.iter().map(PointPlan::config) {
+        if !subscriptions.contains(&point_config.topic.as_str())
+            && point_config.applies_to(&msg.broker)
+            && processor.topic_matches(&point_config.topic, &msg.topic)
+        {
+            subscriptions.push(&point_config.topic);
+            metrics().message_received(&point_config.topic);
+        }
+    }
 
     // Check each point configuration
-    for point_config in points {
-        // Check if topic matches
-        if !processor.topic_matches(&point_config.topic, &msg.topic) {
+    for plan in points {
+        let point_config = plan.config();
+
+        // Check if broker and topic match
+        if !point_config.applies_to(&msg.broker)
+            || !processor.topic_matches(&point_config.topic, &msg.topic)
+        {
             continue;
         }
 
         // Check schedule if configured
-        if let Some(schedule) = &point_config.schedule
-            && !processor.schedule_matches(schedule)
-        {
-            debug!("Skipping {} due to schedule {}", msg.topic, schedule);
+        if !plan.schedule_matches() {
+            debug!(
+                "Skipping {} due to schedule {}",
+                msg.topic,
+                point_config.schedule.as_deref().unwrap_or_default()
+            );
             continue;
         }
 
+        // Expand into one scope per element if foreach is configured
+        let expanded = plan.items(&scope);
+        let items = match &expanded {
+            Some(expanded) => {
+                if expanded.is_empty() {
+                    debug!(
+                        "No elements found for foreach {}",
+                        point_config.foreach.as_deref().unwrap_or_default()
+                    );
+                }
+                expanded.as_slice()
+            }
+            None => std::slice::from_ref(&scope),
+        };
+
         // Process this point
-        if let Err(e) = process_point(
-            point_config,
-            &parsed,
-            processor,
-            influxdb_writer,
-            http_forwarder,
-        )
-        .await
-        {
-            error!(
-                "Failed to process point {}: {}",
-                point_config.measurement, e
-            );
+        for item in items {
+            // Check condition if configured
+            if !plan.condition_matches(item) {
+                debug!(
+                    "Skipping {} due to condition {}",
+                    msg.topic,
+                    point_config.when.as_deref().unwrap_or_default()
+                );
+                continue;
+            }
+
+            if let Err(e) =
+                process_point(plan, item, batch_writer, http_forwarder, msg.ack.clone()).await
+            {
+                error!(
+                    "Failed to process point {}: {}",
+                    point_config.measurement, e
+                );
+                if let Some(ack) = &msg.ack {
+                    ack.fail();
+                }
+            }
         }
     }
+
+    // Acknowledge once all points are queued, unless one of them failed
+    if let Some(ack) = &msg.ack {
+        ack.complete();
+    }
 }
 
 async fn process_point(
-    point_config: &PointConfig,
-    parsed: &sinqtt::bridge::ParsedMessage,
-    processor: &MessageProcessor,
-    influxdb_writer: &InfluxDBWriter,
+    plan: &PointPlan,
+    scope: &MessageScope<'_>,
+    batch_writer: &BatchWriter,
     http_forwarder: Option<&HttpForwarder>,
+    ack: Option<MessageAck>,
 ) -> Result<(), SinqttError> {
+    // Extraction failures are labelled with the measurement as configured,
+    // which identifies the point regardless of the message
+    let configured = plan.measurement().source();
+
     // Get measurement name
-    let measurement = match processor.get_value(&point_config.measurement, parsed) {
+    let measurement = match plan.measurement().evaluate(scope) {
         Some(serde_json::Value::String(s)) => s,
         Some(v) => v.to_string().trim_matches('"').to_string(),
         None => {
-            warn!(
-                "Could not determine measurement name for {}",
-                point_config.measurement
-            );
+            warn!("Could not determine measurement name for {}", configured);
+            metrics().extraction_failed(ExtractionKind::of(configured), configured);
             return Ok(());
         }
     };
@@ -272,8 +456,8 @@ async fn process_point(
     let mut point = Point::new(&measurement);
 
     // Add tags
-    for (tag_name, tag_spec) in &point_config.tags {
-        if let Some(value) = processor.get_value(tag_spec, parsed) {
+    for (tag_name, tag_spec) in plan.tags() {
+        if let Some(value) = tag_spec.evaluate(scope) {
             let tag_value = match value {
                 serde_json::Value::String(s) => s,
                 v => v.to_string().trim_matches('"').to_string(),
@@ -281,17 +465,23 @@ async fn process_point(
             if !tag_value.is_empty() {
                 point.add_tag(tag_name, &tag_value);
             }
+        } else {
+            metrics().extraction_failed(ExtractionKind::of(tag_spec.source()), configured);
         }
     }
 
     // Add fields
     let mut fields_added = 0;
-    for (field_name, field_spec) in &point_config.fields {
-        if let Some(value) = processor.extract_field(field_spec, parsed)
-            && let Some(field_value) = FieldValue::from_json(&value)
-        {
-            point.add_field(field_name, field_value);
-            fields_added += 1;
+    for field in plan.fields() {
+        let fields = field.extract(scope);
+        if fields.is_empty() {
+            metrics().extraction_failed(ExtractionKind::of(field.value().source()), configured);
+        }
+        for (name, value) in fields {
+            if let Some(field_value) = FieldValue::from_json(&value) {
+                point.add_field(name, field_value);
+                fields_added += 1;
+            }
         }
     }
 
@@ -300,34 +490,48 @@ async fn process_point(
         return Ok(());
     }
 
-    // Add timestamp (current time in nanoseconds)
-    let timestamp = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .map(|d| {
-            // Safe conversion: i64 can hold nanoseconds until year ~2262
-            i64::try_from(d.as_nanos()).unwrap_or(i64::MAX)
-        })
-        .unwrap_or(0);
+    // Add timestamp from the message, falling back to current time in nanoseconds
+    let message_timestamp = plan.timestamp().and_then(|timestamp| {
+        let ts = timestamp.extract(scope);
+        if ts.is_none() {
+            debug!(
+                "Could not extract timestamp {}, using receive time",
+                timestamp.value().source()
+            );
+        }
+        ts
+    });
+    let timestamp = message_timestamp.unwrap_or_else(|| {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| {
+                // Safe conversion: i64 can hold nanoseconds until year ~2262
+                i64::try_from(d.as_nanos()).unwrap_or(i64::MAX)
+            })
+            .unwrap_or(0)
+    });
     let point = point.timestamp(timestamp);
 
-    // Write to InfluxDB
-    let bucket = point_config.bucket.as_deref();
-    influxdb_writer.write_point(&point, bucket).await?;
-    debug!("Wrote point to InfluxDB: {}", measurement);
+    // Queue for batched write to InfluxDB
+    let bucket = plan.config().bucket.as_deref();
+    batch_writer.write_point(&point, bucket, ack).await?;
+    debug!("Queued point for InfluxDB: {}", measurement);
 
     // HTTP forwarding if configured
     if let Some(forwarder) = http_forwarder
-        && !point_config.httpcontent.is_empty()
+        && !plan.httpcontent().is_empty()
     {
         let mut content = HttpContentBuilder::new();
-        for (key, spec) in &point_config.httpcontent {
-            if let Some(value) = processor.get_value(spec, parsed) {
+        for (key, spec) in plan.httpcontent() {
+            if let Some(value) = spec.evaluate(scope) {
                 content.add_from_json(key, &value);
             }
         }
         if !content.is_empty() {
             let json_content = content.build_json();
-            if let Err(e) = forwarder.forward_json(&json_content).await {
+            let result = forwarder.forward_json(&json_content).await;
+            metrics().http_forward(result.is_ok());
+            if let Err(e) = result {
                 warn!("HTTP forward failed: {}", e);
             }
         }
@@ -0,0 +1,444 @@
+//! Prometheus metrics for bridge health.
+//!
+//! Metrics are collected in a process-wide registry returned by [`metrics`]
+//! and rendered in the Prometheus text exposition format.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+/// Upper bounds of the write latency histogram buckets, in seconds.
+const WRITE_DURATION_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+/// Get the process-wide metrics registry.
+#[must_use]
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+/// Kind of value specification that failed to extract a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtractionKind {
+    JsonPath,
+    Expression,
+}
+
+impl ExtractionKind {
+    /// Classify a value specification.
+    #[must_use]
+    pub fn of(spec: &str) -> Self {
+        if spec.trim_start().starts_with('=') {
+            Self::Expression
+        } else {
+            Self::JsonPath
+        }
+    }
+
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::JsonPath => "jsonpath",
+            Self::Expression => "expression",
+        }
+    }
+}
+
+/// Registry of bridge metrics.
+#[derive(Debug)]
+pub struct Metrics {
+    messages_received: Family<u64>,
+    points_written: Family<u64>,
+    points_failed: Family<u64>,
+    extraction_failures: Family<u64>,
+    http_forwards: Family<u64>,
+    channel_backlog: Family<i64>,
+    mqtt_endpoint_active: Family<i64>,
+    mqtt_connection_failures: Family<u64>,
+    write_duration: Histogram,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    /// Create an empty registry.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            messages_received: Family::new(
+                "sinqtt_mqtt_messages_received_total",
+                "MQTT messages received per subscription.",
+                MetricType::Counter,
+                &["subscription"],
+            ),
+            points_written: Family::new(
+                "sinqtt_points_written_total",
+                "Points written to InfluxDB.",
+                MetricType::Counter,
+                &["bucket", "measurement"],
+            ),
+            points_failed: Family::new(
+                "sinqtt_points_failed_total",
+                "Points that failed to be written to InfluxDB.",
+                MetricType::Counter,
+                &["bucket", "measurement"],
+            ),
+            extraction_failures: Family::new(
+                "sinqtt_extraction_failures_total",
+                "Values that could not be extracted from a message.",
+                MetricType::Counter,
+                &["kind", "measurement"],
+            ),
+            http_forwards: Family::new(
+                "sinqtt_http_forwards_total",
+                "HTTP forward requests by outcome.",
+                MetricType::Counter,
+                &["outcome"],
+            ),
+            channel_backlog: Family::new(
+                "sinqtt_channel_backlog",
+                "Entries waiting in internal channels.",
+                MetricType::Gauge,
+                &["channel"],
+            ),
+            mqtt_endpoint_active: Family::new(
+                "sinqtt_mqtt_endpoint_active",
+                "Whether a broker endpoint is the active MQTT connection.",
+                MetricType::Gauge,
+                &["broker", "endpoint"],
+            ),
+            mqtt_connection_failures: Family::new(
+                "sinqtt_mqtt_connection_failures_total",
+                "Failed or lost MQTT connections per broker endpoint.",
+                MetricType::Counter,
+                &["broker", "endpoint"],
+            ),
+            write_duration: Histogram::new(
+                "sinqtt_influxdb_write_duration_seconds",
+                "Latency of InfluxDB write requests.",
+                WRITE_DURATION_BUCKETS,
+            ),
+        }
+    }
+
+    /// Count a message received on a subscription.
+    pub fn message_received(&self, subscription: &str) {
+        self.messages_received.add(&[subscription], 1);
+    }
+
+    /// Count points written to a bucket.
+    pub fn points_written(&self, bucket: &str, measurement: &str, count: u64) {
+        self.points_written.add(&[bucket, measurement], count);
+    }
+
+    /// Count points that failed to be written to a bucket.
+    pub fn points_failed(&self, bucket: &str, measurement: &str, count: u64) {
+        self.points_failed.add(&[bucket, measurement], count);
+    }
+
+    /// Count a value that could not be extracted from a message.
+    pub fn extraction_failed(&self, kind: ExtractionKind, measurement: &str) {
+        self.extraction_failures
+            .add(&[kind.as_str(), measurement], 1);
+    }
+
+    /// Count an HTTP forward request.
+    pub fn http_forward(&self, success: bool) {
+        let outcome = if success { "success" } else { "failure" };
+        self.http_forwards.add(&[outcome], 1);
+    }
+
+    /// Record the number of entries waiting in a channel.
+    pub fn set_channel_backlog(&self, channel: &str, depth: usize) {
+        self.channel_backlog
+            .set(&[channel], i64::try_from(depth).unwrap_or(i64::MAX));
+    }
+
+    /// Record whether a broker endpoint is the active connection.
+    pub fn set_mqtt_endpoint_active(&self, broker: &str, endpoint: &str, active: bool) {
+        self.mqtt_endpoint_active
+            .set(&[broker, endpoint], i64::from(active));
+    }
+
+    /// Count a failed or lost connection to a broker endpoint.
+    pub fn mqtt_connection_failed(&self, broker: &str, endpoint: &str) {
+        self.mqtt_connection_failures.add(&[broker, endpoint], 1);
+    }
+
+    /// Record the latency of an `InfluxDB` write request.
+    pub fn observe_write_duration(&self, duration: Duration) {
+        self.write_duration.observe(duration.as_secs_f64());
+    }
+
+    /// Render all metrics in the Prometheus text exposition format.
+    #[must_use]
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        self.messages_received.render(&mut out);
+        self.points_written.render(&mut out);
+        self.points_failed.render(&mut out);
+        self.extraction_failures.render(&mut out);
+        self.http_forwards.render(&mut out);
+        self.channel_backlog.render(&mut out);
+        self.mqtt_endpoint_active.render(&mut out);
+        self.mqtt_connection_failures.render(&mut out);
+        self.write_duration.render(&mut out);
+        out
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum MetricType {
+    Counter,
+    Gauge,
+}
+
+/// A labeled set of counters or gauges sharing a name.
+#[derive(Debug)]
+struct Family<T> {
+    name: &'static str,
+    help: &'static str,
+    metric_type: MetricType,
+    label_names: &'static [&'static str],
+    values: Mutex<BTreeMap<Vec<String>, T>>,
+}
+
+impl<T> Family<T>
+where
+    T: Copy + Default + std::ops::AddAssign + std::fmt::Display,
+{
+    fn new(
+        name: &'static str,
+        help: &'static str,
+        metric_type: MetricType,
+        label_names: &'static [&'static str],
+    ) -> Self {
+        Self {
+            name,
+            help,
+            metric_type,
+            label_names,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn add(&self, labels: &[&str], value: T) {
+        let mut values = self.values.lock().unwrap_or_else(|e| e.into_inner());
+        *values.entry(to_owned(labels)).or_default() += value;
+    }
+
+    fn set(&self, labels: &[&str], value: T) {
+        let mut values = self.values.lock().unwrap_or_else(|e| e.into_inner());
+        values.insert(to_owned(labels), value);
+    }
+
+    fn render(&self, out: &mut String) {
+        let type_name = match self.metric_type {
+            MetricType::Counter => "counter",
+            MetricType::Gauge => "gauge",
+        };
+        let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(out, "# TYPE {} {}", self.name, type_name);
+
+        let values = self.values.lock().unwrap_or_else(|e| e.into_inner());
+        for (labels, value) in values.iter() {
+            let _ = writeln!(
+                out,
+                "{}{} {}",
+                self.name,
+                format_labels(self.label_names, labels),
+                value
+            );
+        }
+    }
+}
+
+/// An unlabeled histogram with fixed bucket boundaries.
+#[derive(Debug)]
+struct Histogram {
+    name: &'static str,
+    help: &'static str,
+    bounds: &'static [f64],
+    state: Mutex<HistogramState>,
+}
+
+#[derive(Debug, Default)]
+struct HistogramState {
+    /// Non-cumulative count per bucket.
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(name: &'static str, help: &'static str, bounds: &'static [f64]) -> Self {
+        Self {
+            name,
+            help,
+            bounds,
+            state: Mutex::new(HistogramState {
+                counts: vec![0; bounds.len()],
+                ..HistogramState::default()
+            }),
+        }
+    }
+
+    fn observe(&self, value: f64) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(i) = self.bounds.iter().position(|&bound| value <= bound) {
+            state.counts[i] += 1;
+        }
+        state.sum += value;
+        state.count += 1;
+    }
+
+    fn render(&self, out: &mut String) {
+        let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(out, "# TYPE {} histogram", self.name);
+
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds.iter().zip(&state.counts) {
+            cumulative += count;
+            let _ = writeln!(out, "{}_bucket{{le=\"{bound}\"}} {cumulative}", self.name);
+        }
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", self.name, state.count);
+        let _ = writeln!(out, "{}_sum {}", self.name, state.sum);
+        let _ = writeln!(out, "{}_count {}", self.name, state.count);
+    }
+}
+
+fn to_owned(labels: &[&str]) -> Vec<String> {
+    labels.iter().map(|l| (*l).to_string()).collect()
+}
+
+/// Format a label set, e.g. `{bucket="a",measurement="b"}`.
+fn format_labels(names: &[&str], values: &[String]) -> String {
+    let pairs: Vec<String> = names
+        .iter()
+        .zip(values)
+        .map(|(name, value)| format!("{name}=\"{}\"", escape_label(value)))
+        .collect();
+
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_counters() {
+        let metrics = Metrics::new();
+        metrics.message_received("sensors/#");
+        metrics.message_received("sensors/#");
+        metrics.points_written("bucket", "temperature", 5);
+
+        let output = metrics.render();
+        assert!(output.contains("# TYPE sinqtt_mqtt_messages_received_total counter"));
+        assert!(
+            output.contains("sinqtt_mqtt_messages_received_total{subscription=\"sensors/#\"} 2")
+        );
+        assert!(output.contains(
+            "sinqtt_points_written_total{bucket=\"bucket\",measurement=\"temperature\"} 5"
+        ));
+    }
+
+    #[test]
+    fn test_render_gauge_overwrites() {
+        let metrics = Metrics::new();
+        metrics.set_channel_backlog("mqtt", 10);
+        metrics.set_channel_backlog("mqtt", 3);
+
+        let output = metrics.render();
+        assert!(output.contains("# TYPE sinqtt_channel_backlog gauge"));
+        assert!(output.contains("sinqtt_channel_backlog{channel=\"mqtt\"} 3"));
+    }
+
+    #[test]
+    fn test_render_histogram() {
+        let metrics = Metrics::new();
+        metrics.observe_write_duration(Duration::from_millis(20));
+        metrics.observe_write_duration(Duration::from_secs(30));
+
+        let output = metrics.render();
+        let name = "sinqtt_influxdb_write_duration_seconds";
+        assert!(output.contains(&format!("{name}_bucket{{le=\"0.01\"}} 0")));
+        assert!(output.contains(&format!("{name}_bucket{{le=\"0.025\"}} 1")));
+        assert!(output.contains(&format!("{name}_bucket{{le=\"10\"}} 1")));
+        assert!(output.contains(&format!("{name}_bucket{{le=\"+Inf\"}} 2")));
+        assert!(output.contains(&format!("{name}_count 2")));
+    }
+
+    #[test]
+    fn test_extraction_kind() {
+        assert_eq!(
+            ExtractionKind::of("$.payload.temp"),
+            ExtractionKind::JsonPath
+        );
+        assert_eq!(
+            ExtractionKind::of("= $.payload.a * 2"),
+            ExtractionKind::Expression
+        );
+
+        let metrics = Metrics::new();
+        metrics.extraction_failed(ExtractionKind::Expression, "power");
+        assert!(metrics.render().contains(
+            "sinqtt_extraction_failures_total{kind=\"expression\",measurement=\"power\"} 1"
+        ));
+    }
+
+    #[test]
+    fn test_render_mqtt_endpoints() {
+        let metrics = Metrics::new();
+        metrics.set_mqtt_endpoint_active("default", "node1:1883", true);
+        metrics.set_mqtt_endpoint_active("default", "node1:1883", false);
+        metrics.set_mqtt_endpoint_active("default", "node2:1883", true);
+        metrics.mqtt_connection_failed("default", "node1:1883");
+
+        let output = metrics.render();
+        assert!(
+            output.contains(
+                "sinqtt_mqtt_endpoint_active{broker=\"default\",endpoint=\"node1:1883\"} 0"
+            )
+        );
+        assert!(
+            output.contains(
+                "sinqtt_mqtt_endpoint_active{broker=\"default\",endpoint=\"node2:1883\"} 1"
+            )
+        );
+        assert!(output.contains(
+            "sinqtt_mqtt_connection_failures_total{broker=\"default\",endpoint=\"node1:1883\"} 1"
+        ));
+    }
+
+    #[test]
+    fn test_label_escaping() {
+        let metrics = Metrics::new();
+        metrics.points_failed("b\"1", "m\\n", 1);
+
+        assert!(
+            metrics
+                .render()
+                .contains(r#"sinqtt_points_failed_total{bucket="b\"1",measurement="m\\n"} 1"#)
+        );
+    }
+}
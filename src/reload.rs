@@ -0,0 +1,242 @@
+//! Configuration hot reload on SIGHUP or file change.
+
+use crate::config::{Config, MqttConfig, load_config};
+use crate::error::ConfigError;
+use serde::Serialize;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+/// Reloads the configuration file and publishes valid configurations.
+///
+/// Subscribers receive the new configuration through a [`watch::Receiver`].
+/// An invalid configuration is logged and the current one kept.
+pub struct ConfigReloader {
+    path: PathBuf,
+    tx: watch::Sender<Arc<Config>>,
+    watch_interval: Option<Duration>,
+}
+
+impl ConfigReloader {
+    /// Create a reloader for the configuration file at `path`, starting with `config`.
+    #[must_use]
+    pub fn new(path: PathBuf, config: Config) -> (Self, watch::Receiver<Arc<Config>>) {
+        let (tx, rx) = watch::channel(Arc::new(config));
+        let reloader = Self {
+            path,
+            tx,
+            watch_interval: None,
+        };
+        (reloader, rx)
+    }
+
+    /// Also reload when the file modification time changes, checked at `interval`.
+    #[must_use]
+    pub fn watch_file(mut self, interval: Duration) -> Self {
+        self.watch_interval = Some(interval);
+        self
+    }
+
+    /// Load, validate and publish the configuration file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be loaded or fails validation;
+    /// the current configuration is left unchanged.
+    pub fn reload(&self) -> Result<(), ConfigError> {
+        let config = load_config(&self.path)?;
+
+        let current = self.tx.borrow().clone();
+        for section in restart_required(&current, &config) {
+            warn!(
+                "Changes to the '{}' section require a restart to take effect",
+                section
+            );
+        }
+
+        info!(
+            "Configuration reloaded from {:?}, {} points configured",
+            self.path,
+            config.points.len()
+        );
+        self.tx.send_replace(Arc::new(config));
+        Ok(())
+    }
+
+    /// Reload on SIGHUP and, if enabled, on file change until cancelled.
+    pub async fn run(self, cancel_token: CancellationToken) {
+        #[cfg(unix)]
+        let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+        {
+            Ok(signal) => Some(signal),
+            Err(e) => {
+                error!("Failed to install SIGHUP handler: {}", e);
+                None
+            }
+        };
+
+        let mut modified = self.modified();
+        let mut ticker = self.watch_interval.map(|interval| {
+            let mut ticker =
+                tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            ticker
+        });
+
+        loop {
+            #[cfg(unix)]
+            let hangup_received = async {
+                match &mut hangup {
+                    Some(signal) => signal.recv().await,
+                    None => std::future::pending().await,
+                }
+            };
+            #[cfg(not(unix))]
+            let hangup_received = std::future::pending::<Option<()>>();
+
+            let file_checked = async {
+                match &mut ticker {
+                    Some(ticker) => ticker.tick().await,
+                    None => std::future::pending().await,
+                }
+            };
+
+            tokio::select! {
+                _ = hangup_received => {
+                    info!("SIGHUP received, reloading configuration");
+                }
+                _ = file_checked => {
+                    let current = self.modified();
+                    if current == modified {
+                        continue;
+                    }
+                    modified = current;
+                    info!("Configuration file changed, reloading");
+                }
+                () = cancel_token.cancelled() => break,
+            }
+
+            if let Err(e) = self.reload() {
+                error!("Failed to reload configuration, keeping current: {}", e);
+            }
+        }
+
+        debug!("Configuration reloader stopped");
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        std::fs::metadata(&self.path)
+            .and_then(|m| m.modified())
+            .ok()
+    }
+}
+
+/// Sections that differ between two configurations but are only applied on restart.
+#[must_use]
+pub fn restart_required(current: &Config, new: &Config) -> Vec<&'static str> {
+    let mut sections = Vec::new();
+    // The share group only affects subscriptions, which are updated on reload
+    let new_mqtt: Vec<MqttConfig> = new
+        .mqtt
+        .iter()
+        .zip(&current.mqtt)
+        .map(|(new, current)| MqttConfig {
+            share_group: current.share_group.clone(),
+            ..new.clone()
+        })
+        .collect();
+    if current.mqtt.len() != new.mqtt.len() || differs(&current.mqtt, &new_mqtt) {
+        sections.push("mqtt");
+    }
+    if differs(&current.influxdb, &new.influxdb) {
+        sections.push("influxdb");
+    }
+    if differs(&current.server, &new.server) {
+        sections.push("server");
+    }
+    sections
+}
+
+fn differs<T: Serialize>(a: &T, b: &T) -> bool {
+    serde_json::to_value(a).ok() != serde_json::to_value(b).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn config_yaml(host: &str, measurement: &str) -> String {
+        format!(
+            r#"
+mqtt:
+  host: {host}
+  port: 1883
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: {measurement}
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#
+        )
+    }
+
+    fn write_config(file: &mut NamedTempFile, content: &str) {
+        let file = file.as_file_mut();
+        file.set_len(0).unwrap();
+        std::io::Seek::rewind(file).unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn test_reload_publishes_valid_config() {
+        let mut file = NamedTempFile::new().unwrap();
+        write_config(&mut file, &config_yaml("localhost", "first"));
+        let config = load_config(file.path()).unwrap();
+        let (reloader, rx) = ConfigReloader::new(file.path().to_path_buf(), config);
+
+        write_config(&mut file, &config_yaml("localhost", "second"));
+        reloader.reload().unwrap();
+
+        assert!(rx.has_changed().unwrap());
+        assert_eq!(rx.borrow().points[0].measurement, "second");
+    }
+
+    #[test]
+    fn test_reload_keeps_config_on_error() {
+        let mut file = NamedTempFile::new().unwrap();
+        write_config(&mut file, &config_yaml("localhost", "first"));
+        let config = load_config(file.path()).unwrap();
+        let (reloader, rx) = ConfigReloader::new(file.path().to_path_buf(), config);
+
+        write_config(&mut file, "points: []\n");
+        assert!(reloader.reload().is_err());
+
+        assert!(!rx.has_changed().unwrap());
+        assert_eq!(rx.borrow().points[0].measurement, "first");
+    }
+
+    #[test]
+    fn test_restart_required_sections() {
+        let mut file = NamedTempFile::new().unwrap();
+        write_config(&mut file, &config_yaml("localhost", "first"));
+        let current = load_config(file.path()).unwrap();
+
+        write_config(&mut file, &config_yaml("localhost", "second"));
+        let points_only = load_config(file.path()).unwrap();
+        assert!(restart_required(&current, &points_only).is_empty());
+
+        write_config(&mut file, &config_yaml("broker", "first"));
+        let new_broker = load_config(file.path()).unwrap();
+        assert_eq!(restart_required(&current, &new_broker), vec!["mqtt"]);
+    }
+}
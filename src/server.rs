@@ -0,0 +1,250 @@
+//! Embedded HTTP server exposing operational endpoints.
+
+use crate::config::{HealthConfig, ServerConfig};
+use crate::error::SinqttError;
+use crate::health::{Status, health};
+use crate::metrics::metrics;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info};
+
+/// Maximum size of a request head.
+const MAX_REQUEST_BYTES: usize = 8 * 1024;
+
+/// Time allowed for a client to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Content type of the Prometheus text exposition format.
+const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// HTTP response produced by a route.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    fn text(status: u16, body: impl Into<String>) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: body.into(),
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.status,
+            reason_phrase(self.status),
+            self.content_type,
+            self.body.len(),
+            self.body
+        )
+        .into_bytes()
+    }
+}
+
+/// Embedded HTTP server.
+pub struct Server {
+    listener: TcpListener,
+    health: Arc<HealthConfig>,
+}
+
+impl Server {
+    /// Bind the server to the configured address.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the address cannot be bound.
+    pub async fn bind(config: &ServerConfig) -> Result<Self, SinqttError> {
+        let listener = TcpListener::bind(&config.listen).await?;
+        Ok(Self {
+            listener,
+            health: Arc::new(config.health.clone()),
+        })
+    }
+
+    /// Get the bound address.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the address cannot be determined.
+    pub fn local_addr(&self) -> Result<SocketAddr, SinqttError> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Serve requests until cancelled.
+    pub async fn run(self, cancel_token: CancellationToken) {
+        if let Ok(addr) = self.listener.local_addr() {
+            info!("HTTP server listening on {}", addr);
+        }
+
+        loop {
+            tokio::select! {
+                accepted = self.listener.accept() => match accepted {
+                    Ok((stream, peer)) => {
+                        let health = self.health.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = handle_connection(stream, &health).await {
+                                debug!("HTTP connection from {} failed: {}", peer, e);
+                            }
+                        });
+                    }
+                    Err(e) => debug!("Failed to accept HTTP connection: {}", e),
+                },
+                () = cancel_token.cancelled() => {
+                    debug!("HTTP server shutting down");
+                    break;
+                }
+            }
+        }
+    }
+}
+
+async fn handle_connection(mut stream: TcpStream, health: &HealthConfig) -> std::io::Result<()> {
+    let head = match tokio::time::timeout(REQUEST_TIMEOUT, read_request_head(&mut stream)).await {
+        Ok(head) => head?,
+        Err(_) => return Ok(()),
+    };
+
+    let response = match parse_request_line(&head) {
+        Some((method, path)) => route(method, path, health),
+        None => Response::text(400, "Bad Request\n"),
+    };
+
+    stream.write_all(&response.to_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Read until the end of the request head or the size limit.
+async fn read_request_head(stream: &mut TcpStream) -> std::io::Result<String> {
+    let mut buf = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 1024];
+
+    while buf.len() < MAX_REQUEST_BYTES {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        if buf.windows(4).any(|w| w == b"\r\n\r\n") {
+            break;
+        }
+    }
+
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+/// Parse the method and path from the request line, ignoring any query string.
+fn parse_request_line(head: &str) -> Option<(&str, &str)> {
+    let mut parts = head.lines().next()?.split_whitespace();
+    let method = parts.next()?;
+    let target = parts.next()?;
+    let path = target.split('?').next().unwrap_or(target);
+    Some((method, path))
+}
+
+/// Produce the response for a request.
+#[must_use]
+pub fn route(method: &str, path: &str, health_config: &HealthConfig) -> Response {
+    if method != "GET" {
+        return Response::text(405, "Method Not Allowed\n");
+    }
+
+    match path {
+        "/metrics" => Response {
+            status: 200,
+            content_type: METRICS_CONTENT_TYPE,
+            body: metrics().render(),
+        },
+        "/healthz" => status_response(&health().liveness(health_config, Instant::now())),
+        "/readyz" => status_response(&health().readiness(health_config, Instant::now())),
+        _ => Response::text(404, "Not Found\n"),
+    }
+}
+
+fn status_response(status: &Status) -> Response {
+    let code = if status.is_ok() { 200 } else { 503 };
+    Response::text(code, status.render())
+}
+
+const fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_request_line() {
+        assert_eq!(
+            parse_request_line("GET /metrics?x=1 HTTP/1.1\r\nHost: a\r\n\r\n"),
+            Some(("GET", "/metrics"))
+        );
+        assert_eq!(parse_request_line(""), None);
+        assert_eq!(parse_request_line("GET"), None);
+    }
+
+    #[test]
+    fn test_route() {
+        let health = HealthConfig::default();
+        let response = route("GET", "/metrics", &health);
+        assert_eq!(response.status, 200);
+        assert_eq!(response.content_type, METRICS_CONTENT_TYPE);
+        assert!(
+            response
+                .body
+                .contains("# TYPE sinqtt_points_written_total counter")
+        );
+
+        assert_eq!(route("GET", "/healthz", &health).status, 200);
+        assert!(
+            route("GET", "/readyz", &health)
+                .body
+                .contains("mqtt_connected")
+        );
+        assert_eq!(route("GET", "/unknown", &health).status, 404);
+        assert_eq!(route("POST", "/metrics", &health).status, 405);
+    }
+
+    #[tokio::test]
+    async fn test_serves_metrics_over_tcp() {
+        let config = ServerConfig {
+            listen: "127.0.0.1:0".to_string(),
+            health: HealthConfig::default(),
+        };
+        let server = Server::bind(&config).await.unwrap();
+        let addr = server.local_addr().unwrap();
+        let cancel_token = CancellationToken::new();
+        let handle = tokio::spawn(server.run(cancel_token.clone()));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("sinqtt_influxdb_write_duration_seconds_count"));
+
+        cancel_token.cancel();
+        handle.await.unwrap();
+    }
+}
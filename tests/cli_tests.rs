@@ -181,6 +181,15 @@ fn test_daemon_short_option_accepted() {
         .success();
 }
 
+#[test]
+fn test_watch_option_accepted() {
+    let config_file = create_valid_config();
+    let mut cmd = sinqtt_cmd();
+    cmd.args(["-c", config_file.path().to_str().unwrap(), "-t", "--watch"])
+        .assert()
+        .success();
+}
+
 // ============================================================================
 // Config Validation Tests
 // ============================================================================
@@ -369,3 +378,43 @@ points:
         .assert()
         .success();
 }
+
+#[cfg(feature = "tls")]
+#[test]
+fn test_test_mode_mismatched_client_key() {
+    let fixtures = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tls");
+    let config = format!(
+        r#"
+mqtt:
+  host: localhost
+  port: 8883
+  tls: true
+  certfile: {}
+  keyfile: {}
+
+influxdb:
+  host: localhost
+  port: 8181
+  token: test-token
+  org: test-org
+  bucket: test-bucket
+
+points:
+  - measurement: test
+    topic: test
+    fields:
+      value: "$.payload"
+"#,
+        fixtures.join("client.crt").display(),
+        fixtures.join("other.key").display()
+    );
+
+    let mut file = NamedTempFile::new().unwrap();
+    file.write_all(config.as_bytes()).unwrap();
+
+    let mut cmd = sinqtt_cmd();
+    cmd.args(["-c", file.path().to_str().unwrap(), "-t"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("does not match key file"));
+}
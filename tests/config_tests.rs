@@ -1,6 +1,9 @@
 //! Comprehensive tests for configuration module.
 
-use sinqtt::config::{Config, FieldSpec};
+use sinqtt::config::{
+    Config, FailoverStrategy, FieldSpec, FlattenArrays, InfluxDBApiVersion, JwtAlgorithm,
+    MqttProtocol, MqttTransport,
+};
 use sinqtt::error::ConfigError;
 use sinqtt::load_config;
 use std::io::Write;
@@ -35,10 +38,10 @@ points:
       value: "$.payload"
 "#;
     let config = load_yaml_config(yaml).unwrap();
-    assert_eq!(config.mqtt.host, "localhost");
-    assert_eq!(config.mqtt.port, 1883);
-    assert!(config.mqtt.username.is_none());
-    assert!(config.mqtt.password.is_none());
+    assert_eq!(config.mqtt[0].host, "localhost");
+    assert_eq!(config.mqtt[0].port, 1883);
+    assert!(config.mqtt[0].username.is_none());
+    assert!(config.mqtt[0].password.is_none());
 }
 
 #[test]
@@ -61,10 +64,10 @@ points:
       value: "$.payload"
 "#;
     let config = load_yaml_config(yaml).unwrap();
-    assert_eq!(config.mqtt.host, "mqtt.example.com");
-    assert_eq!(config.mqtt.port, 8883);
-    assert_eq!(config.mqtt.username, Some("user".to_string()));
-    assert_eq!(config.mqtt.password, Some("secret".to_string()));
+    assert_eq!(config.mqtt[0].host, "mqtt.example.com");
+    assert_eq!(config.mqtt[0].port, 8883);
+    assert_eq!(config.mqtt[0].username, Some("user".to_string()));
+    assert_eq!(config.mqtt[0].password, Some("secret".to_string()));
 }
 
 #[test]
@@ -85,7 +88,7 @@ points:
       value: "$.payload"
 "#;
     let config = load_yaml_config(yaml).unwrap();
-    assert_eq!(config.mqtt.port, 0);
+    assert_eq!(config.mqtt[0].port, 0);
 }
 
 #[test]
@@ -106,7 +109,7 @@ points:
       value: "$.payload"
 "#;
     let config = load_yaml_config(yaml).unwrap();
-    assert_eq!(config.mqtt.port, 65535);
+    assert_eq!(config.mqtt[0].port, 65535);
 }
 
 #[test]
@@ -182,6 +185,269 @@ points:
     assert!(config.influxdb.enable_gzip);
 }
 
+#[test]
+fn test_influxdb_batch_defaults() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  port: 1883
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let config = load_yaml_config(yaml).unwrap();
+    assert_eq!(config.influxdb.batch.max_lines, 1000);
+    assert_eq!(config.influxdb.batch.max_bytes, 1024 * 1024);
+    assert_eq!(config.influxdb.batch.max_latency_ms, 1000);
+}
+
+#[test]
+fn test_influxdb_batch_config() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  port: 1883
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+  batch:
+    max_lines: 500
+    max_bytes: 65536
+    max_latency_ms: 250
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let config = load_yaml_config(yaml).unwrap();
+    assert_eq!(config.influxdb.batch.max_lines, 500);
+    assert_eq!(config.influxdb.batch.max_bytes, 65536);
+    assert_eq!(config.influxdb.batch.max_latency_ms, 250);
+}
+
+#[test]
+fn test_influxdb_batch_zero_lines_rejected() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  port: 1883
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+  batch:
+    max_lines: 0
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let result = load_yaml_config(yaml);
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_influxdb_retry_config() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  port: 1883
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+  retry:
+    max_retries: 5
+    initial_delay_ms: 100
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let config = load_yaml_config(yaml).unwrap();
+    assert_eq!(config.influxdb.retry.max_retries, 5);
+    assert_eq!(config.influxdb.retry.initial_delay_ms, 100);
+    assert_eq!(config.influxdb.retry.max_delay_ms, 30_000); // Default
+}
+
+#[test]
+fn test_influxdb_retry_max_below_initial_rejected() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  port: 1883
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+  retry:
+    initial_delay_ms: 5000
+    max_delay_ms: 1000
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let result = load_yaml_config(yaml);
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_influxdb_spool_config() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  port: 1883
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+  spool:
+    directory: /var/lib/sinqtt/spool
+    max_bytes: 1048576
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let config = load_yaml_config(yaml).unwrap();
+    let spool = config.influxdb.spool.unwrap();
+    assert_eq!(spool.directory.to_str(), Some("/var/lib/sinqtt/spool"));
+    assert_eq!(spool.max_bytes, 1_048_576);
+    assert_eq!(spool.replay_interval_ms, 5000); // Default
+}
+
+#[test]
+fn test_influxdb_spool_zero_budget_rejected() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  port: 1883
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+  spool:
+    directory: /tmp/spool
+    max_bytes: 0
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let result = load_yaml_config(yaml);
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_influxdb_api_version_default_v2() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  port: 1883
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let config = load_yaml_config(yaml).unwrap();
+    assert_eq!(config.influxdb.api_version, InfluxDBApiVersion::V2);
+}
+
+#[test]
+fn test_influxdb_api_version_v3_without_org() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  port: 1883
+influxdb:
+  host: localhost
+  token: token
+  bucket: sensors
+  api_version: v3
+  accept_partial: false
+  no_sync: true
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let config = load_yaml_config(yaml).unwrap();
+    assert_eq!(config.influxdb.api_version, InfluxDBApiVersion::V3);
+    assert!(config.influxdb.org.is_empty());
+    assert_eq!(config.influxdb.accept_partial, Some(false));
+    assert_eq!(config.influxdb.no_sync, Some(true));
+}
+
+#[test]
+fn test_influxdb_v2_requires_org() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  port: 1883
+influxdb:
+  host: localhost
+  token: token
+  bucket: bucket
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let result = load_yaml_config(yaml);
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_influxdb_v3_options_rejected_for_v2() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  port: 1883
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+  no_sync: true
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let result = load_yaml_config(yaml);
+    assert!(result.is_err());
+}
+
 #[test]
 fn test_influxdb_empty_token_rejected() {
     let yaml = r#"
@@ -410,7 +676,7 @@ points:
 }
 
 #[test]
-fn test_point_measurement_jsonpath() {
+fn test_point_timestamp_config() {
     let yaml = r#"
 mqtt:
   host: localhost
@@ -421,17 +687,23 @@ influxdb:
   org: org
   bucket: bucket
 points:
-  - measurement: "$.payload.type"
-    topic: test
+  - measurement: test
+    topic: test/#
+    timestamp: "$.payload.time"
+    timestamp_format: "%Y-%m-%d %H:%M:%S"
+    timezone: "+01:00"
     fields:
-      value: "$.payload"
+      value: "$.payload.value"
 "#;
     let config = load_yaml_config(yaml).unwrap();
-    assert_eq!(config.points[0].measurement, "$.payload.type");
+    let point = &config.points[0];
+    assert_eq!(point.timestamp.as_deref(), Some("$.payload.time"));
+    assert_eq!(point.timestamp_format.as_deref(), Some("%Y-%m-%d %H:%M:%S"));
+    assert_eq!(point.timezone.as_deref(), Some("+01:00"));
 }
 
 #[test]
-fn test_point_invalid_measurement_jsonpath() {
+fn test_point_foreach_config() {
     let yaml = r#"
 mqtt:
   host: localhost
@@ -442,21 +714,27 @@ influxdb:
   org: org
   bucket: bucket
 points:
-  - measurement: "$.invalid["
-    topic: test
+  - measurement: test
+    topic: test/#
+    foreach: "$.payload.readings"
     fields:
-      value: "$.payload"
+      value: "$.item.value"
+  - measurement: test2
+    topic: test/#
+    iterate: "$.payload.items"
+    fields:
+      value: "$.item"
 "#;
-    let result = load_yaml_config(yaml);
-    assert!(result.is_err());
+    let config = load_yaml_config(yaml).unwrap();
+    assert_eq!(
+        config.points[0].foreach.as_deref(),
+        Some("$.payload.readings")
+    );
+    assert_eq!(config.points[1].foreach.as_deref(), Some("$.payload.items"));
 }
 
-// ============================================================================
-// Config Tests
-// ============================================================================
-
 #[test]
-fn test_empty_points_rejected() {
+fn test_point_foreach_requires_jsonpath() {
     let yaml = r#"
 mqtt:
   host: localhost
@@ -466,15 +744,23 @@ influxdb:
   token: token
   org: org
   bucket: bucket
-points: []
+points:
+  - measurement: test
+    topic: test/#
+    foreach: readings
+    fields:
+      value: "$.item"
 "#;
     let result = load_yaml_config(yaml);
     assert!(result.is_err());
 }
 
 #[test]
-fn test_missing_mqtt_rejected() {
+fn test_point_invalid_timestamp_format() {
     let yaml = r#"
+mqtt:
+  host: localhost
+  port: 1883
 influxdb:
   host: localhost
   token: token
@@ -482,23 +768,173 @@ influxdb:
   bucket: bucket
 points:
   - measurement: test
-    topic: test
+    topic: test/#
+    timestamp: "$.payload.time"
+    timestamp_format: fortnights
     fields:
-      value: "$.payload"
+      value: "$.payload.value"
 "#;
     let result = load_yaml_config(yaml);
     assert!(result.is_err());
 }
 
 #[test]
-fn test_missing_influxdb_rejected() {
+fn test_point_invalid_timezone() {
     let yaml = r#"
 mqtt:
   host: localhost
   port: 1883
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
 points:
   - measurement: test
-    topic: test
+    topic: test/#
+    timestamp: "$.payload.time"
+    timezone: "Nowhere/Special"
+    fields:
+      value: "$.payload.value"
+"#;
+    let result = load_yaml_config(yaml);
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_point_measurement_jsonpath() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  port: 1883
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: "$.payload.type"
+    topic: test
+    fields:
+      value: "$.payload"
+"#;
+    let config = load_yaml_config(yaml).unwrap();
+    assert_eq!(config.points[0].measurement, "$.payload.type");
+}
+
+#[test]
+fn test_point_invalid_measurement_jsonpath() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  port: 1883
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: "$.invalid["
+    topic: test
+    fields:
+      value: "$.payload"
+"#;
+    let result = load_yaml_config(yaml);
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_point_invalid_tag_jsonpath() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: environment
+    topic: test
+    fields:
+      value: "$.payload"
+    tags:
+      room: "$.payload.room["
+"#;
+    let result = load_yaml_config(yaml);
+    assert!(matches!(result, Err(ConfigError::InvalidJsonPath(_))));
+}
+
+#[test]
+fn test_point_invalid_httpcontent_jsonpath() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: environment
+    topic: test
+    fields:
+      value: "$.payload"
+    httpcontent:
+      room: "$.payload.room["
+"#;
+    let result = load_yaml_config(yaml);
+    assert!(matches!(result, Err(ConfigError::InvalidJsonPath(_))));
+}
+
+// ============================================================================
+// Config Tests
+// ============================================================================
+
+#[test]
+fn test_empty_points_rejected() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  port: 1883
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points: []
+"#;
+    let result = load_yaml_config(yaml);
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_missing_mqtt_rejected() {
+    let yaml = r#"
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: test
+    topic: test
+    fields:
+      value: "$.payload"
+"#;
+    let result = load_yaml_config(yaml);
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_missing_influxdb_rejected() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  port: 1883
+points:
+  - measurement: test
+    topic: test
     fields:
       value: "$.payload"
 "#;
@@ -574,7 +1010,7 @@ points:
       value: "$.payload"
 "#;
     let config = load_yaml_config(yaml).unwrap();
-    assert_eq!(config.mqtt.host, "localhost");
+    assert_eq!(config.mqtt[0].host, "localhost");
 }
 
 #[test]
@@ -598,7 +1034,7 @@ points:
       value: "$.payload"
 "#;
     let config = load_yaml_config(yaml).unwrap();
-    assert_eq!(config.mqtt.host, "mqtt.example.com");
+    assert_eq!(config.mqtt[0].host, "mqtt.example.com");
 
     // SAFETY: Test is single-threaded
     unsafe { std::env::remove_var("SINQTT_MQTT_HOST") };
@@ -725,3 +1161,1154 @@ points:
         _ => panic!("Expected Typed field spec"),
     }
 }
+
+#[test]
+fn test_flatten_field_config() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  port: 1883
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      "*": "$.payload"
+      "env_*":
+        value: "$.payload.env"
+        separator: "_"
+        exclude: ["debug*"]
+        max_depth: 2
+        arrays: json
+"#;
+    let config = load_yaml_config(yaml).unwrap();
+    let fields = &config.points[0].fields;
+
+    assert!(matches!(fields.get("*"), Some(FieldSpec::Simple(s)) if s == "$.payload"));
+    match fields.get("env_*").unwrap() {
+        FieldSpec::Flatten(fc) => {
+            assert_eq!(fc.value, "$.payload.env");
+            assert_eq!(fc.separator, "_");
+            assert_eq!(fc.exclude, vec!["debug*".to_string()]);
+            assert!(fc.include.is_empty());
+            assert_eq!(fc.max_depth, Some(2));
+            assert_eq!(fc.arrays, FlattenArrays::Json);
+        }
+        _ => panic!("Expected Flatten field spec"),
+    }
+}
+
+#[test]
+fn test_flatten_options_require_wildcard_name() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  port: 1883
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      env:
+        value: "$.payload.env"
+        separator: "_"
+"#;
+    let result = load_yaml_config(yaml);
+    assert!(matches!(result, Err(ConfigError::Validation(_))));
+}
+
+#[test]
+fn test_flatten_requires_jsonpath() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  port: 1883
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      "*": "payload"
+"#;
+    let result = load_yaml_config(yaml);
+    assert!(matches!(result, Err(ConfigError::Validation(_))));
+}
+
+#[test]
+fn test_server_config() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  port: 1883
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+server:
+  listen: "127.0.0.1:9100"
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let config = load_yaml_config(yaml).unwrap();
+    assert_eq!(config.server.unwrap().listen, "127.0.0.1:9100");
+}
+
+#[test]
+fn test_server_config_defaults() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  port: 1883
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+server: {}
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let config = load_yaml_config(yaml).unwrap();
+    let server = config.server.unwrap();
+    assert_eq!(server.listen, "0.0.0.0:9464");
+    assert_eq!(server.health.write_staleness_secs, 300);
+    assert_eq!(server.health.max_disconnected_secs, None);
+}
+
+#[test]
+fn test_server_health_config() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  port: 1883
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+server:
+  health:
+    write_staleness_secs: 60
+    max_disconnected_secs: 600
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let config = load_yaml_config(yaml).unwrap();
+    let health = config.server.unwrap().health;
+    assert_eq!(health.write_staleness_secs, 60);
+    assert_eq!(health.max_disconnected_secs, Some(600));
+}
+
+#[test]
+fn test_server_invalid_listen_address() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  port: 1883
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+server:
+  listen: "localhost"
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let result = load_yaml_config(yaml);
+    assert!(matches!(result, Err(ConfigError::Validation(_))));
+}
+
+#[test]
+fn test_mqtt_session_defaults() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  port: 1883
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let config = load_yaml_config(yaml).unwrap();
+    assert_eq!(config.mqtt[0].protocol, MqttProtocol::V3);
+    assert_eq!(config.mqtt[0].client_id, None);
+    assert!(config.mqtt[0].clean_session);
+    assert!(!config.mqtt[0].ack_after_write);
+}
+
+#[test]
+fn test_mqtt_persistent_session() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  port: 1883
+  client_id: sinqtt-1
+  clean_session: false
+  ack_after_write: true
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let config = load_yaml_config(yaml).unwrap();
+    assert_eq!(config.mqtt[0].client_id.as_deref(), Some("sinqtt-1"));
+    assert!(!config.mqtt[0].clean_session);
+    assert!(config.mqtt[0].ack_after_write);
+}
+
+#[test]
+fn test_mqtt_persistent_session_requires_client_id() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  port: 1883
+  clean_session: false
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let result = load_yaml_config(yaml);
+    assert!(matches!(result, Err(ConfigError::Validation(_))));
+}
+
+#[test]
+fn test_mqtt_protocol_v5() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  port: 1883
+  protocol: v5
+  client_id: sinqtt-1
+  clean_session: false
+  session_expiry_secs: 3600
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let config = load_yaml_config(yaml).unwrap();
+    assert_eq!(config.mqtt[0].protocol, MqttProtocol::V5);
+    assert_eq!(config.mqtt[0].session_expiry_secs, Some(3600));
+}
+
+#[test]
+fn test_mqtt_session_expiry_requires_v5() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  port: 1883
+  session_expiry_secs: 3600
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let result = load_yaml_config(yaml);
+    assert!(matches!(result, Err(ConfigError::Validation(_))));
+}
+
+#[test]
+fn test_mqtt_share_group() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  port: 1883
+  share_group: sinqtt
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+  - measurement: shared
+    topic: $share/other/shared/#
+    fields:
+      value: "$.payload"
+"#;
+    let config = load_yaml_config(yaml).unwrap();
+    assert_eq!(config.mqtt[0].share_group.as_deref(), Some("sinqtt"));
+}
+
+#[test]
+fn test_mqtt_share_group_invalid() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  port: 1883
+  share_group: "a/b"
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let result = load_yaml_config(yaml);
+    assert!(matches!(result, Err(ConfigError::Validation(_))));
+}
+
+#[test]
+fn test_point_shared_topic_requires_group_and_filter() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  port: 1883
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: test
+    topic: $share/sinqtt
+    fields:
+      value: "$.payload"
+"#;
+    let result = load_yaml_config(yaml);
+    assert!(matches!(result, Err(ConfigError::Validation(_))));
+}
+
+#[test]
+fn test_point_qos() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  port: 1883
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: default
+    topic: a/#
+    fields:
+      value: "$.payload"
+  - measurement: exactly_once
+    topic: b/#
+    qos: 2
+    fields:
+      value: "$.payload"
+"#;
+    let config = load_yaml_config(yaml).unwrap();
+    assert_eq!(config.points[0].qos, 1);
+    assert_eq!(config.points[1].qos, 2);
+}
+
+#[test]
+fn test_point_qos_invalid() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  port: 1883
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: test
+    topic: test/#
+    qos: 3
+    fields:
+      value: "$.payload"
+"#;
+    let result = load_yaml_config(yaml);
+    assert!(matches!(result, Err(ConfigError::Validation(_))));
+}
+
+#[test]
+fn test_mqtt_websocket_transport() {
+    let yaml = r#"
+mqtt:
+  host: broker.example.com
+  port: 8080
+  transport: websocket
+  path: /ws
+  headers:
+    Proxy-Authorization: Basic dXNlcjpwYXNz
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let config = load_yaml_config(yaml).unwrap();
+    assert_eq!(config.mqtt[0].transport, MqttTransport::Websocket);
+    assert_eq!(config.mqtt[0].path.as_deref(), Some("/ws"));
+    assert_eq!(
+        config.mqtt[0].headers["Proxy-Authorization"],
+        "Basic dXNlcjpwYXNz"
+    );
+}
+
+#[test]
+fn test_mqtt_websocket_url_host() {
+    let yaml = r#"
+mqtt:
+  host: wss://broker.example.com/mqtt
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let config = load_yaml_config(yaml).unwrap();
+    assert!(config.mqtt[0].is_websocket());
+    assert_eq!(config.mqtt[0].port, 1883);
+}
+
+#[test]
+fn test_mqtt_invalid_url_scheme() {
+    let yaml = r#"
+mqtt:
+  host: http://broker.example.com
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let result = load_yaml_config(yaml);
+    assert!(matches!(result, Err(ConfigError::Validation(_))));
+}
+
+#[test]
+fn test_mqtt_headers_require_websocket() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  port: 1883
+  headers:
+    Authorization: Basic dXNlcjpwYXNz
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let result = load_yaml_config(yaml);
+    assert!(matches!(result, Err(ConfigError::Validation(_))));
+}
+
+#[test]
+fn test_mqtt_multiple_brokers() {
+    let yaml = r#"
+mqtt:
+  - name: eu
+    host: mqtt-eu.example.com
+    username: bridge
+    password: secret
+  - name: us
+    host: mqtt-us.example.com
+    port: 8883
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: all
+    topic: test/#
+    fields:
+      value: "$.payload"
+  - measurement: eu_only
+    topic: eu/#
+    broker: eu
+    fields:
+      value: "$.payload"
+  - measurement: both
+    topic: both/#
+    broker: [eu, us]
+    fields:
+      value: "$.payload"
+"#;
+    let config = load_yaml_config(yaml).unwrap();
+    assert_eq!(config.mqtt.len(), 2);
+    assert_eq!(config.mqtt[0].name(), "eu");
+    assert_eq!(config.mqtt[0].username.as_deref(), Some("bridge"));
+    assert_eq!(config.mqtt[1].name(), "us");
+    assert_eq!(config.mqtt[1].port, 8883);
+
+    assert!(config.points[0].brokers.is_empty());
+    assert!(config.points[0].applies_to("us"));
+    assert_eq!(config.points[1].brokers, vec!["eu"]);
+    assert!(!config.points[1].applies_to("us"));
+    assert_eq!(config.points[2].brokers, vec!["eu", "us"]);
+}
+
+#[test]
+fn test_mqtt_single_broker_default_name() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: test
+    topic: test/#
+    broker: default
+    fields:
+      value: "$.payload"
+"#;
+    let config = load_yaml_config(yaml).unwrap();
+    assert_eq!(config.mqtt.len(), 1);
+    assert_eq!(config.mqtt[0].name(), "default");
+    assert!(config.points[0].applies_to("default"));
+}
+
+#[test]
+fn test_mqtt_multiple_brokers_require_unique_names() {
+    let unnamed = r#"
+mqtt:
+  - host: mqtt-eu.example.com
+  - name: us
+    host: mqtt-us.example.com
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let result = load_yaml_config(unnamed);
+    assert!(matches!(result, Err(ConfigError::Validation(_))));
+
+    let duplicate = unnamed.replace("- host: mqtt-eu", "- name: us\n    host: mqtt-eu");
+    let result = load_yaml_config(&duplicate);
+    assert!(matches!(result, Err(ConfigError::Validation(_))));
+}
+
+#[test]
+fn test_point_unknown_broker() {
+    let yaml = r#"
+mqtt:
+  - name: eu
+    host: mqtt-eu.example.com
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: test
+    topic: test/#
+    broker: us
+    fields:
+      value: "$.payload"
+"#;
+    let result = load_yaml_config(yaml);
+    assert!(matches!(result, Err(ConfigError::Validation(_))));
+}
+
+#[test]
+fn test_mqtt_unknown_field_in_broker_list() {
+    let yaml = r#"
+mqtt:
+  - name: eu
+    host: mqtt-eu.example.com
+    hots: typo
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let err = load_yaml_config(yaml).unwrap_err();
+    assert!(err.to_string().contains("hots"));
+}
+
+#[test]
+fn test_mqtt_failover_defaults() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let config = load_yaml_config(yaml).unwrap();
+    let mqtt = &config.mqtt[0];
+    assert!(mqtt.failover_hosts.is_empty());
+    assert_eq!(mqtt.failover, FailoverStrategy::Ordered);
+    assert_eq!(mqtt.reconnect.initial_delay_ms, 1000);
+    assert_eq!(mqtt.reconnect.max_delay_ms, 30_000);
+    assert_eq!(mqtt.endpoints().len(), 1);
+}
+
+#[test]
+fn test_mqtt_failover_hosts() {
+    let yaml = r#"
+mqtt:
+  host: node1
+  port: 8883
+  failover_hosts:
+    - node2
+    - node3:1884
+    - "[::1]:1885"
+    - "fd00::3"
+  failover: round_robin
+  reconnect:
+    initial_delay_ms: 200
+    max_delay_ms: 5000
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let config = load_yaml_config(yaml).unwrap();
+    let mqtt = &config.mqtt[0];
+    assert_eq!(mqtt.failover, FailoverStrategy::RoundRobin);
+    assert_eq!(mqtt.reconnect.initial_delay_ms, 200);
+    assert_eq!(mqtt.reconnect.max_delay_ms, 5000);
+
+    let endpoints: Vec<(String, u16)> = mqtt
+        .endpoints()
+        .into_iter()
+        .map(|e| (e.host, e.port))
+        .collect();
+    assert_eq!(
+        endpoints,
+        vec![
+            ("node1".to_string(), 8883),
+            ("node2".to_string(), 8883),
+            ("node3".to_string(), 1884),
+            ("::1".to_string(), 1885),
+            ("fd00::3".to_string(), 8883),
+        ]
+    );
+}
+
+#[test]
+fn test_mqtt_failover_host_invalid_scheme() {
+    let yaml = r#"
+mqtt:
+  host: node1
+  failover_hosts:
+    - http://node2
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let result = load_yaml_config(yaml);
+    assert!(matches!(result, Err(ConfigError::Validation(_))));
+}
+
+#[test]
+fn test_mqtt_reconnect_delay_invalid() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  reconnect:
+    initial_delay_ms: 5000
+    max_delay_ms: 1000
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let result = load_yaml_config(yaml);
+    assert!(matches!(result, Err(ConfigError::Validation(_))));
+}
+
+#[test]
+fn test_mqtt_username_only() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  username: device-1
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let config = load_yaml_config(yaml).unwrap();
+    assert_eq!(config.mqtt[0].username.as_deref(), Some("device-1"));
+    assert!(config.mqtt[0].password.is_none());
+}
+
+#[test]
+fn test_mqtt_password_file() {
+    let mut password_file = NamedTempFile::new().unwrap();
+    password_file.write_all(b"secret\n").unwrap();
+    let yaml = format!(
+        r#"
+mqtt:
+  host: localhost
+  username: bridge
+  password_file: {}
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#,
+        password_file.path().display()
+    );
+    let config = load_yaml_config(&yaml).unwrap();
+    assert_eq!(
+        config.mqtt[0].password_file.as_deref(),
+        Some(password_file.path())
+    );
+}
+
+#[test]
+fn test_mqtt_password_file_missing() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  username: bridge
+  password_file: /nonexistent/mqtt-password
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let result = load_yaml_config(yaml);
+    assert!(matches!(result, Err(ConfigError::FileNotFound(_))));
+}
+
+#[test]
+fn test_mqtt_jwt() {
+    let keyfile = NamedTempFile::new().unwrap();
+    let yaml = format!(
+        r#"
+mqtt:
+  host: localhost
+  username: unused
+  jwt:
+    keyfile: {}
+    algorithm: ES256
+    audience: my-project
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#,
+        keyfile.path().display()
+    );
+    let config = load_yaml_config(&yaml).unwrap();
+    let jwt = config.mqtt[0].jwt.as_ref().unwrap();
+    assert_eq!(jwt.algorithm, JwtAlgorithm::ES256);
+    assert_eq!(jwt.audience.as_deref(), Some("my-project"));
+    assert_eq!(jwt.expiry_secs, 3600);
+}
+
+#[test]
+fn test_mqtt_password_sources_exclusive() {
+    let keyfile = NamedTempFile::new().unwrap();
+    let yaml = format!(
+        r#"
+mqtt:
+  host: localhost
+  username: bridge
+  password: secret
+  jwt:
+    keyfile: {}
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#,
+        keyfile.path().display()
+    );
+    let result = load_yaml_config(&yaml);
+    assert!(matches!(result, Err(ConfigError::Validation(_))));
+}
+
+#[test]
+fn test_mqtt_v3_password_requires_username() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  password: secret
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let result = load_yaml_config(yaml);
+    assert!(matches!(result, Err(ConfigError::Validation(_))));
+}
+
+#[test]
+fn test_influxdb_tls() {
+    let cafile = NamedTempFile::new().unwrap();
+    let yaml = format!(
+        r#"
+mqtt:
+  host: localhost
+influxdb:
+  host: https://influxdb.internal
+  token: token
+  org: org
+  bucket: bucket
+  cafile: {}
+  insecure_skip_verify: true
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#,
+        cafile.path().display()
+    );
+    let config = load_yaml_config(&yaml).unwrap();
+    assert_eq!(config.influxdb.cafile.as_deref(), Some(cafile.path()));
+    assert!(config.influxdb.certfile.is_none());
+    assert!(config.influxdb.insecure_skip_verify);
+}
+
+#[test]
+fn test_influxdb_certfile_requires_keyfile() {
+    let certfile = NamedTempFile::new().unwrap();
+    let yaml = format!(
+        r#"
+mqtt:
+  host: localhost
+influxdb:
+  host: https://influxdb.internal
+  token: token
+  org: org
+  bucket: bucket
+  certfile: {}
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#,
+        certfile.path().display()
+    );
+    let result = load_yaml_config(&yaml);
+    assert!(matches!(result, Err(ConfigError::Validation(_))));
+}
+
+#[test]
+fn test_http_tls_file_missing() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+http:
+  destination: https://example.com/api
+  action: post
+  cafile: /nonexistent/ca.crt
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let result = load_yaml_config(yaml);
+    assert!(matches!(result, Err(ConfigError::FileNotFound(_))));
+}
+
+#[test]
+fn test_mqtt_tls_without_cafile() {
+    let yaml = r#"
+mqtt:
+  host: broker.example.com
+  port: 443
+  tls: true
+  tls_verify_name: broker.internal
+  alpn: [x-amzn-mqtt-ca]
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let config = load_yaml_config(yaml).unwrap();
+    let mqtt = &config.mqtt[0];
+    assert!(mqtt.uses_tls());
+    assert!(mqtt.cafile.is_none());
+    assert_eq!(mqtt.tls_verify_name.as_deref(), Some("broker.internal"));
+    assert_eq!(mqtt.alpn, vec!["x-amzn-mqtt-ca"]);
+}
+
+#[test]
+fn test_mqtt_alpn_requires_tls() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+  alpn: [mqtt]
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#;
+    let result = load_yaml_config(yaml);
+    assert!(matches!(result, Err(ConfigError::Validation(_))));
+}
+
+#[test]
+fn test_mqtt_keyfile_requires_certfile() {
+    let keyfile = NamedTempFile::new().unwrap();
+    let yaml = format!(
+        r#"
+mqtt:
+  host: localhost
+  tls: true
+  keyfile: {}
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: test
+    topic: test/#
+    fields:
+      value: "$.payload"
+"#,
+        keyfile.path().display()
+    );
+    let result = load_yaml_config(&yaml);
+    assert!(matches!(result, Err(ConfigError::Validation(_))));
+}
+
+#[test]
+fn test_point_when_condition() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: environment
+    topic: sensors/#
+    when: '$.payload.type == "env" && exists($.payload.temp)'
+    fields:
+      temp: "$.payload.temp"
+"#;
+    let config = load_yaml_config(yaml).unwrap();
+    assert_eq!(
+        config.points[0].when.as_deref(),
+        Some(r#"$.payload.type == "env" && exists($.payload.temp)"#)
+    );
+}
+
+#[test]
+fn test_point_invalid_field_expression() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: environment
+    topic: sensors/#
+    fields:
+      temp: "= ($.payload.temp * 2"
+"#;
+    let result = load_yaml_config(yaml);
+    assert!(matches!(
+        result,
+        Err(ConfigError::Validation(msg))
+            if msg == "Point 0 field 'temp' is invalid: Invalid expression at column 3: unclosed '('"
+    ));
+}
+
+#[test]
+fn test_point_invalid_when_condition() {
+    let yaml = r#"
+mqtt:
+  host: localhost
+influxdb:
+  host: localhost
+  token: token
+  org: org
+  bucket: bucket
+points:
+  - measurement: environment
+    topic: sensors/#
+    when: '$.payload.type == ('
+    fields:
+      temp: "$.payload.temp"
+"#;
+    let result = load_yaml_config(yaml);
+    assert!(matches!(
+        result,
+        Err(ConfigError::Validation(msg)) if msg.contains("at column 20: unexpected end of expression")
+    ));
+}
@@ -4,7 +4,7 @@
 //! ported from Python `test_integration.py`.
 
 use serde_json::json;
-use sinqtt::bridge::{FieldValue, MessageProcessor, Point};
+use sinqtt::bridge::{FieldValue, MessageProcessor, MessageScope, Point, PointPlan};
 use sinqtt::config::{FieldConfig, FieldSpec, PointConfig};
 use std::collections::HashMap;
 
@@ -18,8 +18,15 @@ fn make_point_config(
     PointConfig {
         measurement: measurement.to_string(),
         topic: topic.to_string(),
+        qos: 1,
+        brokers: Vec::new(),
         bucket: None,
         schedule: None,
+        when: None,
+        foreach: None,
+        timestamp: None,
+        timestamp_format: None,
+        timezone: None,
         fields: fields
             .into_iter()
             .map(|(k, v)| (k.to_string(), FieldSpec::Simple(v.to_string())))
@@ -41,8 +48,15 @@ fn make_typed_point_config(
     PointConfig {
         measurement: measurement.to_string(),
         topic: topic.to_string(),
+        qos: 1,
+        brokers: Vec::new(),
         bucket: None,
         schedule: None,
+        when: None,
+        foreach: None,
+        timestamp: None,
+        timestamp_format: None,
+        timezone: None,
         fields: fields
             .into_iter()
             .map(|(k, v, t)| {
@@ -77,8 +91,38 @@ fn process_message_to_point(
         return None;
     }
 
+    let plan = PointPlan::compile(point_config).ok()?;
+    build_point(&plan, &MessageScope::new(&parsed))
+}
+
+/// Simulate processing a message with `foreach`, building one point per element.
+fn process_message_to_points(
+    processor: &MessageProcessor,
+    point_config: &PointConfig,
+    topic: &str,
+    payload: &[u8],
+) -> Vec<Point> {
+    let Ok(parsed) = processor.parse_message(topic, payload, 0) else {
+        return Vec::new();
+    };
+    let Ok(plan) = PointPlan::compile(point_config) else {
+        return Vec::new();
+    };
+    let scope = MessageScope::new(&parsed);
+    let Some(items) = plan.items(&scope) else {
+        return build_point(&plan, &scope).into_iter().collect();
+    };
+
+    items
+        .iter()
+        .filter_map(|item| build_point(&plan, item))
+        .collect()
+}
+
+/// Build an InfluxDB point from a message scope.
+fn build_point(plan: &PointPlan, scope: &MessageScope<'_>) -> Option<Point> {
     // Get measurement name
-    let measurement = match processor.get_value(&point_config.measurement, &parsed)? {
+    let measurement = match plan.measurement().evaluate(scope)? {
         serde_json::Value::String(s) => s,
         v => v.to_string().trim_matches('"').to_string(),
     };
@@ -87,8 +131,8 @@ fn process_message_to_point(
     let mut point = Point::new(&measurement);
 
     // Add tags
-    for (tag_name, tag_spec) in &point_config.tags {
-        if let Some(value) = processor.get_value(tag_spec, &parsed) {
+    for (tag_name, tag_spec) in plan.tags() {
+        if let Some(value) = tag_spec.evaluate(scope) {
             let tag_value = match value {
                 serde_json::Value::String(s) => s,
                 v => v.to_string().trim_matches('"').to_string(),
@@ -101,12 +145,12 @@ fn process_message_to_point(
 
     // Add fields
     let mut fields_added = 0;
-    for (field_name, field_spec) in &point_config.fields {
-        if let Some(value) = processor.extract_field(field_spec, &parsed)
-            && let Some(field_value) = FieldValue::from_json(&value)
-        {
-            point.add_field(field_name, field_value);
-            fields_added += 1;
+    for field in plan.fields() {
+        for (name, value) in field.extract(scope) {
+            if let Some(field_value) = FieldValue::from_json(&value) {
+                point.add_field(name, field_value);
+                fields_added += 1;
+            }
         }
     }
 
@@ -114,6 +158,14 @@ fn process_message_to_point(
         return None;
     }
 
+    // Add timestamp if configured
+    if let Some(ts) = plan
+        .timestamp()
+        .and_then(|timestamp| timestamp.extract(scope))
+    {
+        point = point.timestamp(ts);
+    }
+
     Some(point)
 }
 
@@ -559,6 +611,79 @@ fn test_deeply_nested_json() {
     assert!(line.contains("deep_value=42"));
 }
 
+// ============================================================================
+// Foreach Expansion Tests
+// ============================================================================
+
+#[test]
+fn test_foreach_expands_array_into_points() {
+    let processor = MessageProcessor::new(None);
+    let mut config = make_point_config(
+        "reading",
+        "gateway/+/batch",
+        vec![("temperature", "$.item.temp")],
+        vec![("gateway", "$.topic[1]"), ("sensor", "$.item.id")],
+    );
+    config.foreach = Some("$.payload.readings".to_string());
+
+    let payload = br#"{"readings": [{"id": "a", "temp": 21.5}, {"id": "b", "temp": 22.5}]}"#;
+    let points = process_message_to_points(&processor, &config, "gateway/gw1/batch", payload);
+
+    assert_eq!(points.len(), 2);
+    let lines: Vec<String> = points.iter().map(Point::to_line_protocol).collect();
+    assert_eq!(lines[0], "reading,gateway=gw1,sensor=a temperature=21.5");
+    assert_eq!(lines[1], "reading,gateway=gw1,sensor=b temperature=22.5");
+}
+
+#[test]
+fn test_foreach_parent_message_addressable() {
+    let processor = MessageProcessor::new(None);
+    let mut config = make_point_config(
+        "reading",
+        "gateway/#",
+        vec![("value", "$.item"), ("battery", "$.payload.battery")],
+        vec![("device", "$.payload.device")],
+    );
+    config.foreach = Some("$.payload.values".to_string());
+
+    let payload = br#"{"device": "dev1", "battery": 87, "values": [1, 2, 3]}"#;
+    let points = process_message_to_points(&processor, &config, "gateway/x", payload);
+
+    assert_eq!(points.len(), 3);
+    for (i, point) in points.iter().enumerate() {
+        let line = point.to_line_protocol();
+        assert!(line.contains("device=dev1"));
+        assert!(line.contains("battery=87i"));
+        assert!(line.contains(&format!("value={}i", i + 1)));
+    }
+}
+
+#[test]
+fn test_foreach_per_element_timestamp() {
+    let processor = MessageProcessor::new(None);
+    let mut config = make_point_config("reading", "gateway/#", vec![("value", "$.item.v")], vec![]);
+    config.foreach = Some("$.payload.readings".to_string());
+    config.timestamp = Some("$.item.ts".to_string());
+    config.timestamp_format = Some("s".to_string());
+
+    let payload = br#"{"readings": [{"ts": 1700000000, "v": 1}, {"ts": 1700000060, "v": 2}]}"#;
+    let points = process_message_to_points(&processor, &config, "gateway/x", payload);
+
+    assert_eq!(points.len(), 2);
+    assert_eq!(points[0].timestamp, Some(1_700_000_000_000_000_000));
+    assert_eq!(points[1].timestamp, Some(1_700_000_060_000_000_000));
+}
+
+#[test]
+fn test_foreach_missing_array_produces_no_points() {
+    let processor = MessageProcessor::new(None);
+    let mut config = make_point_config("reading", "gateway/#", vec![("value", "$.item")], vec![]);
+    config.foreach = Some("$.payload.readings".to_string());
+
+    let points = process_message_to_points(&processor, &config, "gateway/x", br#"{"other": 1}"#);
+    assert!(points.is_empty());
+}
+
 // ============================================================================
 // Special Characters Tests
 // ============================================================================
@@ -654,3 +779,46 @@ fn test_string_field_quoting() {
     // String values should be quoted in line protocol
     assert!(line.contains("status="));
 }
+
+// ============================================================================
+// Flatten Field Tests
+// ============================================================================
+
+#[test]
+fn test_flatten_payload_into_fields() {
+    let processor = MessageProcessor::new(None);
+    let config = make_point_config(
+        "sensor",
+        "sensors/+/data",
+        vec![("*", "$.payload")],
+        vec![("device", "$.topic[1]")],
+    );
+
+    let payload = br#"{"env": {"temp": 21.5, "hum": 40}, "battery": 87, "status": null}"#;
+    let point = process_message_to_point(&processor, &config, "sensors/dev1/data", payload)
+        .expect("Should produce point");
+
+    assert_eq!(
+        point.to_line_protocol(),
+        "sensor,device=dev1 battery=87i,env.hum=40i,env.temp=21.5"
+    );
+}
+
+#[test]
+fn test_flatten_combined_with_regular_fields() {
+    let processor = MessageProcessor::new(None);
+    let config = make_point_config(
+        "sensor",
+        "sensors/#",
+        vec![("env_*", "$.payload.env"), ("rssi", "$.payload.rssi")],
+        vec![],
+    );
+
+    let payload = br#"{"env": {"temp": 21.5}, "rssi": -70}"#;
+    let point = process_message_to_point(&processor, &config, "sensors/dev1", payload)
+        .expect("Should produce point");
+
+    let line = point.to_line_protocol();
+    assert!(line.contains("env_temp=21.5"));
+    assert!(line.contains("rssi=-70i"));
+}
@@ -3,8 +3,11 @@
 //! Ported from Python `test_mqtt2influxdb.py`.
 
 use serde_json::{Value, json};
-use sinqtt::bridge::MessageProcessor;
-use sinqtt::config::{Base64DecodeConfig, FieldConfig, FieldSpec};
+use sinqtt::bridge::{
+    CompiledExpression, FieldPlan, MessageProcessor, MessageScope, MqttMessage, ParsedMessage,
+    PointPlan, TimestampPlan, ValueSpec,
+};
+use sinqtt::config::{Base64DecodeConfig, FieldConfig, FieldSpec, FlattenConfig, PointConfig};
 
 // ============================================================================
 // Message Parsing Tests
@@ -521,6 +524,15 @@ fn test_topic_multiple_single_wildcards() {
     assert!(!processor.topic_matches("node/+/sensor/+/value", "node/abc/sensor/xyz/other"));
 }
 
+#[test]
+fn test_topic_shared_subscription() {
+    let processor = MessageProcessor::new(None);
+
+    assert!(processor.topic_matches("$share/sinqtt/test/+/temp", "test/sensor1/temp"));
+    assert!(processor.topic_matches("$share/sinqtt/test/#", "test/a/b"));
+    assert!(!processor.topic_matches("$share/sinqtt/test/#", "other/a"));
+}
+
 #[test]
 fn test_topic_wildcard_at_start() {
     let processor = MessageProcessor::new(None);
@@ -673,6 +685,22 @@ fn test_extract_field_typed_int() {
     assert_eq!(result, json!(42));
 }
 
+#[test]
+fn test_extract_field_typed_int_expression_keeps_digits() {
+    let processor = MessageProcessor::new(None);
+    let msg = processor
+        .parse_message("test", br#"{"counter": 9007199254740993}"#, 0)
+        .unwrap();
+
+    let spec = FieldSpec::Typed(FieldConfig {
+        value: "= $.payload.counter + 2".to_string(),
+        field_type: Some("int".to_string()),
+    });
+    let result = processor.extract_field(&spec, &msg).unwrap();
+
+    assert_eq!(result, json!(9_007_199_254_740_995_i64));
+}
+
 #[test]
 fn test_extract_field_typed_booltoint() {
     let processor = MessageProcessor::new(None);
@@ -776,6 +804,431 @@ fn test_expression_multiple_variables() {
     assert_eq!(result, json!(13.0));
 }
 
+#[test]
+fn test_expression_ternary_string_result() {
+    let processor = MessageProcessor::new(None);
+    let good = processor
+        .parse_message("test", br#"{"rssi": -60}"#, 0)
+        .unwrap();
+    let poor = processor
+        .parse_message("test", br#"{"rssi": -85}"#, 0)
+        .unwrap();
+
+    let spec = r#"= $.payload.rssi > -70 ? "good" : "poor""#;
+    assert_eq!(processor.get_value(spec, &good), Some(json!("good")));
+    assert_eq!(processor.get_value(spec, &poor), Some(json!("poor")));
+}
+
+#[test]
+fn test_expression_string_and_bool_inputs() {
+    let processor = MessageProcessor::new(None);
+    let msg = processor
+        .parse_message("test", br#"{"site": "lab", "room": "a1", "on": true}"#, 0)
+        .unwrap();
+
+    assert_eq!(
+        processor.get_value(r#"= $.payload.site + "-" + $.payload.room"#, &msg),
+        Some(json!("lab-a1"))
+    );
+    assert_eq!(
+        processor.get_value("= if($.payload.on, 1, 0)", &msg),
+        Some(json!(1.0))
+    );
+    assert_eq!(
+        processor.get_value(r#"= $.payload.site == "lab""#, &msg),
+        Some(json!(true))
+    );
+}
+
+#[test]
+fn test_expression_integer_division_stays_float() {
+    let processor = MessageProcessor::new(None);
+    let msg = processor.parse_message("test", b"5", 0).unwrap();
+
+    assert_eq!(
+        processor.get_value("= $.payload / 2", &msg),
+        Some(json!(2.5))
+    );
+    assert_eq!(processor.get_value("= 7 / 2", &msg), Some(json!(3.5)));
+}
+
+#[test]
+fn test_expression_bracket_and_filter_paths() {
+    let processor = MessageProcessor::new(None);
+    let msg = processor
+        .parse_message(
+            "test",
+            br#"{"pm2.5": 12, "sensors": [{"id": "a", "v": 1}, {"id": "b", "v": 5}]}"#,
+            0,
+        )
+        .unwrap();
+
+    assert_eq!(
+        processor.get_value("= $.payload['pm2.5'] * 2", &msg),
+        Some(json!(24.0))
+    );
+    assert_eq!(
+        processor.get_value("= $.payload.sensors[?@.id == 'b'].v + 1", &msg),
+        Some(json!(6.0))
+    );
+}
+
+#[test]
+fn test_expression_power_of_nested_parentheses() {
+    let processor = MessageProcessor::new(None);
+    let msg = processor
+        .parse_message("test", br#"{"a": 2, "b": 1, "c": 2}"#, 0)
+        .unwrap();
+
+    assert_eq!(
+        processor.get_value("= ($.payload.a * ($.payload.b + $.payload.c)) ^ 2", &msg),
+        Some(json!(36.0))
+    );
+}
+
+#[test]
+fn test_expression_similar_paths_are_distinct() {
+    let processor = MessageProcessor::new(None);
+    let msg = processor
+        .parse_message("test", br#"{"a_b": 1, "a": {"b": 2}}"#, 0)
+        .unwrap();
+
+    assert_eq!(
+        processor.get_value("= $.payload.a_b - $.payload.a.b", &msg),
+        Some(json!(-1.0))
+    );
+}
+
+#[test]
+fn test_expression_builtin_functions() {
+    let processor = MessageProcessor::new(None);
+    let msg = processor
+        .parse_message(
+            "test",
+            br#"{"temp": 21.456, "rh": 50, "status": 5, "raw": "0x1F", "id": "Dev-01"}"#,
+            0,
+        )
+        .unwrap();
+
+    assert_eq!(
+        processor.get_value("= round($.payload.temp, 1)", &msg),
+        Some(json!(21.5))
+    );
+    assert_eq!(
+        processor.get_value(r#"= round(convert($.payload.temp, "c", "f"), 1)"#, &msg),
+        Some(json!(70.6))
+    );
+    assert_eq!(
+        processor.get_value("= round(dew_point($.payload.temp, $.payload.rh), 1)", &msg),
+        Some(json!(10.6))
+    );
+    assert_eq!(
+        processor.get_value("= bit($.payload.status, 2)", &msg),
+        Some(json!(true))
+    );
+    assert_eq!(
+        processor.get_value("= hex_to_int($.payload.raw)", &msg),
+        Some(json!(31.0))
+    );
+    assert_eq!(
+        processor.get_value("= lower(substr($.payload.id, 0, 3))", &msg),
+        Some(json!("dev"))
+    );
+}
+
+#[test]
+fn test_expression_function_error_returns_none() {
+    let processor = MessageProcessor::new(None);
+    let msg = processor.parse_message("test", b"-4", 0).unwrap();
+
+    assert_eq!(processor.get_value("= sqrt($.payload)", &msg), None);
+    assert_eq!(
+        processor.get_value("= sqrt(abs($.payload))", &msg),
+        Some(json!(2.0))
+    );
+}
+
+// ============================================================================
+// Condition Tests
+// ============================================================================
+
+/// Check a `when` condition against a message scope.
+fn condition_matches(condition: &str, scope: &MessageScope<'_>) -> bool {
+    CompiledExpression::compile(condition)
+        .unwrap()
+        .matches(scope)
+}
+
+#[test]
+fn test_condition_string_equality() {
+    let processor = MessageProcessor::new(None);
+    let env = processor
+        .parse_message("sensors/a", br#"{"type": "env", "temp": 21.5}"#, 0)
+        .unwrap();
+    let power = processor
+        .parse_message("sensors/a", br#"{"type": "power", "watts": 150}"#, 0)
+        .unwrap();
+
+    let condition = r#"$.payload.type == "env""#;
+    assert!(condition_matches(condition, &MessageScope::new(&env)));
+    assert!(!condition_matches(condition, &MessageScope::new(&power)));
+}
+
+#[test]
+fn test_condition_comparison_and_exists() {
+    let processor = MessageProcessor::new(None);
+    let msg = processor
+        .parse_message("sensors/a", br#"{"temp": 21.5, "battery": 15}"#, 0)
+        .unwrap();
+    let scope = MessageScope::new(&msg);
+
+    assert!(condition_matches(
+        "$.payload.temp >= 20 && $.payload.battery < 20",
+        &scope
+    ));
+    assert!(condition_matches("exists($.payload.battery)", &scope));
+    assert!(!condition_matches("exists($.payload.voltage)", &scope));
+    assert!(condition_matches("$.qos == 0", &scope));
+}
+
+#[test]
+fn test_condition_missing_path_does_not_match() {
+    let processor = MessageProcessor::new(None);
+    let msg = processor.parse_message("test", br#"{"a": 1}"#, 0).unwrap();
+    let scope = MessageScope::new(&msg);
+
+    assert!(!condition_matches(r#"$.payload.type == "env""#, &scope));
+    assert!(!condition_matches(r#"$.payload.type != "env""#, &scope));
+}
+
+#[test]
+fn test_condition_on_foreach_item() {
+    let processor = MessageProcessor::new(None);
+    let msg = processor
+        .parse_message("test", br#"{"readings": [{"ok": true}, {"ok": false}]}"#, 0)
+        .unwrap();
+    let scope = MessageScope::new(&msg);
+
+    let items = foreach_plan("$.payload.readings").items(&scope).unwrap();
+    assert!(condition_matches("$.item.ok", &items[0]));
+    assert!(!condition_matches("$.item.ok", &items[1]));
+}
+
+// ============================================================================
+// Foreach Expansion Tests
+// ============================================================================
+
+/// Compile a point that expands messages with the given `foreach` path.
+fn foreach_plan(foreach: &str) -> PointPlan {
+    let config: PointConfig = serde_yaml::from_str(&format!(
+        "measurement: test\ntopic: test\nforeach: \"{foreach}\"\nfields:\n  value: $.item\n"
+    ))
+    .unwrap();
+    PointPlan::compile(&config).unwrap()
+}
+
+#[test]
+fn test_expand_foreach_array() {
+    let processor = MessageProcessor::new(None);
+    let msg = processor
+        .parse_message("test", br#"{"readings": [{"t": 1}, {"t": 2}]}"#, 0)
+        .unwrap();
+    let scope = MessageScope::new(&msg);
+
+    let items = foreach_plan("$.payload.readings").items(&scope).unwrap();
+    let value = |spec, item| ValueSpec::compile(spec).unwrap().evaluate(item);
+
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[0].item(), Some(&json!({"t": 1})));
+    assert_eq!(value("$.item.t", &items[1]), Some(json!(2)));
+    assert_eq!(value("$.index", &items[1]), Some(json!(1)));
+}
+
+#[test]
+fn test_expand_foreach_wildcard() {
+    let processor = MessageProcessor::new(None);
+    let msg = processor
+        .parse_message("test", br#"{"a": {"v": 1}, "b": {"v": 2}}"#, 0)
+        .unwrap();
+    let scope = MessageScope::new(&msg);
+
+    let items = foreach_plan("$.payload.*").items(&scope).unwrap();
+    assert_eq!(items.len(), 2);
+}
+
+#[test]
+fn test_expand_foreach_missing_path() {
+    let processor = MessageProcessor::new(None);
+    let msg = processor.parse_message("test", br#"{"a": 1}"#, 0).unwrap();
+    let scope = MessageScope::new(&msg);
+
+    let items = foreach_plan("$.payload.missing").items(&scope).unwrap();
+    assert!(items.is_empty());
+}
+
+// ============================================================================
+// Flatten Field Tests
+// ============================================================================
+
+/// Extract the named fields of a field specification from a message.
+fn extract_fields(name: &str, spec: &FieldSpec, msg: &ParsedMessage) -> Vec<(String, Value)> {
+    FieldPlan::compile(name, spec)
+        .unwrap()
+        .extract(&MessageScope::new(msg))
+}
+
+#[test]
+fn test_extract_fields_flatten_object() {
+    let processor = MessageProcessor::new(None);
+    let msg = processor
+        .parse_message(
+            "test",
+            br#"{"env": {"temp": 21.5, "hum": 40}, "battery": 87}"#,
+            0,
+        )
+        .unwrap();
+
+    let mut fields = extract_fields("*", &FieldSpec::Simple("$.payload".to_string()), &msg);
+    fields.sort_by(|a, b| a.0.cmp(&b.0));
+
+    assert_eq!(
+        fields,
+        vec![
+            ("battery".to_string(), json!(87)),
+            ("env.hum".to_string(), json!(40)),
+            ("env.temp".to_string(), json!(21.5)),
+        ]
+    );
+}
+
+#[test]
+fn test_extract_fields_flatten_prefix_and_type() {
+    let processor = MessageProcessor::new(None);
+    let msg = processor
+        .parse_message("test", br#"{"env": {"temp": "21.5", "hum": "40"}}"#, 0)
+        .unwrap();
+
+    let spec = FieldSpec::Flatten(FlattenConfig {
+        field_type: Some("float".to_string()),
+        separator: "_".to_string(),
+        include: vec!["env_t*".to_string()],
+        ..FlattenConfig::new("$.payload")
+    });
+    let fields = extract_fields("sensor_*", &spec, &msg);
+
+    assert_eq!(fields, vec![("sensor_env_temp".to_string(), json!(21.5))]);
+}
+
+#[test]
+fn test_extract_fields_single_field() {
+    let processor = MessageProcessor::new(None);
+    let msg = processor
+        .parse_message("test", br#"{"temp": 21.5}"#, 0)
+        .unwrap();
+
+    let spec = FieldSpec::Simple("$.payload.temp".to_string());
+    assert_eq!(
+        extract_fields("temperature", &spec, &msg),
+        vec![("temperature".to_string(), json!(21.5))]
+    );
+
+    let spec = FieldSpec::Simple("$.payload.missing".to_string());
+    assert!(extract_fields("missing", &spec, &msg).is_empty());
+}
+
+// ============================================================================
+// Timestamp Extraction Tests
+// ============================================================================
+
+/// Extract a timestamp in nanoseconds from a message.
+fn extract_timestamp(
+    spec: &str,
+    format: Option<&str>,
+    timezone: Option<&str>,
+    msg: &ParsedMessage,
+) -> Option<i64> {
+    TimestampPlan::compile(spec, format, timezone)
+        .unwrap()
+        .extract(&MessageScope::new(msg))
+}
+
+#[test]
+fn test_extract_timestamp_unix_seconds() {
+    let processor = MessageProcessor::new(None);
+    let msg = processor
+        .parse_message("test", br#"{"ts": 1700000000, "value": 1}"#, 0)
+        .unwrap();
+
+    let ts = extract_timestamp("$.payload.ts", Some("s"), None, &msg);
+    assert_eq!(ts, Some(1_700_000_000_000_000_000));
+}
+
+#[test]
+fn test_extract_timestamp_auto_milliseconds() {
+    let processor = MessageProcessor::new(None);
+    let msg = processor
+        .parse_message("test", br#"{"ts": 1700000000123}"#, 0)
+        .unwrap();
+
+    let ts = extract_timestamp("$.payload.ts", None, None, &msg);
+    assert_eq!(ts, Some(1_700_000_000_123_000_000));
+}
+
+#[test]
+fn test_extract_timestamp_rfc3339() {
+    let processor = MessageProcessor::new(None);
+    let msg = processor
+        .parse_message("test", br#"{"time": "2023-11-14T22:13:20.5Z"}"#, 0)
+        .unwrap();
+
+    let ts = extract_timestamp("$.payload.time", Some("rfc3339"), None, &msg);
+    assert_eq!(ts, Some(1_700_000_000_500_000_000));
+}
+
+#[test]
+fn test_extract_timestamp_pattern_with_timezone() {
+    let processor = MessageProcessor::new(None);
+    let msg = processor
+        .parse_message("test", br#"{"time": "2023-11-15 00:13:20"}"#, 0)
+        .unwrap();
+
+    let ts = extract_timestamp(
+        "$.payload.time",
+        Some("%Y-%m-%d %H:%M:%S"),
+        Some("+02:00"),
+        &msg,
+    );
+    assert_eq!(ts, Some(1_700_000_000_000_000_000));
+}
+
+#[test]
+fn test_extract_timestamp_expression() {
+    let processor = MessageProcessor::new(None);
+    let msg = processor
+        .parse_message("test", br#"{"base": 1700000000, "offset": 60}"#, 0)
+        .unwrap();
+
+    let ts = extract_timestamp("= $.payload.base + $.payload.offset", Some("s"), None, &msg);
+    assert_eq!(ts, Some(1_700_000_060_000_000_000));
+}
+
+#[test]
+fn test_extract_timestamp_missing_or_invalid() {
+    let processor = MessageProcessor::new(None);
+    let msg = processor
+        .parse_message("test", br#"{"time": "not a time"}"#, 0)
+        .unwrap();
+
+    assert_eq!(
+        extract_timestamp("$.payload.missing", None, None, &msg),
+        None
+    );
+    assert_eq!(
+        extract_timestamp("$.payload.time", Some("rfc3339"), None, &msg),
+        None
+    );
+}
+
 // ============================================================================
 // Build Message Object Tests
 // ============================================================================
@@ -815,6 +1268,60 @@ fn test_build_message_object_with_base64decoded() {
     assert_eq!(obj["base64decoded"]["decoded"]["hex"], json!("74657374"));
 }
 
+#[test]
+fn test_build_message_object_with_properties() {
+    let processor = MessageProcessor::new(None);
+    let mqtt_msg = MqttMessage {
+        broker: "default".to_string(),
+        topic: "test/topic".to_string(),
+        payload: b"42".to_vec(),
+        qos: 1,
+        properties: Some(json!({
+            "content_type": "application/json",
+            "user_properties": {"device": "sensor-1"},
+        })),
+        ack: None,
+    };
+    let msg = processor.parse_mqtt_message(&mqtt_msg).unwrap();
+
+    assert_eq!(
+        processor.get_value("$.properties.user_properties.device", &msg),
+        Some(json!("sensor-1"))
+    );
+    assert_eq!(
+        processor.get_value("$.properties.content_type", &msg),
+        Some(json!("application/json"))
+    );
+
+    // Messages without properties do not expose the key
+    let msg = processor.parse_message("test/topic", b"42", 1).unwrap();
+    assert!(
+        processor
+            .build_message_object(&msg)
+            .get("properties")
+            .is_none()
+    );
+}
+
+#[test]
+fn test_build_message_object_with_broker() {
+    let processor = MessageProcessor::new(None);
+    let mqtt_msg = MqttMessage {
+        broker: "eu-west".to_string(),
+        topic: "test/topic".to_string(),
+        payload: b"42".to_vec(),
+        qos: 1,
+        properties: None,
+        ack: None,
+    };
+    let msg = processor.parse_mqtt_message(&mqtt_msg).unwrap();
+
+    assert_eq!(
+        processor.get_value("$.broker", &msg),
+        Some(json!("eu-west"))
+    );
+}
+
 // ============================================================================
 // Cron Schedule Tests
 // ============================================================================